@@ -0,0 +1,1021 @@
+use anyhow::{bail, Context, Result};
+use hf_hub::{
+    api::{
+        sync::{Api, ApiBuilder, ApiError, ApiRepo},
+        Progress,
+    },
+    Cache, CacheRepo,
+};
+use ort::{
+    ExecutionProvider, ExecutionProviderDispatch, GraphOptimizationLevel, Session, SessionBuilder,
+    ValueType,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::available_parallelism,
+    time::Duration,
+};
+use tokenizers::{
+    AddedToken, PaddingParams, PaddingStrategy, TruncationDirection, TruncationParams,
+};
+
+use crate::{error::invalid_input, EmbeddingModel, FastEmbedError};
+
+pub(crate) const DEFAULT_BATCH_SIZE: usize = 256;
+pub(crate) const DEFAULT_MAX_LENGTH: usize = 512;
+/// Cache directory used when the platform has none
+pub(crate) const DEFAULT_CACHE_DIR: &str = ".fastembed_cache";
+/// Pad token used when the tokenizer configuration does not define one
+const DEFAULT_PAD_TOKEN: &str = "[PAD]";
+/// Ceiling of the model_max_length read from the tokenizer configs, which some set to 1e30 for "unlimited"
+pub(crate) const MAX_MODEL_MAX_LENGTH: usize = 1_000_000;
+
+/// Default directory of the model cache, `fastembed` in the cache directory of the platform
+///
+/// Such as `$XDG_CACHE_HOME/fastembed` or `~/.cache/fastembed` on Linux, `~/Library/Caches/fastembed` on macOS
+/// and `{FOLDERID_LocalAppData}\\fastembed` on Windows. Falls back to `.fastembed_cache` in the working directory
+/// on the platforms without a cache directory
+pub fn default_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .map(|dir| dir.join("fastembed"))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR))
+}
+
+/// Type alias for the embedding vector
+pub type Embedding = Vec<f32>;
+
+/// Embeddings along with the model that produced them, to store or send them with the `serde` feature
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Embeddings {
+    pub model: EmbeddingModel,
+    pub embeddings: Vec<Embedding>,
+}
+
+/// Sparse embedding vector, holding the indices and the values of its non-zero entries
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SparseEmbedding {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+/// Padding strategy applied to the sequences of a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Padding {
+    /// Pad the sequences to the longest one in the batch - Default
+    #[default]
+    BatchLongest,
+    /// Pad every sequence to `max_length`, for accelerators that require fixed input shapes
+    Fixed,
+}
+
+/// Progress of a model file download, as reported to the progress callback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub filename: String,
+    /// Number of bytes downloaded so far
+    pub downloaded: usize,
+    /// Size of the file in bytes
+    pub total: usize,
+}
+
+/// Callback invoked as the model files are downloaded
+pub type ProgressCallback = Arc<dyn Fn(DownloadProgress) + Send + Sync>;
+
+/// Callback invoked with a warning when an execution provider is replaced by the CPU
+pub type FallbackCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Interface shared by the embedding models, to write code generic over the model type
+///
+/// This lets the models be stored as `Box<dyn Embed<Output = Embedding>>` and swapped at runtime.
+/// The inherent `embed` methods of the models accept any string type and remain the most convenient
+/// to call when the type is known
+pub trait Embed {
+    /// Type of the embeddings produced by the model
+    type Output;
+
+    /// Generate the embeddings of the texts, in batches of `batch_size` or of the model's default batch size
+    fn embed(
+        &self,
+        texts: Vec<&str>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Self::Output>, FastEmbedError>;
+}
+
+/// Callback invoked with the index of an input and its length in tokens, when it is truncated to `max_length`
+pub type TruncationCallback = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Filter called with the id of every token of the texts, returning `true` for the tokens to keep
+pub type TokenFilter = Arc<dyn Fn(u32) -> bool + Send + Sync>;
+
+/// What to do with the inputs longer than `max_length`, which are truncated
+#[derive(Clone, Default)]
+pub enum OnTruncation {
+    /// Truncate the inputs without notice - Default
+    #[default]
+    Silent,
+    /// Truncate the inputs and call the callback for each of them
+    Warn(TruncationCallback),
+    /// Return an error listing the inputs that would be truncated, instead of embedding them
+    Error,
+}
+
+impl std::fmt::Debug for OnTruncation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Silent => write!(f, "Silent"),
+            Self::Warn(_) => write!(f, "Warn(<callback>)"),
+            Self::Error => write!(f, "Error"),
+        }
+    }
+}
+
+/// What to do with the empty or whitespace-only texts, which only hold the special tokens once encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnEmptyText {
+    /// Embed them like any other text, to keep the behavior of the models - Default
+    #[default]
+    Embed,
+    /// Skip their inference and return an empty embedding in their place
+    Skip,
+    /// Return an error with the index of the first empty text, instead of embedding them
+    Error,
+}
+
+// Tokenizer files for "bring your own" embedding models
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenizerFiles {
+    pub tokenizer_file: Vec<u8>,
+    pub config_file: Vec<u8>,
+    pub special_tokens_map_file: Vec<u8>,
+    pub tokenizer_config_file: Vec<u8>,
+}
+
+/// Ids of the special tokens of a tokenizer, `None` for the tokens it does not have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpecialTokenIds {
+    pub pad: Option<u32>,
+    pub cls: Option<u32>,
+    pub sep: Option<u32>,
+    pub unk: Option<u32>,
+    pub mask: Option<u32>,
+}
+
+impl SpecialTokenIds {
+    /// Look up the special tokens of the BERT (`[CLS]`) and RoBERTa (`<s>`) vocabularies
+    ///
+    /// The pad id is the one the tokenizer pads with, as configured by load_tokenizer
+    pub(crate) fn from_tokenizer(tokenizer: &Tokenizer) -> Self {
+        let token_id =
+            |tokens: &[&str]| tokens.iter().find_map(|token| tokenizer.token_to_id(token));
+        Self {
+            pad: tokenizer
+                .get_padding()
+                .map(|padding| padding.pad_id)
+                .or_else(|| token_id(&["[PAD]", "<pad>"])),
+            cls: token_id(&["[CLS]", "<s>"]),
+            sep: token_id(&["[SEP]", "</s>"]),
+            unk: token_id(&["[UNK]", "<unk>"]),
+            mask: token_id(&["[MASK]", "<mask>"]),
+        }
+    }
+}
+
+/// Name, shape and type of an input or output of a model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorSignature {
+    pub name: String,
+    /// Dimensions of the tensor, -1 for the dynamic ones such as the batch size, `None` for the sequences and maps
+    pub shape: Option<Vec<i64>>,
+    pub value_type: ValueType,
+}
+
+/// Inputs and outputs of the ONNX graph of a model, as loaded by ONNX Runtime
+///
+/// Lets the exports of user-defined models be checked against the inputs fed by the crate,
+/// such as whether they expect `token_type_ids`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelSignature {
+    pub inputs: Vec<TensorSignature>,
+    pub outputs: Vec<TensorSignature>,
+}
+
+impl ModelSignature {
+    pub(crate) fn from_session(session: &Session) -> Self {
+        let signature = |name: &str, value_type: &ValueType| TensorSignature {
+            name: name.to_string(),
+            shape: value_type.tensor_dimensions().cloned(),
+            value_type: value_type.clone(),
+        };
+        Self {
+            inputs: session
+                .inputs
+                .iter()
+                .map(|input| signature(&input.name, &input.input_type))
+                .collect(),
+            outputs: session
+                .outputs
+                .iter()
+                .map(|output| signature(&output.name, &output.output_type))
+                .collect(),
+        }
+    }
+}
+
+/// Name of the provider ONNX Runtime falls back to when no other one could be registered
+pub(crate) const CPU_EXECUTION_PROVIDER: &str = "CPUExecutionProvider";
+
+/// Level of the graph optimizations applied by ONNX Runtime when loading a model
+///
+/// Lower levels load faster and avoid the issues of some custom ops with the fused nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationLevel {
+    /// No graph optimization
+    Disable,
+    /// Semantics-preserving rewrites, such as constant folding and the removal of redundant nodes
+    Level1,
+    /// Level1 and the fusions of nodes, such as the attention and GELU fusions
+    Level2,
+    /// All the optimizations, including the layout ones that depend on the hardware - Default
+    #[default]
+    Level3,
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+/// Ordered list of execution providers, from the most to the least preferred one
+///
+/// ONNX Runtime assigns each node of the graph to the first provider of the chain that supports it,
+/// the next providers run the nodes the previous ones can not. The providers that fail to register,
+/// such as CUDA without a GPU, are skipped and reported through the `fallback_callback`, and the CPU
+/// always comes last. The providers that were registered are listed by `TextEmbedding::active_execution_providers`
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionProviderChain {
+    providers: Vec<ExecutionProviderDispatch>,
+}
+
+impl ExecutionProviderChain {
+    /// Create an empty chain, which runs the models on the CPU
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a provider, which runs the nodes the previous providers of the chain do not support
+    pub fn then(mut self, provider: impl Into<ExecutionProviderDispatch>) -> Self {
+        self.providers.push(provider.into());
+        self
+    }
+
+    /// The providers of the chain, in order of priority
+    pub fn providers(&self) -> &[ExecutionProviderDispatch] {
+        &self.providers
+    }
+}
+
+impl From<ExecutionProviderChain> for Vec<ExecutionProviderDispatch> {
+    fn from(chain: ExecutionProviderChain) -> Self {
+        chain.providers
+    }
+}
+
+/// Cap the memory arena of the CUDA execution providers to `gpu_mem_limit` bytes, if set
+pub(crate) fn with_gpu_mem_limit(
+    execution_providers: Vec<ExecutionProviderDispatch>,
+    gpu_mem_limit: Option<usize>,
+) -> Vec<ExecutionProviderDispatch> {
+    let Some(gpu_mem_limit) = gpu_mem_limit else {
+        return execution_providers;
+    };
+    execution_providers
+        .into_iter()
+        .map(|provider| match provider {
+            ExecutionProviderDispatch::CUDA(cuda) => {
+                ExecutionProviderDispatch::CUDA(cuda.with_memory_limit(gpu_mem_limit))
+            }
+            provider => provider,
+        })
+        .collect()
+}
+
+/// Create the session builder shared by the models' constructors
+///
+/// Also returns the names of the execution providers that were registered, in order of priority
+pub(crate) fn session_builder(
+    execution_providers: Vec<ExecutionProviderDispatch>,
+    threads: Option<usize>,
+    inter_threads: Option<usize>,
+    optimization_level: OptimizationLevel,
+    fallback_callback: Option<&FallbackCallback>,
+) -> Result<(SessionBuilder, Vec<String>)> {
+    let threads = match threads {
+        Some(0) => bail!(invalid_input("The number of threads must be at least 1")),
+        Some(threads) => threads,
+        None => available_parallelism()?.get(),
+    };
+
+    // The providers are registered one by one instead of through with_execution_providers,
+    // which silently skips the ones that fail, to know which ones are actually used
+    let session_builder = Session::builder()?;
+    let mut active_execution_providers = Vec::new();
+    for provider in &execution_providers {
+        match provider.register(&session_builder) {
+            Ok(()) => active_execution_providers.push(provider.as_str().to_string()),
+            Err(error) => {
+                if let Some(callback) = fallback_callback {
+                    callback(&format!(
+                        "{} could not be registered, falling back to the CPU: {}",
+                        provider.as_str(),
+                        error
+                    ));
+                }
+            }
+        }
+    }
+    // The CPU is always available to run the nodes the other providers do not support
+    if !active_execution_providers
+        .iter()
+        .any(|provider| provider == CPU_EXECUTION_PROVIDER)
+    {
+        active_execution_providers.push(CPU_EXECUTION_PROVIDER.to_string());
+    }
+
+    let mut session_builder = session_builder
+        .with_optimization_level(optimization_level.into())?
+        .with_intra_threads(threads as i16)?;
+
+    // The inter-op thread pool is only used in the parallel execution mode
+    match inter_threads {
+        Some(0) => bail!(invalid_input(
+            "The number of inter-op threads must be at least 1"
+        )),
+        Some(inter_threads) => {
+            session_builder = session_builder
+                .with_parallel_execution(true)?
+                .with_inter_threads(inter_threads as i16)?;
+        }
+        None => {}
+    }
+    Ok((session_builder, active_execution_providers))
+}
+
+/// Copy the tokenizer with its truncation, and its padding when it is fixed, set to `max_length`
+pub(crate) fn with_max_length(tokenizer: &Tokenizer, max_length: usize) -> Result<Tokenizer> {
+    let mut tokenizer = tokenizer.clone();
+    let truncation = tokenizer.get_truncation().cloned().unwrap_or_default();
+    tokenizer
+        .with_truncation(Some(TruncationParams {
+            max_length,
+            ..truncation
+        }))
+        .map_err(FastEmbedError::InvalidInput)?;
+    if let Some(padding) = tokenizer.get_padding_mut() {
+        if let PaddingStrategy::Fixed(_) = padding.strategy {
+            padding.strategy = PaddingStrategy::Fixed(max_length);
+        }
+    }
+    Ok(tokenizer)
+}
+
+/// Enable the profiler of ONNX Runtime if a profiling file is set, which requires the `profiling` feature
+pub(crate) fn with_profiling(
+    session_builder: SessionBuilder,
+    profiling_file: Option<&Path>,
+) -> Result<SessionBuilder> {
+    let Some(profiling_file) = profiling_file else {
+        return Ok(session_builder);
+    };
+    #[cfg(feature = "profiling")]
+    {
+        // ONNX Runtime appends a timestamp to the file name, the trace is moved by end_profiling
+        Ok(session_builder.with_profiling(profiling_file.to_string_lossy())?)
+    }
+    #[cfg(not(feature = "profiling"))]
+    {
+        bail!(invalid_input(format!(
+            "Profiling to {} requires the `profiling` feature",
+            profiling_file.display()
+        )))
+    }
+}
+
+/// Decompress the bytes of an onnx file compressed with gzip or zstd, detected from their magic bytes
+///
+/// Uncompressed bytes are returned as is. Decompressing requires the `compression` feature
+pub(crate) fn decompress_model(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let format = if bytes.starts_with(&[0x1f, 0x8b]) {
+        "gzip"
+    } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        "zstd"
+    } else {
+        return Ok(Cow::Borrowed(bytes));
+    };
+    #[cfg(feature = "compression")]
+    {
+        let mut decompressed = Vec::new();
+        let result = if format == "gzip" {
+            flate2::read::GzDecoder::new(bytes).read_to_end(&mut decompressed)
+        } else {
+            ruzstd::decoding::StreamingDecoder::new(bytes)
+                .with_context(|| format!("Could not read the {} compressed onnx file", format))?
+                .read_to_end(&mut decompressed)
+        };
+        result
+            .with_context(|| format!("Could not decompress the {} compressed onnx file", format))?;
+        Ok(Cow::Owned(decompressed))
+    }
+    #[cfg(not(feature = "compression"))]
+    {
+        bail!(invalid_input(format!(
+            "Loading a {} compressed onnx file requires the `compression` feature",
+            format
+        )))
+    }
+}
+
+/// Return the model's directory from cache or remote retrieval
+///
+/// In offline mode, only the files already present in the cache can be retrieved
+///
+/// The default progress bar is replaced by the progress callback when one is provided
+pub(crate) fn retrieve_model(
+    model_code: String,
+    cache_dir: PathBuf,
+    show_download_progress: bool,
+    offline: bool,
+    hub_config: HubConfig,
+    progress_callback: Option<ProgressCallback>,
+) -> Result<ModelRepo> {
+    let cache = Cache::new(cache_dir.clone()).model(model_code.clone());
+    if offline {
+        return Ok(ModelRepo::Offline {
+            repo: cache,
+            cache_dir,
+        });
+    }
+
+    let show_download_progress = show_download_progress && progress_callback.is_none();
+    let api = hub_api(cache_dir, show_download_progress, hub_config)?;
+
+    let repo = Box::new(api.model(model_code));
+    Ok(ModelRepo::Remote {
+        repo,
+        cache,
+        progress_callback,
+        retry_policy: RetryPolicy::default(),
+    })
+}
+
+/// Settings of the requests made to the Hugging Face Hub
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HubConfig {
+    pub(crate) hf_token: Option<String>,
+    pub(crate) endpoint: Option<String>,
+    pub(crate) user_agent: Option<String>,
+}
+
+/// Build the Hugging Face Hub client used to download the model files
+pub(crate) fn hub_api(
+    cache_dir: PathBuf,
+    show_download_progress: bool,
+    hub_config: HubConfig,
+) -> Result<Api> {
+    let HubConfig {
+        hf_token,
+        endpoint,
+        user_agent,
+    } = hub_config;
+
+    let cache = Cache::new(cache_dir);
+    let (name, version) = user_agent_field(user_agent.as_deref());
+    let mut api_builder = ApiBuilder::from_cache(cache)
+        .with_progress(show_download_progress)
+        .with_user_agent(&name, &version);
+    // Without any explicit token, hf_hub uses the one stored in the cache directory
+    if let Some(token) = resolve_hf_token(hf_token, std::env::var("HF_TOKEN").ok()) {
+        api_builder = api_builder.with_token(Some(token));
+    }
+    if let Some(endpoint) = endpoint {
+        api_builder = api_builder.with_endpoint(endpoint);
+    }
+    Ok(api_builder.build()?)
+}
+
+/// Name and version appended to the User-Agent of hf_hub, which identify the application downloading the models
+///
+/// The user agent is split at its first `/`, such as `my-app/1.2.0`. Defaults to `fastembed/<crate version>`
+pub(crate) fn user_agent_field(user_agent: Option<&str>) -> (String, String) {
+    let user_agent = user_agent.unwrap_or(concat!("fastembed/", env!("CARGO_PKG_VERSION")));
+    match user_agent.split_once('/') {
+        Some((name, version)) => (name.to_string(), version.to_string()),
+        None => (user_agent.to_string(), String::from("unknown")),
+    }
+}
+
+/// The procedure for loading tokenizer files from the hugging face hub is separated
+/// from the main load_tokenizer function (which is expecting bytes, from any source).
+pub(crate) fn load_tokenizer_hf_hub(
+    model_repo: ModelRepo,
+    max_length: usize,
+    padding: Padding,
+    truncation_direction: TruncationDirection,
+    allow_exceed_model_max_length: bool,
+) -> Result<Tokenizer> {
+    let tokenizer_files: TokenizerFiles = TokenizerFiles {
+        tokenizer_file: read_file_to_bytes(&model_repo.get("tokenizer.json")?)?,
+        config_file: read_file_to_bytes(&model_repo.get("config.json")?)?,
+        special_tokens_map_file: read_file_to_bytes(&model_repo.get("special_tokens_map.json")?)?,
+
+        tokenizer_config_file: read_file_to_bytes(&model_repo.get("tokenizer_config.json")?)?,
+    };
+
+    load_tokenizer(
+        tokenizer_files,
+        max_length,
+        padding,
+        truncation_direction,
+        None,
+        None,
+        allow_exceed_model_max_length,
+    )
+}
+
+/// Read the `model_max_length` of a tokenizer config, clamped to MAX_MODEL_MAX_LENGTH
+///
+/// For BGEBaseSmall or MultilingualE5Large, the model_max_length value is set to 1000000000000000019884624838656,
+/// which fits in a f64 but not in a usize
+pub(crate) fn model_max_length(tokenizer_config: &serde_json::Value) -> Option<usize> {
+    let model_max_length = tokenizer_config["model_max_length"].as_f64()?;
+    if model_max_length < 0.0 {
+        return None;
+    }
+    Some(model_max_length.min(MAX_MODEL_MAX_LENGTH as f64) as usize)
+}
+
+/// Function can be called directly from the try_new_from_user_defined function (providing file bytes)
+///
+/// Or indirectly from the try_new function via load_tokenizer_hf_hub (converting HF files to bytes)
+///
+/// `pad_token` and `pad_id` override the values read from the configuration files,
+/// `allow_exceed_model_max_length` skips the clamping of `max_length` to the `model_max_length` of the model
+pub(crate) fn load_tokenizer(
+    tokenizer_files: TokenizerFiles,
+    max_length: usize,
+    padding: Padding,
+    truncation_direction: TruncationDirection,
+    pad_token: Option<String>,
+    pad_id: Option<u32>,
+    allow_exceed_model_max_length: bool,
+) -> Result<Tokenizer> {
+    let base_error_message =
+        "Error building TokenizerFiles for UserDefinedEmbeddingModel. Could not read {} file.";
+
+    // Serialise each tokenizer file
+    // The configuration files can be left empty for the self-contained tokenizer.json files
+    let parse_config = |bytes: &[u8], filename: &str| -> Result<serde_json::Value> {
+        if bytes.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(serde_json::from_slice(bytes).map_err(|_| {
+            FastEmbedError::TokenizerLoad(base_error_message.replace("{}", filename).into())
+        })?)
+    };
+    let config = parse_config(&tokenizer_files.config_file, "config.json")?;
+    let special_tokens_map = parse_config(
+        &tokenizer_files.special_tokens_map_file,
+        "special_tokens_map.json",
+    )?;
+    let tokenizer_config = parse_config(
+        &tokenizer_files.tokenizer_config_file,
+        "tokenizer_config.json",
+    )?;
+    let mut tokenizer: tokenizers::Tokenizer =
+        tokenizers::Tokenizer::from_bytes(tokenizer_files.tokenizer_file).map_err(|_| {
+            FastEmbedError::TokenizerLoad(base_error_message.replace("{}", "tokenizer.json").into())
+        })?;
+
+    // Tokenizers built by hand often omit it, in which case the configured max_length is used as is
+    let max_length = match model_max_length(&tokenizer_config) {
+        Some(model_max_length) if !allow_exceed_model_max_length => {
+            max_length.min(model_max_length)
+        }
+        _ => max_length,
+    };
+    // Without configuration files, the padding embedded in tokenizer.json is used if any
+    let embedded_padding = tokenizer.get_padding();
+    let pad_id = pad_id.unwrap_or_else(|| match config["pad_token_id"].as_u64() {
+        Some(pad_id) => pad_id as u32,
+        None => embedded_padding.map_or(0, |padding| padding.pad_id),
+    });
+    // The pad token is either a plain string or a serialized AddedToken
+    let pad_token = pad_token.unwrap_or_else(|| {
+        let pad_token = &tokenizer_config["pad_token"];
+        pad_token
+            .as_str()
+            .or_else(|| pad_token["content"].as_str())
+            .or_else(|| embedded_padding.map(|padding| padding.pad_token.as_str()))
+            .unwrap_or(DEFAULT_PAD_TOKEN)
+            .into()
+    });
+
+    let strategy = match padding {
+        Padding::BatchLongest => PaddingStrategy::BatchLongest,
+        Padding::Fixed => PaddingStrategy::Fixed(max_length),
+    };
+
+    // Only the padding and truncation are replaced, the normalizer, pre-tokenizer and post-processor
+    // of tokenizer.json are kept as they are
+    let mut tokenizer = tokenizer
+        .with_padding(Some(PaddingParams {
+            strategy,
+            pad_token,
+            pad_id,
+            ..Default::default()
+        }))
+        .with_truncation(Some(TruncationParams {
+            max_length,
+            direction: truncation_direction,
+            ..Default::default()
+        }))
+        .map_err(FastEmbedError::TokenizerLoad)?
+        .clone();
+    if let serde_json::Value::Object(root_object) = special_tokens_map {
+        for (_, value) in root_object.iter() {
+            if value.is_string() {
+                tokenizer.add_special_tokens(&[AddedToken {
+                    content: value.as_str().unwrap().into(),
+                    special: true,
+                    ..Default::default()
+                }]);
+            } else if value.is_object() {
+                tokenizer.add_special_tokens(&[AddedToken {
+                    content: value["content"].as_str().unwrap().into(),
+                    special: true,
+                    single_word: value["single_word"].as_bool().unwrap(),
+                    lstrip: value["lstrip"].as_bool().unwrap(),
+                    rstrip: value["rstrip"].as_bool().unwrap(),
+                    normalized: value["normalized"].as_bool().unwrap(),
+                }]);
+            }
+        }
+    }
+    Ok(tokenizer)
+}
+
+/// Use the provided Hugging Face token, or the one of the `HF_TOKEN` environment variable when there is none
+pub(crate) fn resolve_hf_token(
+    hf_token: Option<String>,
+    env_token: Option<String>,
+) -> Option<String> {
+    hf_token.or_else(|| env_token.filter(|token| !token.is_empty()))
+}
+
+/// Location the model files are retrieved from
+pub(crate) enum ModelRepo {
+    /// The Hugging Face Hub, using the cache to avoid downloading files twice
+    Remote {
+        repo: Box<ApiRepo>,
+        cache: CacheRepo,
+        progress_callback: Option<ProgressCallback>,
+        retry_policy: RetryPolicy,
+    },
+    /// The cache only, no network requests are made
+    Offline { repo: CacheRepo, cache_dir: PathBuf },
+}
+
+impl ModelRepo {
+    /// Retry the failed downloads according to the policy
+    pub(crate) fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        if let ModelRepo::Remote { retry_policy, .. } = &mut self {
+            *retry_policy = policy;
+        }
+        self
+    }
+
+    /// Get the local path of a model file, downloading it if necessary and allowed
+    pub(crate) fn get(&self, filename: &str) -> Result<PathBuf> {
+        match self {
+            ModelRepo::Remote { retry_policy, .. } => {
+                retry_policy.run(|| self.fetch(filename)).with_context(|| {
+                    FastEmbedError::Download(format!("Failed to download {}", filename).into())
+                })
+            }
+            ModelRepo::Offline { .. } => self.fetch(filename),
+        }
+    }
+
+    /// Get the local path of a model file in a single attempt
+    fn fetch(&self, filename: &str) -> Result<PathBuf> {
+        match self {
+            ModelRepo::Remote {
+                repo,
+                progress_callback: None,
+                ..
+            } => Ok(repo.get(filename)?),
+            ModelRepo::Remote {
+                repo,
+                cache,
+                progress_callback: Some(callback),
+                ..
+            } => match cache.get(filename) {
+                Some(path) => Ok(path),
+                None => Ok(repo.download_with_progress(
+                    filename,
+                    CallbackProgress {
+                        callback: Arc::clone(callback),
+                        filename: String::new(),
+                        downloaded: 0,
+                        total: 0,
+                    },
+                )?),
+            },
+            ModelRepo::Offline { repo, cache_dir } => repo.get(filename).with_context(|| {
+                FastEmbedError::Download(
+                    format!(
+                        "{} is not available in the cache at {} and offline mode is enabled",
+                        filename,
+                        cache_dir.display()
+                    )
+                    .into(),
+                )
+            }),
+        }
+    }
+
+    /// Get the local path of the model file, after retrieving the additional files it depends on
+    ///
+    /// Each file is looked up at its alternate path if it is missing, see [alternate_model_file]
+    pub(crate) fn get_model_files(
+        &self,
+        model_file: &str,
+        additional_files: &[String],
+        model_name: impl Display,
+    ) -> Result<PathBuf> {
+        for file in additional_files {
+            self.get_or_alternate(file).with_context(|| {
+                FastEmbedError::Download(
+                    format!("Failed to retrieve {} for {}", file, model_name).into(),
+                )
+            })?;
+        }
+        self.get_or_alternate(model_file).with_context(|| {
+            FastEmbedError::Download(
+                format!("Failed to retrieve {} for {}", model_file, model_name).into(),
+            )
+        })
+    }
+
+    /// Get the local path of a model file, or of its alternate path if the file is missing
+    ///
+    /// When both fail, the error of the file holds the one of its alternate path
+    fn get_or_alternate(&self, filename: &str) -> Result<PathBuf> {
+        self.get(filename).or_else(|error| {
+            let alternate = alternate_model_file(filename);
+            self.get(&alternate).map_err(|alternate_error| {
+                error.context(format!(
+                    "{} is not available either: {:#}",
+                    alternate, alternate_error
+                ))
+            })
+        })
+    }
+}
+
+/// Other common location of a model file in the Hugging Face repositories
+///
+/// The ONNX files are either at the root of the repository or in its `onnx` directory
+pub(crate) fn alternate_model_file(model_file: &str) -> String {
+    match model_file.strip_prefix("onnx/") {
+        Some(file) => file.to_string(),
+        None => format!("onnx/{}", model_file),
+    }
+}
+
+/// Number of times a failed download is retried, and the delay before the first retry
+///
+/// The delay doubles after every failed retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct RetryPolicy {
+    pub(crate) retries: usize,
+    pub(crate) backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Run the operation until it succeeds or the retries are exhausted, returning the last error
+    ///
+    /// Only the transient failures are retried, the other errors are returned at once
+    pub(crate) fn run<T>(&self, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut delay = self.backoff;
+        let mut retries = self.retries;
+        loop {
+            match operation() {
+                Err(error) if retries > 0 && is_transient(&error) => {
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                    retries -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether a failed download may succeed when retried, after a network or I/O failure, a server error
+/// or a rate limit
+///
+/// The other HTTP errors, such as a missing file or a rejected token, fail again on every retry
+pub(crate) fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| match cause.downcast_ref::<ApiError>() {
+            Some(error) => is_transient_api_error(error),
+            None => cause.is::<std::io::Error>(),
+        })
+}
+
+fn is_transient_api_error(error: &ApiError) -> bool {
+    match error {
+        ApiError::RequestError(error) => match error.as_ref() {
+            ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+            ureq::Error::Transport(_) => true,
+        },
+        ApiError::IoError(_) | ApiError::LockAcquisition(_) => true,
+        ApiError::TooManyRetries(error) => is_transient_api_error(error),
+        _ => false,
+    }
+}
+
+/// Forwards the download progress reported by hf_hub to the user's callback
+struct CallbackProgress {
+    callback: ProgressCallback,
+    filename: String,
+    downloaded: usize,
+    total: usize,
+}
+
+impl CallbackProgress {
+    fn report(&self) {
+        (self.callback)(DownloadProgress {
+            filename: self.filename.clone(),
+            downloaded: self.downloaded,
+            total: self.total,
+        });
+    }
+}
+
+impl Progress for CallbackProgress {
+    fn init(&mut self, size: usize, filename: &str) {
+        self.filename = filename.to_string();
+        self.downloaded = 0;
+        self.total = size;
+        self.report();
+    }
+
+    fn update(&mut self, size: usize) {
+        self.downloaded += size;
+        self.report();
+    }
+
+    fn finish(&mut self) {}
+}
+
+// This type was inferred using IDE hints
+// Turned into a type alias for type hinting
+pub(crate) type Tokenizer = tokenizers::TokenizerImpl<
+    tokenizers::ModelWrapper,
+    tokenizers::NormalizerWrapper,
+    tokenizers::PreTokenizerWrapper,
+    tokenizers::PostProcessorWrapper,
+    tokenizers::DecoderWrapper,
+>;
+
+/// L2 norm of the vector
+pub(crate) fn l2_norm(v: &[f32]) -> f64 {
+    // The squares are summed in f64, which neither overflows for large values nor loses
+    // the precision of the small ones
+    v.iter()
+        .map(|&val| f64::from(val) * f64::from(val))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Scale the vector to a unit L2 norm, in place
+///
+/// A zero vector is left unchanged
+pub(crate) fn normalize(v: &mut [f32]) {
+    let norm = l2_norm(v);
+    if norm == 0.0 {
+        return;
+    }
+
+    let inverse_norm = norm.recip();
+    v.iter_mut()
+        .for_each(|val| *val = (f64::from(*val) * inverse_norm) as f32);
+}
+
+/// Check that the SHA-256 of the file matches the expected lowercase hex digest
+pub(crate) fn verify_sha256(file: &Path, expected: &str) -> Result<()> {
+    let mut reader =
+        File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    let actual = format!("{:x}", hasher.finalize());
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!(FastEmbedError::Download(format!(
+            "Checksum mismatch for {}: expected SHA-256 {}, got {}. The file may be corrupted or partially downloaded, remove it from the cache to download it again",
+            file.display(),
+            expected,
+            actual
+        ).into()));
+    }
+    Ok(())
+}
+
+/// SHA-256 the Hub recorded for a file of the cache, as hf-hub names the blobs of the LFS files after it
+///
+/// `None` for the files outside of a cache, and for the small files stored by Git, whose blobs are named after their SHA-1
+pub(crate) fn cached_sha256(file: &Path) -> Option<String> {
+    let blob = std::fs::canonicalize(file).ok()?;
+    let name = blob.file_name()?.to_str()?;
+    (name.len() == 64 && name.bytes().all(|byte| byte.is_ascii_hexdigit()))
+        .then(|| name.to_lowercase())
+}
+
+/// Total size in bytes of the files in the directory and its sub-directories
+///
+/// Symbolic links are not followed, so that the files of the Hugging Face cache are only counted once
+pub(crate) fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Directory of a model in the Hugging Face cache
+pub(crate) fn cached_model_dir(cache_dir: &Path, model_code: &str) -> PathBuf {
+    cache_dir.join(format!("models--{}", model_code.replace('/', "--")))
+}
+
+/// Names of the tokenizer files downloaded along with the models
+pub(crate) const TOKENIZER_FILE_NAMES: [&str; 4] = [
+    "tokenizer.json",
+    "config.json",
+    "special_tokens_map.json",
+    "tokenizer_config.json",
+];
+
+/// Whether all the files are in the cached snapshot of the model repository, without any network access
+///
+/// The model files are also looked up at their alternate path, as [ModelRepo::get_model_files] retrieves them
+pub(crate) fn files_cached<'a>(
+    cache_dir: &Path,
+    model_code: &str,
+    mut model_files: impl Iterator<Item = &'a str>,
+    mut other_files: impl Iterator<Item = &'a str>,
+) -> bool {
+    let repo = Cache::new(cache_dir.to_path_buf()).model(model_code.to_string());
+    let cached = |file: &str| repo.get(file).is_some();
+    model_files.all(|file| cached(file) || cached(&alternate_model_file(file)))
+        && other_files.all(cached)
+}
+
+/// Read a file to bytes.
+///
+/// Could be used to read the onnx file from a local cache in order to constitute a UserDefinedEmbeddingModel.
+pub fn read_file_to_bytes(file: &PathBuf) -> Result<Vec<u8>, FastEmbedError> {
+    let mut file = File::open(file)?;
+    let file_size = file.metadata()?.len() as usize;
+    let mut buffer = Vec::with_capacity(file_size);
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
@@ -0,0 +1,189 @@
+use std::{error::Error, fmt::Display};
+
+/// Underlying error of a [FastEmbedError]
+pub(crate) type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Error returned by the crate, telling the kind of failure for the callers matching on specific failure modes
+///
+/// Every variant holds the underlying error of ONNX Runtime, the tokenizers, the Hub or the file system,
+/// with the context added by the crate. The error displays as its underlying error, and `source()`
+/// walks the chain of its causes
+#[derive(Debug)]
+pub enum FastEmbedError {
+    /// A model file could not be downloaded, or is missing from the cache in offline mode
+    Download(BoxError),
+    /// The tokenizer files could not be parsed
+    TokenizerLoad(BoxError),
+    /// The tokenizer failed to encode the texts
+    Tokenization(BoxError),
+    /// ONNX Runtime failed to load or run the model
+    Inference(BoxError),
+    /// The output of the model or the buffer provided by the caller does not have the expected shape
+    ShapeMismatch(BoxError),
+    /// The model, or the requested variant of it, is not supported
+    UnsupportedModel(BoxError),
+    /// The arguments or the options are invalid, such as a batch size of 0
+    InvalidInput(BoxError),
+    /// A file could not be read or written
+    Io(BoxError),
+    /// Any other failure, such as a cancelled embedding
+    Other(BoxError),
+}
+
+impl FastEmbedError {
+    /// Underlying error, to downcast to the error type of the dependency that failed
+    pub fn inner(&self) -> &(dyn Error + Send + Sync + 'static) {
+        match self {
+            FastEmbedError::Download(error)
+            | FastEmbedError::TokenizerLoad(error)
+            | FastEmbedError::Tokenization(error)
+            | FastEmbedError::Inference(error)
+            | FastEmbedError::ShapeMismatch(error)
+            | FastEmbedError::UnsupportedModel(error)
+            | FastEmbedError::InvalidInput(error)
+            | FastEmbedError::Io(error)
+            | FastEmbedError::Other(error) => error.as_ref(),
+        }
+    }
+
+    /// Constructor of the variant of the error, to give its kind to another underlying error
+    fn kind(&self) -> fn(BoxError) -> FastEmbedError {
+        match self {
+            FastEmbedError::Download(_) => FastEmbedError::Download,
+            FastEmbedError::TokenizerLoad(_) => FastEmbedError::TokenizerLoad,
+            FastEmbedError::Tokenization(_) => FastEmbedError::Tokenization,
+            FastEmbedError::Inference(_) => FastEmbedError::Inference,
+            FastEmbedError::ShapeMismatch(_) => FastEmbedError::ShapeMismatch,
+            FastEmbedError::UnsupportedModel(_) => FastEmbedError::UnsupportedModel,
+            FastEmbedError::InvalidInput(_) => FastEmbedError::InvalidInput,
+            FastEmbedError::Io(_) => FastEmbedError::Io,
+            FastEmbedError::Other(_) => FastEmbedError::Other,
+        }
+    }
+}
+
+impl Display for FastEmbedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inner())?;
+        // Print the causes with `{:#}`, like anyhow does
+        if f.alternate() {
+            let mut source = self.inner().source();
+            while let Some(cause) = source {
+                write!(f, ": {}", cause)?;
+                source = cause.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for FastEmbedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+impl From<ort::Error> for FastEmbedError {
+    fn from(error: ort::Error) -> Self {
+        FastEmbedError::Inference(Box::new(error))
+    }
+}
+
+impl From<ndarray::ShapeError> for FastEmbedError {
+    fn from(error: ndarray::ShapeError) -> Self {
+        FastEmbedError::ShapeMismatch(Box::new(error))
+    }
+}
+
+impl From<hf_hub::api::sync::ApiError> for FastEmbedError {
+    fn from(error: hf_hub::api::sync::ApiError) -> Self {
+        FastEmbedError::Download(Box::new(error))
+    }
+}
+
+impl From<std::io::Error> for FastEmbedError {
+    fn from(error: std::io::Error) -> Self {
+        FastEmbedError::Io(Box::new(error))
+    }
+}
+
+/// The crate builds its errors with anyhow, they take the kind of the outermost FastEmbedError attached to them,
+/// or else the one of their causes from ONNX Runtime, ndarray, the Hub or the file system
+impl From<anyhow::Error> for FastEmbedError {
+    fn from(error: anyhow::Error) -> Self {
+        // A lone FastEmbedError is returned as is rather than nested in itself,
+        // and the lone errors of the dependencies are held as they are
+        let error = match take::<FastEmbedError>(error) {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        let error = match take::<ort::Error>(error) {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match take::<ndarray::ShapeError>(error) {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match take::<hf_hub::api::sync::ApiError>(error) {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match take::<std::io::Error>(error) {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+
+        // Unlike the causes, the downcast also finds the FastEmbedError attached as context
+        let kind = match error.downcast_ref::<FastEmbedError>() {
+            Some(kind) => kind.kind(),
+            None => error
+                .chain()
+                .find_map(dependency_error_kind)
+                .unwrap_or(FastEmbedError::Other),
+        };
+        kind(error.into())
+    }
+}
+
+/// Take the error out of the anyhow error, if it is one of type `E` without any context
+///
+/// The first cause is the error itself, or the context error when a context is attached,
+/// so that no context is lost to the downcast
+fn take<E: Error + Send + Sync + 'static>(error: anyhow::Error) -> Result<E, anyhow::Error> {
+    if error.chain().next().is_some_and(|cause| cause.is::<E>()) {
+        error.downcast::<E>()
+    } else {
+        Err(error)
+    }
+}
+
+/// Kind of the errors of the dependencies, by their type
+fn dependency_error_kind(cause: &(dyn Error + 'static)) -> Option<fn(BoxError) -> FastEmbedError> {
+    if cause.is::<ort::Error>() {
+        Some(FastEmbedError::Inference)
+    } else if cause.is::<ndarray::ShapeError>() {
+        Some(FastEmbedError::ShapeMismatch)
+    } else if cause.is::<hf_hub::api::sync::ApiError>() {
+        Some(FastEmbedError::Download)
+    } else if cause.is::<std::io::Error>() {
+        Some(FastEmbedError::Io)
+    } else {
+        None
+    }
+}
+
+/// Context of the errors of ONNX Runtime when running a session
+pub(crate) fn inference_error() -> FastEmbedError {
+    FastEmbedError::Inference(BoxError::from("ONNX Runtime failed to run the model"))
+}
+
+/// Error of the tokenizer encoding the texts
+pub(crate) fn tokenization_error(error: tokenizers::Error) -> anyhow::Error {
+    FastEmbedError::Tokenization(error).into()
+}
+
+/// Error of arguments or options rejected by the crate
+pub(crate) fn invalid_input(message: impl Display) -> FastEmbedError {
+    FastEmbedError::InvalidInput(BoxError::from(message.to_string()))
+}
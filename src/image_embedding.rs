@@ -0,0 +1,280 @@
+use crate::{
+    common::{
+        default_cache_dir, normalize, retrieve_model, session_builder, Embedding, HubConfig,
+        OptimizationLevel, DEFAULT_BATCH_SIZE,
+    },
+    error::{inference_error, invalid_input, FastEmbedError},
+    models::{image_models_list, ImageEmbeddingModel, ModelInfo},
+};
+use anyhow::{bail, Context, Result};
+use image::{imageops::FilterType, DynamicImage};
+use ndarray::{Array4, Axis, Ix2, Ix3};
+use ort::{ExecutionProviderDispatch, Session, Value};
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+use std::path::PathBuf;
+
+const DEFAULT_IMAGE_MODEL: ImageEmbeddingModel = ImageEmbeddingModel::ClipVitB32;
+
+/// Options for initializing the ImageEmbedding model
+#[derive(Debug, Clone)]
+pub struct ImageInitOptions {
+    pub model_name: ImageEmbeddingModel,
+    pub execution_providers: Vec<ExecutionProviderDispatch>,
+    pub cache_dir: PathBuf,
+    pub show_download_progress: bool,
+}
+
+impl Default for ImageInitOptions {
+    fn default() -> Self {
+        Self {
+            model_name: DEFAULT_IMAGE_MODEL,
+            execution_providers: Default::default(),
+            cache_dir: default_cache_dir(),
+            show_download_progress: true,
+        }
+    }
+}
+
+/// Rust representation of the ImageEmbedding model
+pub struct ImageEmbedding {
+    session: Session,
+    preprocessing: ImagePreprocessing,
+}
+
+impl ImageEmbedding {
+    /// Try to generate a new ImageEmbedding Instance
+    ///
+    /// Uses the highest level of Graph optimization
+    ///
+    /// Uses the total number of CPUs available as the number of intra-threads
+    pub fn try_new(options: ImageInitOptions) -> Result<Self, FastEmbedError> {
+        let ImageInitOptions {
+            model_name,
+            execution_providers,
+            cache_dir,
+            show_download_progress,
+        } = options;
+
+        let model_info = ImageEmbedding::get_model_info(&model_name);
+        let model_repo = retrieve_model(
+            model_info.model_code.clone(),
+            cache_dir,
+            show_download_progress,
+            false,
+            HubConfig::default(),
+            None,
+        )?;
+
+        let model_file_reference = model_repo.get_model_files(
+            &model_info.model_file,
+            &model_info.additional_files,
+            &model_name,
+        )?;
+        let preprocessor_config =
+            model_repo
+                .get("preprocessor_config.json")
+                .with_context(|| {
+                    format!(
+                        "Failed to retrieve preprocessor_config.json for {}",
+                        model_name
+                    )
+                })?;
+        let preprocessor_config: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(preprocessor_config)?)
+                .context("Could not parse preprocessor_config.json")?;
+
+        let (session_builder, _) = session_builder(
+            execution_providers,
+            None,
+            None,
+            OptimizationLevel::default(),
+            None,
+        )?;
+        let session = session_builder.with_model_from_file(model_file_reference)?;
+
+        Ok(Self {
+            session,
+            preprocessing: ImagePreprocessing::from_config(&preprocessor_config),
+        })
+    }
+
+    /// Retrieve a list of supported image models
+    pub fn list_supported_models() -> Vec<ModelInfo<ImageEmbeddingModel>> {
+        image_models_list()
+    }
+
+    /// Get ModelInfo from ImageEmbeddingModel
+    pub fn get_model_info(model: &ImageEmbeddingModel) -> ModelInfo<ImageEmbeddingModel> {
+        ImageEmbedding::list_supported_models()
+            .into_iter()
+            .find(|m| &m.model == model)
+            .expect("Model not found.")
+    }
+
+    /// Method to generate normalized image embeddings for a slice of images
+    ///
+    /// The images are resized, center cropped and normalized as the model was trained with.
+    /// The CLIP embeddings share their space with the embeddings of the matching CLIP text encoder
+    pub fn embed(
+        &self,
+        images: &[DynamicImage],
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        // Determine the batch size, default if not specified
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+
+        let output = images
+            .par_chunks(batch_size)
+            .map(|batch| {
+                let pixel_values = self.preprocessing.pixel_values(batch)?;
+
+                // CLIP vision models take a single input, usually named "pixel_values"
+                let input_name = &self.session.inputs[0].name;
+                let outputs = self
+                    .session
+                    .run(ort::inputs![
+                        input_name.as_str() => Value::from_array(pixel_values)?,
+                    ]?)
+                    .with_context(inference_error)?;
+
+                let output_data = match outputs.get("image_embeds") {
+                    Some(output) => output,
+                    None => &outputs[0],
+                }
+                .extract_tensor::<f32>()?;
+                let output_view = output_data.view();
+
+                // Some exports output the hidden states of all the patches, the first one is the image embedding
+                let embeddings = match output_view.ndim() {
+                    3 => output_view
+                        .view()
+                        .into_dimensionality::<Ix3>()?
+                        .index_axis(Axis(1), 0)
+                        .to_owned(),
+                    _ => output_view.view().into_dimensionality::<Ix2>()?.to_owned(),
+                };
+
+                Ok(embeddings
+                    .rows()
+                    .into_iter()
+                    .map(|row| {
+                        let mut embedding = row.to_vec();
+                        normalize(&mut embedding);
+                        embedding
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Ok(output)
+    }
+}
+
+/// Resizing, cropping and normalization applied to the images, read from the preprocessor_config.json of the model
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ImagePreprocessing {
+    /// Length the shortest edge of the images is resized to
+    pub(crate) size: u32,
+    /// Width and height of the center crop
+    pub(crate) crop_size: (u32, u32),
+    pub(crate) mean: [f32; 3],
+    pub(crate) std: [f32; 3],
+}
+
+impl Default for ImagePreprocessing {
+    /// The preprocessing of the original CLIP models
+    fn default() -> Self {
+        Self {
+            size: 224,
+            crop_size: (224, 224),
+            mean: [0.481_454_66, 0.457_827_5, 0.408_210_73],
+            std: [0.268_629_54, 0.261_302_6, 0.275_777_1],
+        }
+    }
+}
+
+impl ImagePreprocessing {
+    /// Read the preprocessing from a preprocessor_config.json, using the CLIP defaults for the missing values
+    ///
+    /// Both the plain numbers of the older configurations and the `shortest_edge` and `height`/`width`
+    /// objects of the newer ones are supported
+    pub(crate) fn from_config(config: &serde_json::Value) -> Self {
+        let default = Self::default();
+        let size = config["size"]
+            .as_u64()
+            .or_else(|| config["size"]["shortest_edge"].as_u64())
+            .map_or(default.size, |size| size as u32);
+        let crop_size = &config["crop_size"];
+        let crop_size = match crop_size.as_u64() {
+            Some(crop_size) => (crop_size as u32, crop_size as u32),
+            None => match (crop_size["width"].as_u64(), crop_size["height"].as_u64()) {
+                (Some(width), Some(height)) => (width as u32, height as u32),
+                _ => default.crop_size,
+            },
+        };
+        let channels = |values: &serde_json::Value, default: [f32; 3]| -> [f32; 3] {
+            let values: Option<Vec<f32>> = values.as_array().and_then(|values| {
+                values
+                    .iter()
+                    .map(|value| value.as_f64().map(|value| value as f32))
+                    .collect()
+            });
+            match values.as_deref() {
+                Some(&[red, green, blue]) => [red, green, blue],
+                _ => default,
+            }
+        };
+        Self {
+            size,
+            crop_size,
+            mean: channels(&config["image_mean"], default.mean),
+            std: channels(&config["image_std"], default.std),
+        }
+    }
+
+    /// Turn a batch of images into the normalized pixel values of the model, in NCHW layout
+    pub(crate) fn pixel_values(&self, images: &[DynamicImage]) -> Result<Array4<f32>> {
+        if self.size == 0 || self.crop_size.0 == 0 || self.crop_size.1 == 0 {
+            bail!(invalid_input(
+                "The image size and crop size must be at least 1"
+            ));
+        }
+        let (crop_width, crop_height) = self.crop_size;
+        let mut pixel_values =
+            Array4::zeros((images.len(), 3, crop_height as usize, crop_width as usize));
+
+        for (index, image) in images.iter().enumerate() {
+            if image.width() == 0 || image.height() == 0 {
+                bail!(invalid_input(format!("The image {} is empty", index)));
+            }
+            // Resize the shortest edge to `size`, keeping the aspect ratio, then crop the center
+            let scale = self.size as f64 / image.width().min(image.height()) as f64;
+            let width = ((image.width() as f64 * scale).round() as u32).max(crop_width);
+            let height = ((image.height() as f64 * scale).round() as u32).max(crop_height);
+            let resized = image.resize_exact(width, height, FilterType::CatmullRom);
+            let cropped = resized
+                .crop_imm(
+                    (width - crop_width) / 2,
+                    (height - crop_height) / 2,
+                    crop_width,
+                    crop_height,
+                )
+                .to_rgb8();
+
+            for (x, y, pixel) in cropped.enumerate_pixels() {
+                for channel in 0..3 {
+                    let value = pixel[channel] as f32 / 255.0;
+                    pixel_values[[index, channel, y as usize, x as usize]] =
+                        (value - self.mean[channel]) / self.std[channel];
+                }
+            }
+        }
+        Ok(pixel_values)
+    }
+}
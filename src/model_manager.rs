@@ -0,0 +1,88 @@
+use crate::{Embedding, EmbeddingModel, FastEmbedError, InitOptions, TextEmbedding};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError, TryLockError},
+};
+
+/// Model slot, filled on the first use of the model
+pub(crate) type ModelSlot = Arc<Mutex<Option<Arc<TextEmbedding>>>>;
+
+/// Holds several TextEmbedding models sharing the same options, such as the cache directory
+/// and the execution providers
+///
+/// The models are initialized on their first use, and shared between the threads afterwards.
+/// Different models are initialized concurrently, while the callers of a model being initialized
+/// wait for it instead of downloading it again
+pub struct ModelManager {
+    options: InitOptions,
+    models: Mutex<HashMap<EmbeddingModel, ModelSlot>>,
+}
+
+impl ModelManager {
+    /// Create a manager initializing the models with the options, whose `model_name` is ignored
+    pub fn new(options: InitOptions) -> Self {
+        Self {
+            options,
+            models: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the model, initializing it if it is the first time it is used
+    ///
+    /// A model that failed to initialize is retried on its next use
+    pub fn get(&self, model: &EmbeddingModel) -> Result<Arc<TextEmbedding>, FastEmbedError> {
+        let slot = self.slot(model);
+
+        // The slot is only filled once the model is initialized, so it is consistent even if a thread panicked
+        let mut slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(text_embedding) = slot.as_ref() {
+            return Ok(Arc::clone(text_embedding));
+        }
+        let text_embedding = Arc::new(TextEmbedding::try_new(InitOptions {
+            model_name: model.clone(),
+            ..self.options.clone()
+        })?);
+        *slot = Some(Arc::clone(&text_embedding));
+        Ok(text_embedding)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts with the model, see TextEmbedding::embed
+    pub fn embed<S: AsRef<str> + Send + Sync>(
+        &self,
+        model: &EmbeddingModel,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        self.get(model)?.embed(texts, batch_size)
+    }
+
+    /// The models initialized so far
+    ///
+    /// The models being initialized are not waited for, they are not loaded yet
+    pub fn loaded_models(&self) -> Vec<EmbeddingModel> {
+        // The map is released before checking the slots, so that the other models stay available
+        let slots: Vec<(EmbeddingModel, ModelSlot)> = {
+            let models = self.models.lock().unwrap_or_else(PoisonError::into_inner);
+            models
+                .iter()
+                .map(|(model, slot)| (model.clone(), Arc::clone(slot)))
+                .collect()
+        };
+        slots
+            .into_iter()
+            .filter(|(_, slot)| match slot.try_lock() {
+                Ok(slot) => slot.is_some(),
+                Err(TryLockError::Poisoned(slot)) => slot.into_inner().is_some(),
+                Err(TryLockError::WouldBlock) => false,
+            })
+            .map(|(model, _)| model)
+            .collect()
+    }
+
+    /// Slot of the model, created empty on its first use
+    pub(crate) fn slot(&self, model: &EmbeddingModel) -> ModelSlot {
+        let mut models = self.models.lock().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(models.entry(model.clone()).or_default())
+    }
+}
@@ -1,6 +1,10 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::FastEmbedError;
+
 use variant_count::VariantCount;
 
-#[derive(Debug, Clone, PartialEq, Eq, VariantCount)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, VariantCount)]
 pub enum EmbeddingModel {
     /// sentence-transformers/all-MiniLM-L6-v2
     AllMiniLML6V2,
@@ -38,12 +42,45 @@ pub enum EmbeddingModel {
     MultilingualE5Base,
     /// intfloat/multilingual-e5-large
     MultilingualE5Large,
+    /// intfloat/multilingual-e5-large-instruct
+    MultilingualE5LargeInstruct,
     /// mixedbread-ai/mxbai-embed-large-v1
     MxbaiEmbedLargeV1,
     /// Quantized mixedbread-ai/mxbai-embed-large-v1
     MxbaiEmbedLargeV1Q,
 }
 
+impl EmbeddingModel {
+    /// Every supported model, in the order of their declaration
+    ///
+    /// Unlike TextEmbedding::list_supported_models, nothing is allocated
+    pub fn all() -> &'static [EmbeddingModel] {
+        &[
+            EmbeddingModel::AllMiniLML6V2,
+            EmbeddingModel::AllMiniLML6V2Q,
+            EmbeddingModel::BGEBaseENV15,
+            EmbeddingModel::BGEBaseENV15Q,
+            EmbeddingModel::BGELargeENV15,
+            EmbeddingModel::BGELargeENV15Q,
+            EmbeddingModel::BGESmallENV15,
+            EmbeddingModel::BGESmallENV15Q,
+            EmbeddingModel::NomicEmbedTextV1,
+            EmbeddingModel::NomicEmbedTextV15,
+            EmbeddingModel::NomicEmbedTextV15Q,
+            EmbeddingModel::ParaphraseMLMiniLML12V2,
+            EmbeddingModel::ParaphraseMLMiniLML12V2Q,
+            EmbeddingModel::ParaphraseMLMpnetBaseV2,
+            EmbeddingModel::BGESmallZHV15,
+            EmbeddingModel::MultilingualE5Small,
+            EmbeddingModel::MultilingualE5Base,
+            EmbeddingModel::MultilingualE5Large,
+            EmbeddingModel::MultilingualE5LargeInstruct,
+            EmbeddingModel::MxbaiEmbedLargeV1,
+            EmbeddingModel::MxbaiEmbedLargeV1Q,
+        ]
+    }
+}
+
 pub(crate) fn models_list() -> Vec<ModelInfo> {
     let models_list = vec![
         ModelInfo {
@@ -52,6 +89,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Sentence Transformer model, MiniLM-L6-v2"),
             model_code: String::from("Qdrant/all-MiniLM-L6-v2-onnx"),
             model_file: String::from("model.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/sentence-transformers/all-MiniLM-L6-v2"),
         },
         ModelInfo {
             model: EmbeddingModel::AllMiniLML6V2Q,
@@ -59,6 +107,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Quantized Sentence Transformer model, MiniLM-L6-v2"),
             model_code: String::from("Xenova/all-MiniLM-L6-v2"),
             model_file: String::from("onnx/model_quantized.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/sentence-transformers/all-MiniLM-L6-v2"),
         },
         ModelInfo {
             model: EmbeddingModel::BGEBaseENV15,
@@ -66,6 +125,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("v1.5 release of the base English model"),
             model_code: String::from("Xenova/bge-base-en-v1.5"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/BAAI/bge-base-en-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::BGEBaseENV15Q,
@@ -73,6 +143,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Quantized v1.5 release of the large English model"),
             model_code: String::from("Qdrant/bge-base-en-v1.5-onnx-Q"),
             model_file: String::from("model_optimized.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/BAAI/bge-base-en-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::BGELargeENV15,
@@ -80,6 +161,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("v1.5 release of the large English model"),
             model_code: String::from("Xenova/bge-large-en-v1.5"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/BAAI/bge-large-en-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::BGELargeENV15Q,
@@ -87,6 +179,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Quantized v1.5 release of the large English model"),
             model_code: String::from("Qdrant/bge-large-en-v1.5-onnx-Q"),
             model_file: String::from("model_optimized.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/BAAI/bge-large-en-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::BGESmallENV15,
@@ -94,6 +197,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("v1.5 release of the fast and default English model"),
             model_code: String::from("Xenova/bge-small-en-v1.5"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/BAAI/bge-small-en-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::BGESmallENV15Q,
@@ -103,6 +217,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             ),
             model_code: String::from("Qdrant/bge-small-en-v1.5-onnx-Q"),
             model_file: String::from("model_optimized.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/BAAI/bge-small-en-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::NomicEmbedTextV1,
@@ -110,6 +235,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("8192 context length english model"),
             model_code: String::from("nomic-ai/nomic-embed-text-v1"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("search_query: "),
+            passage_prefix: String::from("search_document: "),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/nomic-ai/nomic-embed-text-v1"),
         },
         ModelInfo {
             model: EmbeddingModel::NomicEmbedTextV15,
@@ -117,6 +253,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("v1.5 release of the 8192 context length english model"),
             model_code: String::from("nomic-ai/nomic-embed-text-v1.5"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::from("search_query: "),
+            passage_prefix: String::from("search_document: "),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/nomic-ai/nomic-embed-text-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::NomicEmbedTextV15Q,
@@ -126,6 +273,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             ),
             model_code: String::from("nomic-ai/nomic-embed-text-v1.5"),
             model_file: String::from("onnx/model_quantized.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("search_query: "),
+            passage_prefix: String::from("search_document: "),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/nomic-ai/nomic-embed-text-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::ParaphraseMLMiniLML12V2Q,
@@ -133,6 +291,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Quantized Multi-lingual model"),
             model_code: String::from("Qdrant/paraphrase-multilingual-MiniLM-L12-v2-onnx-Q"),
             model_file: String::from("model_optimized.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+            languages: vec![String::from("multilingual")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/sentence-transformers/paraphrase-multilingual-MiniLM-L12-v2"),
         },
         ModelInfo {
             model: EmbeddingModel::ParaphraseMLMiniLML12V2,
@@ -140,6 +309,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Multi-lingual model"),
             model_code: String::from("Xenova/paraphrase-multilingual-MiniLM-L12-v2"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+            languages: vec![String::from("multilingual")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/sentence-transformers/paraphrase-multilingual-MiniLM-L12-v2"),
         },
         ModelInfo {
             model: EmbeddingModel::ParaphraseMLMpnetBaseV2,
@@ -149,6 +329,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             ),
             model_code: String::from("Xenova/paraphrase-multilingual-mpnet-base-v2"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+            languages: vec![String::from("multilingual")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/sentence-transformers/paraphrase-multilingual-mpnet-base-v2"),
         },
         ModelInfo {
             model: EmbeddingModel::BGESmallZHV15,
@@ -156,6 +347,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("v1.5 release of the small Chinese model"),
             model_code: String::from("Xenova/bge-small-zh-v1.5"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::from("为这个句子生成表示以用于检索相关文章："),
+            passage_prefix: String::new(),
+            languages: vec![String::from("zh")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/BAAI/bge-small-zh-v1.5"),
         },
         ModelInfo {
             model: EmbeddingModel::MultilingualE5Small,
@@ -163,6 +365,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Small model of multilingual E5 Text Embeddings"),
             model_code: String::from("intfloat/multilingual-e5-small"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("query: "),
+            passage_prefix: String::from("passage: "),
+            languages: vec![String::from("multilingual")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/intfloat/multilingual-e5-small"),
         },
         ModelInfo {
             model: EmbeddingModel::MultilingualE5Base,
@@ -170,6 +383,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Base model of multilingual E5 Text Embeddings"),
             model_code: String::from("intfloat/multilingual-e5-base"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("query: "),
+            passage_prefix: String::from("passage: "),
+            languages: vec![String::from("multilingual")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/intfloat/multilingual-e5-base"),
         },
         ModelInfo {
             model: EmbeddingModel::MultilingualE5Large,
@@ -177,6 +401,38 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Large model of multilingual E5 Text Embeddings"),
             model_code: String::from("Qdrant/multilingual-e5-large-onnx"),
             model_file: String::from("model.onnx"),
+            model_file_q8: None,
+            additional_files: vec![String::from("model.onnx_data")],
+            query_prefix: String::from("query: "),
+            passage_prefix: String::from("passage: "),
+            languages: vec![String::from("multilingual")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("MIT"),
+            source_url: String::from("https://huggingface.co/intfloat/multilingual-e5-large"),
+        },
+        ModelInfo {
+            model: EmbeddingModel::MultilingualE5LargeInstruct,
+            dim: 1024,
+            description: String::from("Instruction-tuned large model of multilingual E5 Text Embeddings"),
+            model_code: String::from("intfloat/multilingual-e5-large-instruct"),
+            model_file: String::from("onnx/model.onnx"),
+            model_file_q8: None,
+            additional_files: vec![String::from("onnx/model.onnx_data")],
+            // The queries are prefixed by their instruction, the passages are embedded as is
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+            languages: vec![String::from("multilingual")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: Some(String::from("Instruct: {instruction}\nQuery: {text}")),
+            license: String::from("MIT"),
+            source_url: String::from(
+                "https://huggingface.co/intfloat/multilingual-e5-large-instruct",
+            ),
         },
         ModelInfo {
             model: EmbeddingModel::MxbaiEmbedLargeV1,
@@ -184,6 +440,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Large English embedding model from MixedBreed.ai"),
             model_code: String::from("mixedbread-ai/mxbai-embed-large-v1"),
             model_file: String::from("onnx/model.onnx"),
+            model_file_q8: Some(String::from("onnx/model_quantized.onnx")),
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/mixedbread-ai/mxbai-embed-large-v1"),
         },
         ModelInfo {
             model: EmbeddingModel::MxbaiEmbedLargeV1Q,
@@ -191,6 +458,17 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
             description: String::from("Quantized Large English embedding model from MixedBreed.ai"),
             model_code: String::from("mixedbread-ai/mxbai-embed-large-v1"),
             model_file: String::from("onnx/model_quantized.onnx"),
+            model_file_q8: None,
+            additional_files: Vec::new(),
+            query_prefix: String::from("Represent this sentence for searching relevant passages: "),
+            passage_prefix: String::new(),
+            languages: vec![String::from("en")],
+            sha256: None,
+            sha256_q8: None,
+            has_pooler: false,
+            instruction_template: None,
+            license: String::from("Apache-2.0"),
+            source_url: String::from("https://huggingface.co/mixedbread-ai/mxbai-embed-large-v1"),
         },
     ];
 
@@ -209,12 +487,307 @@ pub(crate) fn models_list() -> Vec<ModelInfo> {
 
     models_list
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, VariantCount)]
+pub enum SparseModel {
+    /// prithivida/Splade_PP_en_v1
+    SPLADEPPV1,
+}
+
+pub(crate) fn sparse_models_list() -> Vec<ModelInfo<SparseModel>> {
+    let models_list = vec![ModelInfo {
+        model: SparseModel::SPLADEPPV1,
+        dim: 30522,
+        description: String::from("Independent Implementation of SPLADE++ Model for English"),
+        model_code: String::from("Qdrant/Splade_PP_en_v1"),
+        model_file: String::from("model.onnx"),
+        model_file_q8: None,
+        additional_files: Vec::new(),
+        query_prefix: String::new(),
+        passage_prefix: String::new(),
+        languages: vec![String::from("en")],
+        sha256: None,
+        sha256_q8: None,
+        has_pooler: false,
+        instruction_template: None,
+        license: String::from("Apache-2.0"),
+        source_url: String::from("https://huggingface.co/prithivida/Splade_PP_en_v1"),
+    }];
+
+    assert_eq!(
+        SparseModel::VARIANT_COUNT,
+        models_list.len(),
+        "models::sparse_models_list() is not exhaustive"
+    );
+
+    models_list
+}
+
+#[cfg(feature = "image")]
+#[derive(Debug, Clone, PartialEq, Eq, VariantCount)]
+pub enum ImageEmbeddingModel {
+    /// Qdrant/clip-ViT-B-32-vision
+    ClipVitB32,
+}
+
+#[cfg(feature = "image")]
+pub(crate) fn image_models_list() -> Vec<ModelInfo<ImageEmbeddingModel>> {
+    let models_list = vec![ModelInfo {
+        model: ImageEmbeddingModel::ClipVitB32,
+        dim: 512,
+        description: String::from("CLIP vision encoder based on ViT-B/32"),
+        model_code: String::from("Qdrant/clip-ViT-B-32-vision"),
+        model_file: String::from("model.onnx"),
+        model_file_q8: None,
+        additional_files: Vec::new(),
+        query_prefix: String::new(),
+        passage_prefix: String::new(),
+        languages: vec![String::from("en")],
+        sha256: None,
+        sha256_q8: None,
+        has_pooler: false,
+        instruction_template: None,
+        license: String::from("MIT"),
+        source_url: String::from("https://huggingface.co/openai/clip-vit-base-patch32"),
+    }];
+
+    assert_eq!(
+        ImageEmbeddingModel::VARIANT_COUNT,
+        models_list.len(),
+        "models::image_models_list() is not exhaustive"
+    );
+
+    models_list
+}
+
+/// Quantization of the model weights, used to select the model file to load
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ModelQuantization {
+    /// Full precision weights - Default
+    #[default]
+    None,
+    /// Signed 8-bit weights, faster and smaller on CPU
+    Int8,
+    /// Unsigned 8-bit weights
+    Uint8,
+}
+
 /// Data struct about the available models
 #[derive(Debug, Clone)]
-pub struct ModelInfo {
-    pub model: EmbeddingModel,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModelInfo<T = EmbeddingModel> {
+    pub model: T,
+    /// Dimension of the embeddings produced by the model
     pub dim: usize,
     pub description: String,
     pub model_code: String,
     pub model_file: String,
+    /// Int8 quantized variant of the model file in the same repository, if any
+    pub model_file_q8: Option<String>,
+    /// Other files the model file depends on, such as the external data of models larger than 2GB
+    pub additional_files: Vec<String>,
+    /// Prefix prepended to the texts by TextEmbedding::embed_query, empty if the model does not use one
+    pub query_prefix: String,
+    /// Prefix prepended to the texts by TextEmbedding::embed_passage, empty if the model does not use one
+    pub passage_prefix: String,
+    /// ISO 639-1 codes of the languages the model was trained on, or `multilingual` for the models covering many languages
+    pub languages: Vec<String>,
+    /// Expected SHA-256 of `model_file`, as a lowercase hex string, checked by TextEmbedding::try_new if `verify_checksums` is set
+    pub sha256: Option<String>,
+    /// Expected SHA-256 of `model_file_q8`, checked like `sha256` when the quantized variant is loaded
+    pub sha256_q8: Option<String>,
+    /// Whether the model exposes the output of its pooler layer as a `pooler_output` tensor, used for CLS pooling
+    pub has_pooler: bool,
+    /// Template used by TextEmbedding::embed_with_instruction, holding the `{instruction}` and `{text}` placeholders
+    ///
+    /// Only set for the instruction-tuned models, the other ones do not take instructions
+    pub instruction_template: Option<String>,
+    /// SPDX identifier of the license of the model, such as `MIT` or `Apache-2.0`
+    pub license: String,
+    /// Page of the original model the ONNX export was made from, for the attribution
+    pub source_url: String,
+}
+
+impl<T> ModelInfo<T> {
+    /// Name of the model file to load for the requested quantization, if the model provides it
+    pub fn model_file_for(&self, quantization: ModelQuantization) -> Option<&str> {
+        match quantization {
+            ModelQuantization::None => Some(&self.model_file),
+            ModelQuantization::Int8 => self.model_file_q8.as_deref(),
+            // None of the supported models provides an unsigned variant yet
+            ModelQuantization::Uint8 => None,
+        }
+    }
+}
+
+impl Display for EmbeddingModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let model_info = models_list()
+            .into_iter()
+            .find(|model| model.model == *self)
+            .unwrap();
+        write!(f, "{}", model_info.model_code)
+    }
+}
+
+impl Display for SparseModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let model_info = sparse_models_list()
+            .into_iter()
+            .find(|model| model.model == *self)
+            .unwrap();
+        write!(f, "{}", model_info.model_code)
+    }
+}
+
+#[cfg(feature = "image")]
+impl Display for ImageEmbeddingModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let model_info = image_models_list()
+            .into_iter()
+            .find(|model| model.model == *self)
+            .unwrap();
+        write!(f, "{}", model_info.model_code)
+    }
+}
+
+/// Names of the models in their serialized form, which is the model code
+///
+/// The quantized variants hosted in the same repository as their full precision model
+/// are told apart by the path of their model file, such as `nomic-ai/nomic-embed-text-v1.5/onnx/model_quantized.onnx`
+fn serialized_names() -> Vec<(EmbeddingModel, String)> {
+    let models = models_list();
+    models
+        .iter()
+        .enumerate()
+        .map(|(index, model_info)| {
+            let shared_code = models[..index]
+                .iter()
+                .any(|other| other.model_code == model_info.model_code);
+            let name = if shared_code {
+                format!("{}/{}", model_info.model_code, model_info.model_file)
+            } else {
+                model_info.model_code.clone()
+            };
+            (model_info.model.clone(), name)
+        })
+        .collect()
+}
+
+/// Parse a model from its variant name, such as `BGESmallENV15`, or from its model code,
+/// such as `Xenova/bge-small-en-v1.5`, ignoring the case
+///
+/// A model code shared by several models refers to the first of them, the others are parsed from
+/// their serialized name, made of the model code and the model file
+impl FromStr for EmbeddingModel {
+    type Err = FastEmbedError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let names = serialized_names();
+        names
+            .iter()
+            .find(|(model, serialized_name)| {
+                format!("{:?}", model).eq_ignore_ascii_case(name)
+                    || serialized_name.eq_ignore_ascii_case(name)
+            })
+            .map(|(model, _)| model.clone())
+            .ok_or_else(|| {
+                let valid_names: Vec<String> = names
+                    .iter()
+                    .map(|(model, serialized_name)| format!("{:?} ({})", model, serialized_name))
+                    .collect();
+                FastEmbedError::UnsupportedModel(
+                    format!(
+                        "Unknown embedding model {}, expected one of {}",
+                        name,
+                        valid_names.join(", ")
+                    )
+                    .into(),
+                )
+            })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for EmbeddingModel {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (_, name) = serialized_names()
+            .into_iter()
+            .find(|(model, _)| model == self)
+            .unwrap();
+        serializer.serialize_str(&name)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for EmbeddingModel {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        serialized_names()
+            .into_iter()
+            .find(|(_, model_name)| *model_name == name)
+            .map(|(model, _)| model)
+            .ok_or_else(|| serde::de::Error::custom(format!("Unknown embedding model {}", name)))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SparseModel {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SparseModel {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        sparse_models_list()
+            .into_iter()
+            .find(|model_info| model_info.model_code == name)
+            .map(|model_info| model_info.model)
+            .ok_or_else(|| serde::de::Error::custom(format!("Unknown sparse model {}", name)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, VariantCount)]
+pub enum RerankerModel {
+    /// BAAI/bge-reranker-base
+    BGERerankerBase,
+}
+
+pub(crate) fn reranker_models_list() -> Vec<RerankerModelInfo> {
+    let models_list = vec![RerankerModelInfo {
+        model: RerankerModel::BGERerankerBase,
+        description: String::from("Reranker model for English and Chinese"),
+        model_code: String::from("BAAI/bge-reranker-base"),
+        model_file: String::from("onnx/model.onnx"),
+    }];
+
+    assert_eq!(
+        RerankerModel::VARIANT_COUNT,
+        models_list.len(),
+        "models::reranker_models_list() is not exhaustive"
+    );
+
+    models_list
+}
+
+/// Data struct about the available reranker models
+#[derive(Debug, Clone)]
+pub struct RerankerModelInfo {
+    pub model: RerankerModel,
+    pub description: String,
+    pub model_code: String,
+    pub model_file: String,
+}
+
+impl Display for RerankerModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let model_info = reranker_models_list()
+            .into_iter()
+            .find(|model| model.model == *self)
+            .unwrap();
+        write!(f, "{}", model_info.model_code)
+    }
 }
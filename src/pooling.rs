@@ -0,0 +1,170 @@
+use ndarray::{s, Array2, Array3, ArrayView2, ArrayView3, Axis};
+
+use crate::common::normalize;
+
+/// Strategy used to reduce the per-token hidden states of a model into a single sentence embedding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pooling {
+    /// Use the hidden state of the first ([CLS]) token - Default
+    #[default]
+    Cls,
+    /// Average the hidden states of all the non-padding tokens
+    Mean,
+    /// Take the element-wise maximum of the hidden states of all the non-padding tokens
+    Max,
+    /// Use the hidden state of the last non-padding token, for decoder-based models
+    LastToken,
+}
+
+/// Activation applied to the pooled output, for the classifiers whose output is a probability vector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Activation {
+    /// Keep the pooled output as is - Default
+    #[default]
+    None,
+    /// Apply the logistic function to every value, for the multi-label classifiers
+    Sigmoid,
+    /// Turn every embedding into a probability distribution summing to 1
+    Softmax,
+}
+
+/// Norm the embeddings are divided by, once pooled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Norm {
+    /// Divide by the Euclidean norm, for cosine similarity as dot product - Default
+    #[default]
+    L2,
+    /// Divide by the sum of the absolute values
+    L1,
+    /// Divide by the largest absolute value, scaling the values into `[-1, 1]`
+    Max,
+    /// Keep the embeddings as they are
+    None,
+}
+
+/// `true` converts to the default L2 norm and `false` to no normalization, for the options set from a flag
+impl From<bool> for Norm {
+    fn from(normalize: bool) -> Self {
+        if normalize {
+            Norm::L2
+        } else {
+            Norm::None
+        }
+    }
+}
+
+/// Divide the embedding by its norm, in place. Zero vectors are left as they are
+pub(crate) fn apply_norm(norm: Norm, embedding: &mut [f32]) {
+    let norm = match norm {
+        Norm::None => return,
+        Norm::L2 => return normalize(embedding),
+        Norm::L1 => embedding.iter().map(|&x| f64::from(x.abs())).sum::<f64>(),
+        Norm::Max => embedding
+            .iter()
+            .fold(0.0, |max: f64, &x| max.max(f64::from(x.abs()))),
+    };
+    if norm == 0.0 {
+        return;
+    }
+
+    let inverse_norm = norm.recip();
+    embedding
+        .iter_mut()
+        .for_each(|x| *x = (f64::from(*x) * inverse_norm) as f32);
+}
+
+/// Apply the activation to every row of the pooled output, in place
+pub(crate) fn activate(activation: Activation, pooled: &mut Array2<f32>) {
+    match activation {
+        Activation::None => {}
+        Activation::Sigmoid => pooled.mapv_inplace(|x| 1.0 / (1.0 + (-x).exp())),
+        Activation::Softmax => {
+            for mut row in pooled.rows_mut() {
+                // Subtracting the maximum keeps the exponentials finite
+                let max = row.fold(f32::NEG_INFINITY, |max, &x| max.max(x));
+                row.mapv_inplace(|x| (x - max).exp());
+                let sum = row.sum();
+                row.mapv_inplace(|x| x / sum);
+            }
+        }
+    }
+}
+
+/// L2-normalize the hidden state of every token, for the models normalizing them before pooling
+pub(crate) fn normalize_tokens(token_embeddings: &ArrayView3<f32>) -> Array3<f32> {
+    let mut normalized = token_embeddings.as_standard_layout().into_owned();
+    for mut token in normalized.rows_mut() {
+        normalize(token.as_slice_mut().unwrap());
+    }
+    normalized
+}
+
+/// Take the hidden state of the first token of every sequence
+pub(crate) fn cls(token_embeddings: &ArrayView3<f32>) -> Array2<f32> {
+    token_embeddings.slice(s![.., 0, ..]).to_owned()
+}
+
+/// Average the hidden states over the token dimension, weighted by the attention mask
+/// so that padding tokens do not contribute to the result
+pub(crate) fn mean(
+    token_embeddings: &ArrayView3<f32>,
+    attention_mask: &ArrayView2<i64>,
+) -> Array2<f32> {
+    let mask = attention_mask.mapv(|x| x as f32).insert_axis(Axis(2));
+
+    let summed = (token_embeddings * &mask).sum_axis(Axis(1));
+
+    // Clamp the token count to avoid dividing by zero on fully masked sequences
+    let counts = mask.sum_axis(Axis(1)).mapv(|count| count.max(1e-9));
+
+    summed / counts
+}
+
+/// Take the element-wise maximum of the hidden states over the token dimension,
+/// ignoring the padding tokens whatever their values
+pub(crate) fn max(
+    token_embeddings: &ArrayView3<f32>,
+    attention_mask: &ArrayView2<i64>,
+) -> Array2<f32> {
+    let mut pooled = Array2::from_elem(
+        (token_embeddings.dim().0, token_embeddings.dim().2),
+        f32::NEG_INFINITY,
+    );
+    for ((sequence, mask), mut max) in token_embeddings
+        .outer_iter()
+        .zip(attention_mask.outer_iter())
+        .zip(pooled.outer_iter_mut())
+    {
+        for (token, _) in sequence
+            .outer_iter()
+            .zip(mask.iter())
+            .filter(|(_, &mask)| mask != 0)
+        {
+            max.zip_mut_with(&token, |max, &value| *max = max.max(value));
+        }
+    }
+
+    // Fully masked sequences have no token to take the maximum of
+    pooled.mapv_inplace(|x| if x == f32::NEG_INFINITY { 0.0 } else { x });
+    pooled
+}
+
+/// Take the hidden state of the last non-padding token of every sequence
+///
+/// The position is found from the attention mask, so that both right and left padding are supported.
+/// Fully masked sequences use their first token
+pub(crate) fn last_token(
+    token_embeddings: &ArrayView3<f32>,
+    attention_mask: &ArrayView2<i64>,
+) -> Array2<f32> {
+    let mut pooled = Array2::zeros((token_embeddings.dim().0, token_embeddings.dim().2));
+    for ((sequence, mask), mut row) in token_embeddings
+        .outer_iter()
+        .zip(attention_mask.outer_iter())
+        .zip(pooled.outer_iter_mut())
+    {
+        let last = mask.iter().rposition(|&mask| mask != 0).unwrap_or(0);
+        row.assign(&sequence.row(last));
+    }
+    pooled
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use ndarray::Array2;
+
+use crate::Embedding;
+
+/// Dot product of two vectors
+///
+/// Both vectors are expected to have the same length, which is only checked in debug builds.
+/// In release builds, the extra elements of the longer vector are ignored.
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len(), "Vectors must have the same length");
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Cosine similarity of two vectors, in the range [-1, 1]
+///
+/// Returns 0.0 if either vector has a zero norm.
+/// Both vectors are expected to have the same length, see [dot](crate::dot).
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let norms = dot(a, a).sqrt() * dot(b, b).sqrt();
+    if norms == 0.0 {
+        return 0.0;
+    }
+    dot(a, b) / norms
+}
+
+/// Euclidean (L2) distance between two vectors
+///
+/// Both vectors are expected to have the same length, see [dot](crate::dot).
+pub fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len(), "Vectors must have the same length");
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// How the embeddings are compared, as a similarity or as a distance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric {
+    /// Cosine similarity, in the range [-1, 1] - Default
+    #[default]
+    CosineSimilarity,
+    /// 1 - the cosine similarity, in the range [0, 2]
+    CosineDistance,
+    /// Dot product, equal to the cosine similarity for normalized embeddings
+    DotProduct,
+    /// Euclidean (L2) distance
+    EuclideanL2,
+}
+
+impl Metric {
+    /// Score of the vector `b` against the vector `a`
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::CosineSimilarity => cosine_similarity(a, b),
+            Metric::CosineDistance => 1.0 - cosine_similarity(a, b),
+            Metric::DotProduct => dot(a, b),
+            Metric::EuclideanL2 => euclidean_distance(a, b),
+        }
+    }
+
+    /// Whether the scores are distances, for which the closest vectors have the lowest score
+    pub fn is_distance(&self) -> bool {
+        matches!(self, Metric::CosineDistance | Metric::EuclideanL2)
+    }
+}
+
+/// Find the `k` embeddings of the corpus most similar to the query, using the cosine similarity
+///
+/// Returns the indices in the corpus and the scores, sorted by descending score
+pub fn top_k(query: &[f32], corpus: &[Embedding], k: usize) -> Vec<(usize, f32)> {
+    top_k_by_metric(query, corpus, k, Metric::CosineSimilarity)
+}
+
+/// Find the `k` embeddings of the corpus closest to the query, using the given metric
+///
+/// Returns the indices in the corpus and the scores, sorted by descending similarity,
+/// or by ascending distance for the distance metrics
+pub fn top_k_by_metric(
+    query: &[f32],
+    corpus: &[Embedding],
+    k: usize,
+    metric: Metric,
+) -> Vec<(usize, f32)> {
+    let mut scores: Vec<(usize, f32)> = corpus
+        .iter()
+        .map(|embedding| metric.score(query, embedding))
+        .enumerate()
+        .collect();
+
+    if metric.is_distance() {
+        scores.sort_by(|a, b| a.1.total_cmp(&b.1));
+    } else {
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    }
+    scores.truncate(k);
+    scores
+}
+
+/// Pairwise dot products of the embeddings, which are their cosine similarities when normalized
+///
+/// Computed as a single matrix multiplication. All the embeddings must have the same dimension
+pub(crate) fn dot_matrix(embeddings: &[Embedding]) -> Result<Vec<Vec<f32>>> {
+    let dim = embeddings.first().map_or(0, Vec::len);
+    let values: Vec<f32> = embeddings.iter().flatten().copied().collect();
+    let matrix = Array2::from_shape_vec((embeddings.len(), dim), values)?;
+
+    let similarities = matrix.dot(&matrix.t());
+    Ok(similarities
+        .rows()
+        .into_iter()
+        .map(|row| row.to_vec())
+        .collect())
+}
@@ -0,0 +1,244 @@
+use crate::{
+    common::{
+        default_cache_dir, load_tokenizer_hf_hub, retrieve_model, session_builder, Embed,
+        HubConfig, OptimizationLevel, Padding, SparseEmbedding, Tokenizer, DEFAULT_BATCH_SIZE,
+        DEFAULT_MAX_LENGTH,
+    },
+    error::{inference_error, invalid_input, tokenization_error, FastEmbedError},
+    models::{sparse_models_list, ModelInfo, SparseModel},
+};
+use anyhow::{Context, Result};
+use ndarray::{Array, ArrayView2, ArrayView3, Axis, Ix3};
+use ort::{ExecutionProviderDispatch, Session, Value};
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+use std::path::PathBuf;
+use tokenizers::TruncationDirection;
+
+const DEFAULT_SPARSE_MODEL: SparseModel = SparseModel::SPLADEPPV1;
+
+/// Options for initializing the SparseTextEmbedding model
+#[derive(Debug, Clone)]
+pub struct SparseInitOptions {
+    pub model_name: SparseModel,
+    pub execution_providers: Vec<ExecutionProviderDispatch>,
+    pub max_length: usize,
+    pub cache_dir: PathBuf,
+    pub show_download_progress: bool,
+}
+
+impl Default for SparseInitOptions {
+    fn default() -> Self {
+        Self {
+            model_name: DEFAULT_SPARSE_MODEL,
+            execution_providers: Default::default(),
+            max_length: DEFAULT_MAX_LENGTH,
+            cache_dir: default_cache_dir(),
+            show_download_progress: true,
+        }
+    }
+}
+
+/// Rust representation of the SparseTextEmbedding model
+pub struct SparseTextEmbedding {
+    tokenizer: Tokenizer,
+    session: Session,
+    need_token_type_ids: bool,
+}
+
+impl SparseTextEmbedding {
+    /// Try to generate a new SparseTextEmbedding Instance
+    ///
+    /// Uses the highest level of Graph optimization
+    ///
+    /// Uses the total number of CPUs available as the number of intra-threads
+    pub fn try_new(options: SparseInitOptions) -> Result<Self, FastEmbedError> {
+        let SparseInitOptions {
+            model_name,
+            execution_providers,
+            max_length,
+            cache_dir,
+            show_download_progress,
+        } = options;
+
+        let model_info = SparseTextEmbedding::get_model_info(&model_name);
+        let model_repo = retrieve_model(
+            model_info.model_code.clone(),
+            cache_dir,
+            show_download_progress,
+            false,
+            HubConfig::default(),
+            None,
+        )?;
+
+        let model_file_name = model_info.model_file;
+        let model_file_reference = model_repo.get_model_files(
+            &model_file_name,
+            &model_info.additional_files,
+            &model_name,
+        )?;
+
+        let (session_builder, _) = session_builder(
+            execution_providers,
+            None,
+            None,
+            OptimizationLevel::default(),
+            None,
+        )?;
+        let session = session_builder.with_model_from_file(model_file_reference)?;
+
+        let tokenizer = load_tokenizer_hf_hub(
+            model_repo,
+            max_length,
+            Padding::default(),
+            TruncationDirection::default(),
+            false,
+        )?;
+        Ok(Self::new(tokenizer, session))
+    }
+
+    /// Private method to return an instance
+    fn new(tokenizer: Tokenizer, session: Session) -> Self {
+        let need_token_type_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+        Self {
+            tokenizer,
+            session,
+            need_token_type_ids,
+        }
+    }
+
+    /// Retrieve a list of supported sparse models
+    pub fn list_supported_models() -> Vec<ModelInfo<SparseModel>> {
+        sparse_models_list()
+    }
+
+    /// Get ModelInfo from SparseModel
+    pub fn get_model_info(model: &SparseModel) -> ModelInfo<SparseModel> {
+        SparseTextEmbedding::list_supported_models()
+            .into_iter()
+            .find(|m| &m.model == model)
+            .expect("Model not found.")
+    }
+
+    /// Method to generate sparse embeddings for a Vec of texts
+    ///
+    /// Every embedding holds the vocabulary indices of its non-zero weights, in ascending order
+    // Generic type to accept String, &str, OsString, &OsStr
+    pub fn embed<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<SparseEmbedding>, FastEmbedError> {
+        // Determine the batch size, default if not specified
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+
+        let output = texts
+            .par_chunks(batch_size)
+            .map(|batch| {
+                // Encode the texts in the batch
+                let inputs = batch.iter().map(|text| text.as_ref()).collect();
+                let encodings = self
+                    .tokenizer
+                    .encode_batch(inputs, true)
+                    .map_err(tokenization_error)?;
+
+                let encoding_length = encodings[0].len();
+                let batch_size = batch.len();
+
+                let max_size = encoding_length * batch_size;
+
+                let mut ids_array = Vec::with_capacity(max_size);
+                let mut mask_array = Vec::with_capacity(max_size);
+                let mut typeids_array = Vec::with_capacity(max_size);
+
+                encodings.iter().for_each(|encoding| {
+                    ids_array.extend(encoding.get_ids().iter().map(|x| *x as i64));
+                    mask_array.extend(encoding.get_attention_mask().iter().map(|x| *x as i64));
+                    typeids_array.extend(encoding.get_type_ids().iter().map(|x| *x as i64));
+                });
+
+                let inputs_ids_array =
+                    Array::from_shape_vec((batch_size, encoding_length), ids_array)?;
+
+                let attention_mask_array =
+                    Array::from_shape_vec((batch_size, encoding_length), mask_array)?;
+
+                let token_type_ids_array =
+                    Array::from_shape_vec((batch_size, encoding_length), typeids_array)?;
+
+                let mut session_inputs = ort::inputs![
+                    "input_ids" => Value::from_array(inputs_ids_array)?,
+                    "attention_mask" => Value::from_array(attention_mask_array.view())?,
+                ]?;
+                if self.need_token_type_ids {
+                    session_inputs
+                        .insert("token_type_ids", Value::from_array(token_type_ids_array)?);
+                }
+
+                let outputs = self
+                    .session
+                    .run(session_inputs)
+                    .with_context(inference_error)?;
+
+                // The model outputs one logit per vocabulary entry for every token
+                let output_data = outputs
+                    .get("output")
+                    .context("The model has no \"output\" output")?
+                    .extract_tensor::<f32>()?;
+                let output_view = output_data.view();
+                let logits = output_view.view().into_dimensionality::<Ix3>()?;
+
+                Ok(splade_pool(&logits, &attention_mask_array.view()))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Ok(output)
+    }
+}
+
+impl Embed for SparseTextEmbedding {
+    type Output = SparseEmbedding;
+
+    fn embed(
+        &self,
+        texts: Vec<&str>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<SparseEmbedding>, FastEmbedError> {
+        SparseTextEmbedding::embed(self, texts, batch_size)
+    }
+}
+
+/// Turn the SPLADE logits into sparse vectors
+///
+/// The term weights are log(1 + ReLU(logit)), max pooled over the non-padding tokens.
+/// Only the vocabulary entries with a positive weight are kept
+pub(crate) fn splade_pool(
+    logits: &ArrayView3<f32>,
+    attention_mask: &ArrayView2<i64>,
+) -> Vec<SparseEmbedding> {
+    let mask = attention_mask.mapv(|x| x as f32).insert_axis(Axis(2));
+    let weights = logits.mapv(|x| x.max(0.0).ln_1p()) * &mask;
+
+    weights
+        .fold_axis(Axis(1), 0.0f32, |max, &weight| max.max(weight))
+        .rows()
+        .into_iter()
+        .map(|row| {
+            let (indices, values) = row
+                .iter()
+                .enumerate()
+                .filter(|(_, &weight)| weight > 0.0)
+                .map(|(index, &weight)| (index as u32, weight))
+                .unzip();
+            SparseEmbedding { indices, values }
+        })
+        .collect()
+}
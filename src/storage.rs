@@ -0,0 +1,98 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    common::Embedding,
+    error::{invalid_input, FastEmbedError},
+};
+
+/// Size of the header, holding the dimension and the number of embeddings as u64
+const HEADER_SIZE: usize = 16;
+
+/// Save the embeddings to a binary file
+///
+/// The file starts with the dimension and the number of embeddings, as little-endian u64,
+/// followed by the values of every embedding as little-endian f32. Empty embeddings are rejected
+pub fn save_embeddings(embeddings: &[Embedding], path: &Path) -> Result<(), FastEmbedError> {
+    let dim = embeddings.first().map_or(0, Vec::len);
+    if dim == 0 && !embeddings.is_empty() {
+        return Err(invalid_input("Cannot save embeddings of 0 dimensions"));
+    }
+    if let Some(index) = embeddings
+        .iter()
+        .position(|embedding| embedding.len() != dim)
+    {
+        return Err(invalid_input(format!(
+            "Embedding {} has {} dimensions, expected {} like the first embedding",
+            index,
+            embeddings[index].len(),
+            dim
+        )));
+    }
+
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&(dim as u64).to_le_bytes())?;
+    writer.write_all(&(embeddings.len() as u64).to_le_bytes())?;
+    for value in embeddings.iter().flatten() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Load the embeddings saved by [save_embeddings](crate::save_embeddings)
+pub fn load_embeddings(path: &Path) -> Result<Vec<Embedding>, FastEmbedError> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if bytes.len() < HEADER_SIZE {
+        return Err(invalid_input(format!(
+            "{} is too short to hold the embeddings header",
+            path.display()
+        )));
+    }
+
+    let (header, data) = bytes.split_at(HEADER_SIZE);
+    let dim = u64::from_le_bytes(header[..8].try_into().unwrap()) as usize;
+    let count = u64::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+    // Any number of empty embeddings would match the size of the data
+    if dim == 0 && count > 0 {
+        return Err(invalid_input(format!(
+            "Invalid header in {}, {} embeddings of 0 dimensions",
+            path.display(),
+            count
+        )));
+    }
+
+    let expected_size = dim
+        .checked_mul(count)
+        .and_then(|values| values.checked_mul(std::mem::size_of::<f32>()))
+        .ok_or_else(|| invalid_input(format!("Invalid header in {}", path.display())))?;
+    if data.len() != expected_size {
+        return Err(invalid_input(format!(
+            "{} holds {} bytes of embeddings, expected {} for {} embeddings of {} dimensions",
+            path.display(),
+            data.len(),
+            expected_size,
+            count,
+            dim
+        )));
+    }
+
+    let values: Vec<f32> = data
+        .chunks_exact(std::mem::size_of::<f32>())
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+
+    // chunks panics on a zero size, there are no embeddings in this case
+    if dim == 0 {
+        return Ok(Vec::new());
+    }
+    Ok(values.chunks(dim).map(<[f32]>::to_vec).collect())
+}
@@ -1,11 +1,40 @@
-use std::path::Path;
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
+use ndarray::{array, s, Array2, Array3};
+use ort::ExecutionProvider;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
-    read_file_to_bytes, EmbeddingModel, InitOptions, InitOptionsUserDefined, TextEmbedding,
-    TokenizerFiles, UserDefinedEmbeddingModel, DEFAULT_CACHE_DIR,
+    common::{
+        alternate_model_file, decompress_model, hub_api, is_transient, model_max_length, normalize,
+        resolve_hf_token, retrieve_model, user_agent_field, verify_sha256, with_max_length,
+        HubConfig, RetryPolicy, DEFAULT_MAX_LENGTH, MAX_MODEL_MAX_LENGTH,
+    },
+    cosine_similarity, default_cache_dir, dot, euclidean_distance, load_embeddings, pooling,
+    read_file_to_bytes, save_embeddings,
+    sparse_text_embedding::splade_pool,
+    text_embedding::format_instruction,
+    text_embedding::length_sorted_batches,
+    text_embedding::token_budget_batches,
+    text_embedding::{halve_on_oom, is_out_of_memory, verify_model_file, BatchLimiter},
+    top_k, top_k_by_metric, Activation, DownloadProgress, Embed, EmbedScratch, Embedding,
+    EmbeddingModel, ExecutionProviderChain, FastEmbedError, InitOptions, InitOptionsUserDefined,
+    Metric, ModelManager, ModelQuantization, Norm, OnEmptyText, OnTruncation, OptimizationLevel,
+    Padding, Pooling, ProgressCallback, RerankInitOptions, SparseEmbedding, SparseInitOptions,
+    SparseModel, SparseTextEmbedding, SpecialTokenIds, TensorElementType, TextEmbedding,
+    TextRerank, TokenFilter, TokenizerFiles, TruncationDirection, UserDefinedEmbeddingModel,
+    UserDefinedEmbeddingModelFiles, ValueType,
 };
+#[cfg(feature = "cuda")]
+use crate::{ExecutionProviderDispatch, FallbackCallback};
 
 #[test]
 fn test_embeddings() {
@@ -35,6 +64,27 @@ fn test_embeddings() {
         });
 }
 
+/// Get the snapshot directory of a model in the default cache, which must already be downloaded
+fn cached_model_dir(model: &EmbeddingModel) -> PathBuf {
+    // Get the directory of the model
+    let model_name = TextEmbedding::get_model_info(model)
+        .model_code
+        .replace('/', "--");
+    let model_dir = default_cache_dir().join(format!("models--{}", model_name));
+
+    // Find the "snapshots" sub-directory
+    let snapshots_dir = model_dir.join("snapshots");
+
+    // Get the first sub-directory in snapshots
+    snapshots_dir
+        .read_dir()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path()
+}
+
 #[test]
 
 fn test_user_defined_embedding_model() {
@@ -47,21 +97,7 @@ fn test_user_defined_embedding_model() {
     })
     .unwrap();
 
-    // Get the directory of the model
-    let model_name = test_model_info.model_code.replace('/', "--");
-    let model_dir = Path::new(DEFAULT_CACHE_DIR).join(format!("models--{}", model_name));
-
-    // Find the "snapshots" sub-directory
-    let snapshots_dir = model_dir.join("snapshots");
-
-    // Get the first sub-directory in snapshots
-    let model_files_dir = snapshots_dir
-        .read_dir()
-        .unwrap()
-        .next()
-        .unwrap()
-        .unwrap()
-        .path();
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
 
     // FInd the onnx file - it will be any file ending with .onnx
     let onnx_file = read_file_to_bytes(
@@ -127,3 +163,3698 @@ fn test_user_defined_embedding_model() {
         assert_eq!(embedding.len(), test_model_info.dim);
     }
 }
+
+#[test]
+fn test_mean_pooling() {
+    let cls_model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        pooling: Pooling::Cls,
+        ..Default::default()
+    })
+    .unwrap();
+    let mean_model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        pooling: Pooling::Mean,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let documents = vec![
+        "Hello, World!",
+        "This is a much longer passage, which forces the first one to be padded.",
+    ];
+
+    let cls_embeddings = cls_model.embed(documents.clone(), None).unwrap();
+    let mean_embeddings = mean_model.embed(documents.clone(), None).unwrap();
+    assert_ne!(cls_embeddings[0], mean_embeddings[0]);
+
+    // The padded sequence must pool to the same vector as when it is embedded on its own
+    let unpadded = mean_model.embed(vec![documents[0]], None).unwrap();
+    for (padded, single) in mean_embeddings[0].iter().zip(unpadded[0].iter()) {
+        assert!((padded - single).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn test_mean_pooling_ignores_padding() {
+    // One sequence of two real tokens followed by a padding token with a large value
+    let token_embeddings =
+        Array3::from_shape_vec((1, 3, 2), vec![1.0, 2.0, 3.0, 4.0, 100.0, 100.0]).unwrap();
+    let attention_mask = array![[1_i64, 1, 0]];
+
+    let pooled = pooling::mean(&token_embeddings.view(), &attention_mask.view());
+    assert_eq!(pooled, array![[2.0, 3.0]]);
+
+    let pooled = pooling::cls(&token_embeddings.view());
+    assert_eq!(pooled, array![[1.0, 2.0]]);
+}
+
+#[test]
+fn test_last_token_pooling() {
+    let token_embeddings = Array3::from_shape_vec(
+        (3, 3, 2),
+        vec![
+            1.0, 2.0, 3.0, 4.0, 100.0, 100.0, //
+            5.0, 6.0, 7.0, 8.0, 9.0, 10.0, //
+            100.0, 100.0, 11.0, 12.0, 13.0, 14.0,
+        ],
+    )
+    .unwrap();
+    // Right padding, no padding and left padding
+    let attention_mask = array![[1_i64, 1, 0], [1, 1, 1], [0, 1, 1]];
+
+    let pooled = pooling::last_token(&token_embeddings.view(), &attention_mask.view());
+    assert_eq!(pooled, array![[3.0, 4.0], [9.0, 10.0], [13.0, 14.0]]);
+}
+
+#[test]
+fn test_max_pooling() {
+    // The padding token holds large values, which must not be selected
+    let token_embeddings = Array3::from_shape_vec(
+        (2, 3, 2),
+        vec![
+            1.0, -5.0, 3.0, -2.0, 100.0, 100.0, //
+            -1.0, -4.0, -3.0, 7.0, 0.5, -8.0,
+        ],
+    )
+    .unwrap();
+    let attention_mask = array![[1_i64, 1, 0], [1, 1, 1]];
+
+    let pooled = pooling::max(&token_embeddings.view(), &attention_mask.view());
+    assert_eq!(pooled, array![[3.0, -2.0], [0.5, 7.0]]);
+
+    // A fully masked sequence pools to zeros
+    let pooled = pooling::max(
+        &token_embeddings.view(),
+        &array![[0_i64, 0, 0], [1, 0, 0]].view(),
+    );
+    assert_eq!(pooled, array![[0.0, 0.0], [-1.0, -4.0]]);
+}
+
+#[test]
+fn test_activation() {
+    // Large logits, whose exponentials overflow without the maximum subtracted
+    let logits = array![[1.0_f32, 2.0, 3.0], [1000.0, 1000.0, 0.0]];
+
+    let mut probabilities = logits.clone();
+    pooling::activate(Activation::Softmax, &mut probabilities);
+    for row in probabilities.rows() {
+        assert!((row.sum() - 1.0).abs() < 1e-6);
+    }
+    assert!(probabilities[[0, 0]] < probabilities[[0, 1]]);
+    assert!(probabilities[[0, 1]] < probabilities[[0, 2]]);
+    assert!((probabilities[[1, 0]] - 0.5).abs() < 1e-6);
+    assert_eq!(probabilities[[1, 2]], 0.0);
+
+    let mut sigmoid = array![[0.0_f32, 100.0, -100.0]];
+    pooling::activate(Activation::Sigmoid, &mut sigmoid);
+    assert_eq!(sigmoid, array![[0.5, 1.0, 0.0]]);
+
+    let mut unchanged = logits.clone();
+    pooling::activate(Activation::None, &mut unchanged);
+    assert_eq!(unchanged, logits);
+}
+
+#[test]
+fn test_embeddings_without_normalization() {
+    let model = TextEmbedding::try_new(InitOptions {
+        normalize: Norm::None,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let documents = vec!["Hello, World!"];
+
+    let raw = model.embed(documents.clone(), None).unwrap();
+    assert_eq!(raw, model.embed_raw(documents.clone(), None).unwrap());
+
+    let norm = raw[0].iter().map(|x| x * x).sum::<f32>().sqrt();
+    assert!((norm - 1.0).abs() > 1e-3);
+
+    let normalized_model = TextEmbedding::try_new(Default::default()).unwrap();
+    let normalized = normalized_model.embed(documents, None).unwrap();
+    let mut expected_embedding = raw[0].clone();
+    normalize(&mut expected_embedding);
+    for (expected, actual) in expected_embedding.iter().zip(normalized[0].iter()) {
+        assert!((expected - actual).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_matryoshka_output_dimension() {
+    let model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::NomicEmbedTextV15,
+        output_dimension: Some(256),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let embeddings = model
+        .embed(vec!["search_query: Hello, World!"], None)
+        .unwrap();
+    assert_eq!(embeddings[0].len(), 256);
+
+    let norm = embeddings[0].iter().map(|x| x * x).sum::<f32>().sqrt();
+    assert!((norm - 1.0).abs() < 1e-5);
+
+    // BGESmallENV15 only has 384 dimensions
+    let result = TextEmbedding::try_new(InitOptions {
+        output_dimension: Some(1024),
+        ..Default::default()
+    });
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_embed_async() {
+    let model = std::sync::Arc::new(TextEmbedding::try_new(Default::default()).unwrap());
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let model = model.clone();
+            let documents = documents.clone();
+            tokio::spawn(async move { model.embed_async(documents, None).await })
+        })
+        .collect();
+
+    let expected = model.embed(documents.clone(), None).unwrap();
+    for handle in handles {
+        let embeddings = handle.await.unwrap().unwrap();
+        assert_eq!(embeddings, expected);
+    }
+}
+
+#[test]
+fn test_init_options_builder() {
+    let built = InitOptions::builder()
+        .model_name(EmbeddingModel::AllMiniLML6V2)
+        .max_length(128)
+        .cache_dir(PathBuf::from("custom_cache"))
+        .execution_providers(vec![])
+        .show_download_progress(false)
+        .pooling(Pooling::Mean)
+        .build();
+
+    let literal = InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        max_length: 128,
+        cache_dir: PathBuf::from("custom_cache"),
+        execution_providers: vec![],
+        show_download_progress: false,
+        pooling: Pooling::Mean,
+        ..Default::default()
+    };
+
+    assert_eq!(format!("{:?}", built), format!("{:?}", literal));
+    assert_eq!(
+        format!("{:?}", InitOptions::builder().build()),
+        format!("{:?}", InitOptions::default())
+    );
+}
+
+#[test]
+fn test_intra_threads() {
+    let model = TextEmbedding::try_new(InitOptions {
+        threads: Some(1),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let embeddings = model.embed(vec!["Hello, World!"], None).unwrap();
+    assert_eq!(embeddings[0].len(), 384);
+
+    assert!(TextEmbedding::try_new(InitOptions {
+        threads: Some(0),
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn test_inter_threads() {
+    let model = TextEmbedding::try_new(InitOptions {
+        inter_threads: Some(2),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let embeddings = model.embed(vec!["Hello, World!"], None).unwrap();
+    assert_eq!(embeddings[0].len(), 384);
+}
+
+#[test]
+fn test_embed_tokenizer_error() {
+    TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
+
+    // A word level tokenizer whose unknown token is missing from the vocabulary
+    // fails to encode any word it doesn't know
+    let tokenizer_file = br#"{
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": {"type": "Whitespace"},
+        "post_processor": null,
+        "decoder": null,
+        "model": {"type": "WordLevel", "vocab": {"[PAD]": 0, "hello": 1}, "unk_token": "[UNK]"}
+    }"#;
+
+    let user_defined_model = UserDefinedEmbeddingModel {
+        onnx_file: read_file_to_bytes(&model_files_dir.join("model.onnx")).unwrap(),
+        tokenizer_files: TokenizerFiles {
+            tokenizer_file: tokenizer_file.to_vec(),
+            config_file: b"{}".to_vec(),
+            special_tokens_map_file: b"{}".to_vec(),
+            tokenizer_config_file: br#"{"model_max_length": 512, "pad_token": "[PAD]"}"#.to_vec(),
+        },
+    };
+    let model = TextEmbedding::try_new_from_user_defined(
+        user_defined_model,
+        InitOptionsUserDefined::default(),
+    )
+    .unwrap();
+
+    assert!(model.embed(vec!["hello", "unknown words"], None).is_err());
+    assert!(model.embed(vec!["hello"], Some(0)).is_err());
+}
+
+#[test]
+fn test_missing_model_file_error() {
+    // The cache directory is a regular file, so nothing can ever be stored in it
+    let cache_dir = std::env::temp_dir().join("fastembed_missing_model_file_cache");
+    std::fs::write(&cache_dir, b"").unwrap();
+
+    let result = TextEmbedding::try_new(InitOptions {
+        cache_dir: cache_dir.clone(),
+        ..Default::default()
+    });
+
+    let error = result
+        .err()
+        .expect("Expected an error for an unusable cache");
+    assert!(error.to_string().contains("onnx/model.onnx"));
+
+    std::fs::remove_file(cache_dir).unwrap();
+}
+
+#[test]
+fn test_offline_mode() {
+    // Populate the default cache first
+    TextEmbedding::try_new(Default::default()).unwrap();
+
+    let model = TextEmbedding::try_new(InitOptions {
+        offline: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let embeddings = model.embed(vec!["Hello, World!"], None).unwrap();
+    assert_eq!(embeddings[0].len(), 384);
+
+    let empty_cache_dir = std::env::temp_dir().join("fastembed_empty_offline_cache");
+    std::fs::create_dir_all(&empty_cache_dir).unwrap();
+
+    let result = TextEmbedding::try_new(InitOptions {
+        offline: true,
+        cache_dir: empty_cache_dir,
+        ..Default::default()
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_hf_token() {
+    // The environment is passed in rather than set, as the other tests download models in parallel
+    let env_token = || Some("env_token".to_string());
+    assert_eq!(
+        resolve_hf_token(Some("explicit_token".to_string()), env_token()),
+        Some("explicit_token".to_string())
+    );
+    assert_eq!(resolve_hf_token(None, env_token()), env_token());
+    assert_eq!(resolve_hf_token(None, Some(String::new())), None);
+    assert_eq!(resolve_hf_token(None, None), None);
+
+    let options = InitOptions::builder()
+        .hf_token("secret_token".to_string())
+        .build();
+    assert!(!format!("{:?}", options).contains("secret_token"));
+}
+
+#[test]
+fn test_custom_endpoint() {
+    let api = hub_api(
+        default_cache_dir(),
+        false,
+        HubConfig {
+            endpoint: Some("https://hf-mirror.com".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let url = api
+        .model(EmbeddingModel::BGESmallENV15.to_string())
+        .url("config.json");
+    assert_eq!(
+        url,
+        "https://hf-mirror.com/Xenova/bge-small-en-v1.5/resolve/main/config.json"
+    );
+
+    let api = hub_api(default_cache_dir(), false, HubConfig::default()).unwrap();
+    let url = api
+        .model(EmbeddingModel::BGESmallENV15.to_string())
+        .url("config.json");
+    assert!(url.starts_with("https://huggingface.co/"));
+}
+
+#[test]
+fn test_user_agent() {
+    assert_eq!(
+        user_agent_field(None),
+        (
+            "fastembed".to_string(),
+            env!("CARGO_PKG_VERSION").to_string()
+        )
+    );
+    assert_eq!(
+        user_agent_field(Some("my-app/1.2.0")),
+        ("my-app".to_string(), "1.2.0".to_string())
+    );
+    assert_eq!(
+        user_agent_field(Some("my-app")),
+        ("my-app".to_string(), "unknown".to_string())
+    );
+
+    let options = InitOptions::builder()
+        .user_agent("my-app/1.2.0".to_string())
+        .build();
+    assert_eq!(options.user_agent.as_deref(), Some("my-app/1.2.0"));
+    assert!(hub_api(
+        default_cache_dir(),
+        false,
+        HubConfig {
+            user_agent: options.user_agent,
+            ..Default::default()
+        },
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_model_info_dim() {
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::BGESmallENV15);
+    assert_eq!(model_info.dim, 384);
+
+    let model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::BGESmallENV15,
+        ..Default::default()
+    })
+    .unwrap();
+    let embeddings = model.embed(vec!["Hello, World!"], None).unwrap();
+    assert_eq!(embeddings[0].len(), model_info.dim);
+}
+
+#[test]
+fn test_similarity_functions() {
+    let a = vec![1.0, 0.0, 0.0];
+    let b = vec![0.0, 1.0, 0.0];
+    let c = vec![2.0, 0.0, 0.0];
+
+    assert_eq!(dot(&a, &b), 0.0);
+    assert_eq!(dot(&a, &c), 2.0);
+
+    assert_eq!(cosine_similarity(&a, &b), 0.0);
+    assert!((cosine_similarity(&a, &c) - 1.0).abs() < 1e-6);
+    assert_eq!(cosine_similarity(&a, &[0.0, 0.0, 0.0]), 0.0);
+
+    let corpus = vec![b.clone(), c.clone(), vec![1.0, 1.0, 0.0]];
+    let results = top_k(&a, &corpus, 2);
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].0, 1);
+    assert!((results[0].1 - 1.0).abs() < 1e-6);
+    assert_eq!(results[1].0, 2);
+    assert!((results[1].1 - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+}
+
+#[test]
+fn test_top_k_by_metric() {
+    let query = vec![1.0, 0.0, 0.0];
+    let corpus = vec![
+        vec![0.0, 1.0, 0.0],
+        vec![2.0, 0.0, 0.0],
+        vec![1.0, 1.0, 0.0],
+        vec![-1.0, 0.5, 0.0],
+    ];
+
+    // The cosine distance ranks the items in the same order as the cosine similarity
+    let similarities = top_k_by_metric(&query, &corpus, corpus.len(), Metric::CosineSimilarity);
+    let distances = top_k_by_metric(&query, &corpus, corpus.len(), Metric::CosineDistance);
+    let indices =
+        |results: &[(usize, f32)]| results.iter().map(|&(index, _)| index).collect::<Vec<_>>();
+    assert_eq!(indices(&similarities), vec![1, 2, 0, 3]);
+    assert_eq!(indices(&distances), indices(&similarities));
+    for (&(_, similarity), &(_, distance)) in similarities.iter().zip(&distances) {
+        assert!((distance - (1.0 - similarity)).abs() < 1e-6);
+    }
+    // Sorted by ascending distance, which is the reverse order of the descending similarities
+    let mut reversed = distances.clone();
+    reversed.reverse();
+    assert!(reversed.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    assert_eq!(top_k(&query, &corpus, 2), similarities[..2]);
+
+    // The L2 distance and the dot product take the norms into account
+    let l2 = top_k_by_metric(&query, &corpus, 2, Metric::EuclideanL2);
+    assert_eq!(indices(&l2), vec![1, 2]);
+    assert!((l2[0].1 - 1.0).abs() < 1e-6);
+    assert_eq!(euclidean_distance(&query, &corpus[0]), 2.0f32.sqrt());
+    let dot_products = top_k_by_metric(&query, &corpus, 1, Metric::DotProduct);
+    assert_eq!(dot_products, vec![(1, 2.0)]);
+}
+
+#[test]
+fn test_fixed_padding() {
+    let model = TextEmbedding::try_new(InitOptions {
+        max_length: 64,
+        padding: Padding::Fixed,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let encodings = model
+        .tokenizer
+        .encode_batch(vec!["Hello", "This is a slightly longer passage."], true)
+        .unwrap();
+    for encoding in encodings {
+        assert_eq!(encoding.len(), 64);
+    }
+
+    let embeddings = model.embed(vec!["Hello"], None).unwrap();
+    assert_eq!(embeddings[0].len(), 384);
+}
+
+#[test]
+fn test_truncation_direction() {
+    let text = "one two three four five six seven eight nine ten eleven twelve";
+
+    let full_model = TextEmbedding::try_new(Default::default()).unwrap();
+    let full_ids = full_model
+        .tokenizer
+        .encode(text, true)
+        .unwrap()
+        .get_ids()
+        .to_vec();
+
+    let encode_truncated = |truncation_direction| {
+        let model = TextEmbedding::try_new(InitOptions {
+            max_length: 8,
+            truncation_direction,
+            ..Default::default()
+        })
+        .unwrap();
+        model
+            .tokenizer
+            .encode(text, true)
+            .unwrap()
+            .get_ids()
+            .to_vec()
+    };
+    let right_ids = encode_truncated(TruncationDirection::Right);
+    let left_ids = encode_truncated(TruncationDirection::Left);
+
+    assert_eq!(right_ids.len(), 8);
+    assert_eq!(left_ids.len(), 8);
+    assert_ne!(right_ids, left_ids);
+
+    // Ignoring the [CLS] and [SEP] tokens, right truncation keeps the start and left truncation keeps the end
+    assert_eq!(right_ids[1..7], full_ids[1..7]);
+    assert_eq!(
+        left_ids[1..7],
+        full_ids[full_ids.len() - 7..full_ids.len() - 1]
+    );
+}
+
+#[test]
+fn test_sparse_embeddings() {
+    let model = SparseTextEmbedding::try_new(SparseInitOptions::default()).unwrap();
+
+    let documents = vec![
+        "Hello, World!",
+        "This is an example passage.",
+        "fastembed-rs is licensed under Apache-2.0",
+    ];
+    let embeddings = model.embed(documents.clone(), None).unwrap();
+    assert_eq!(embeddings.len(), documents.len());
+
+    let vocab_size = SparseTextEmbedding::get_model_info(&SparseModel::SPLADEPPV1).dim as u32;
+    for embedding in embeddings {
+        assert!(!embedding.indices.is_empty());
+        assert_eq!(embedding.indices.len(), embedding.values.len());
+        assert!(embedding.indices.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(embedding.indices.iter().all(|&index| index < vocab_size));
+        assert!(embedding.values.iter().all(|&value| value > 0.0));
+    }
+}
+
+#[test]
+fn test_splade_pool() {
+    // Two sequences of two tokens over a vocabulary of four entries, the last token of the second one is padding
+    let logits = Array3::from_shape_vec(
+        (2, 2, 4),
+        vec![
+            1.0, -1.0, 0.0, 2.0, //
+            3.0, -2.0, 0.0, 0.5, //
+            -1.0, 0.5, 0.0, 0.0, //
+            9.0, 9.0, 9.0, 9.0,
+        ],
+    )
+    .unwrap();
+    let attention_mask = array![[1_i64, 1], [1, 0]];
+
+    let embeddings = splade_pool(&logits.view(), &attention_mask.view());
+
+    assert_eq!(
+        embeddings,
+        vec![
+            SparseEmbedding {
+                indices: vec![0, 3],
+                values: vec![3.0_f32.ln_1p(), 2.0_f32.ln_1p()],
+            },
+            SparseEmbedding {
+                indices: vec![1],
+                values: vec![0.5_f32.ln_1p()],
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_rerank() {
+    let model = TextRerank::try_new(RerankInitOptions::default()).unwrap();
+
+    let documents = vec!["The giant panda is a bear species endemic to China.", "hi"];
+    let results = model
+        .rerank("what is panda?", documents.clone(), true, None)
+        .unwrap();
+
+    assert_eq!(results.len(), documents.len());
+    assert_eq!(results[0].index, 0);
+    assert_eq!(results[0].document.as_deref(), Some(documents[0]));
+    assert!(results[0].score > results[1].score);
+
+    let results = model
+        .rerank("what is panda?", documents, false, Some(1))
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].index, 0);
+    assert_eq!(results[0].document, None);
+}
+
+#[test]
+fn test_progress_callback() {
+    // Use an empty cache so that the file is actually downloaded
+    let cache_dir = std::env::temp_dir().join("fastembed_test_progress_callback");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    let reports: Arc<Mutex<Vec<DownloadProgress>>> = Default::default();
+    let progress_callback: ProgressCallback = {
+        let reports = Arc::clone(&reports);
+        Arc::new(move |progress| reports.lock().unwrap().push(progress))
+    };
+
+    let model_repo = retrieve_model(
+        EmbeddingModel::AllMiniLML6V2.to_string(),
+        cache_dir.clone(),
+        true,
+        false,
+        HubConfig::default(),
+        Some(progress_callback),
+    )
+    .unwrap();
+    model_repo.get("config.json").unwrap();
+
+    let reports = reports.lock().unwrap();
+    assert!(reports.len() > 1);
+    assert!(reports
+        .iter()
+        .all(|report| report.filename == "config.json"));
+    assert!(reports
+        .windows(2)
+        .all(|pair| pair[0].downloaded <= pair[1].downloaded));
+
+    let last = reports.last().unwrap();
+    assert!(last.total > 0);
+    assert!(reports[0].downloaded < last.downloaded);
+    assert_eq!(last.downloaded, last.total);
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_embed_iter() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let count = 1000;
+    let batch_size = 64;
+    let texts = (0..count).map(|i| format!("This is document number {}", i));
+
+    let mut total = 0;
+    for batch in model.embed_iter(texts, Some(batch_size)) {
+        let batch = batch.unwrap();
+        assert!(!batch.is_empty() && batch.len() <= batch_size);
+        assert!(batch.iter().all(|embedding| embedding.len() == 384));
+        total += batch.len();
+    }
+    assert_eq!(total, count);
+
+    // The streamed embeddings match the ones computed all at once
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let streamed: Vec<_> = model
+        .embed_iter(documents.clone(), Some(1))
+        .collect::<Result<Vec<_>, FastEmbedError>>()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect();
+    assert_eq!(streamed, model.embed(documents, Some(1)).unwrap());
+
+    let mut invalid = model.embed_iter(vec!["Hello, World!"], Some(0));
+    assert!(invalid.next().unwrap().is_err());
+    assert!(invalid.next().is_none());
+}
+
+#[test]
+fn test_query_passage_prefixes() {
+    let e5_info = TextEmbedding::get_model_info(&EmbeddingModel::MultilingualE5Small);
+    assert_eq!(e5_info.query_prefix, "query: ");
+    assert_eq!(e5_info.passage_prefix, "passage: ");
+
+    let e5_model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::MultilingualE5Small,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        e5_model
+            .embed_query(vec!["how much protein should a female eat"], None)
+            .unwrap(),
+        e5_model
+            .embed(vec!["query: how much protein should a female eat"], None)
+            .unwrap()
+    );
+    assert_eq!(
+        e5_model
+            .embed_passage(vec!["The recommended intake is 46 grams per day"], None)
+            .unwrap(),
+        e5_model
+            .embed(
+                vec!["passage: The recommended intake is 46 grams per day"],
+                None
+            )
+            .unwrap()
+    );
+
+    let plain_info = TextEmbedding::get_model_info(&EmbeddingModel::AllMiniLML6V2);
+    assert!(plain_info.query_prefix.is_empty());
+    assert!(plain_info.passage_prefix.is_empty());
+
+    let plain_model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+    let documents = vec!["Hello, World!"];
+    let embeddings = plain_model.embed(documents.clone(), None).unwrap();
+    assert_eq!(
+        plain_model.embed_query(documents.clone(), None).unwrap(),
+        embeddings
+    );
+    assert_eq!(
+        plain_model.embed_passage(documents, None).unwrap(),
+        embeddings
+    );
+}
+
+#[test]
+fn test_embed_one() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let embedding = model.embed_one("x").unwrap();
+    assert_eq!(embedding, model.embed(vec!["x"], None).unwrap()[0]);
+}
+
+/// Get the tokenizer files of a model, downloading them to the default cache if necessary
+fn model_tokenizer_files(model: &EmbeddingModel) -> TokenizerFiles {
+    let model_repo = retrieve_model(
+        model.to_string(),
+        default_cache_dir(),
+        false,
+        false,
+        HubConfig::default(),
+        None,
+    )
+    .unwrap();
+    let read = |filename| read_file_to_bytes(&model_repo.get(filename).unwrap()).unwrap();
+
+    TokenizerFiles {
+        tokenizer_file: read("tokenizer.json"),
+        config_file: read("config.json"),
+        special_tokens_map_file: read("special_tokens_map.json"),
+        tokenizer_config_file: read("tokenizer_config.json"),
+    }
+}
+
+// Minimal protobuf encoding of the ONNX format, to build tiny models for the tests
+// The field numbers come from https://github.com/onnx/onnx/blob/main/onnx/onnx.proto
+
+const ONNX_FLOAT: u64 = 1;
+const ONNX_INT64: u64 = 7;
+
+fn proto_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn proto_int(buffer: &mut Vec<u8>, field: u64, value: i64) {
+    proto_varint(buffer, field << 3);
+    proto_varint(buffer, value as u64);
+}
+
+fn proto_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    proto_varint(buffer, (field << 3) | 2);
+    proto_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Encode a ValueInfoProto, the dimensions are either fixed sizes or symbolic names
+fn onnx_tensor_info(name: &str, elem_type: u64, dims: &[&str]) -> Vec<u8> {
+    let mut shape = Vec::new();
+    for dim in dims {
+        let mut dimension = Vec::new();
+        match dim.parse::<i64>() {
+            Ok(size) => proto_int(&mut dimension, 1, size),
+            Err(_) => proto_bytes(&mut dimension, 2, dim.as_bytes()),
+        }
+        proto_bytes(&mut shape, 1, &dimension);
+    }
+    let mut tensor_type = Vec::new();
+    proto_int(&mut tensor_type, 1, elem_type as i64);
+    proto_bytes(&mut tensor_type, 2, &shape);
+    let mut type_proto = Vec::new();
+    proto_bytes(&mut type_proto, 1, &tensor_type);
+
+    let mut value_info = Vec::new();
+    proto_bytes(&mut value_info, 1, name.as_bytes());
+    proto_bytes(&mut value_info, 2, &type_proto);
+    value_info
+}
+
+/// Encode an integer AttributeProto
+fn onnx_int_attribute(name: &str, value: i64) -> Vec<u8> {
+    let mut attribute = Vec::new();
+    proto_bytes(&mut attribute, 1, name.as_bytes());
+    proto_int(&mut attribute, 3, value);
+    proto_int(&mut attribute, 20, 2);
+    attribute
+}
+
+/// Encode a NodeProto
+fn onnx_node(op_type: &str, inputs: &[&str], outputs: &[&str], attributes: &[Vec<u8>]) -> Vec<u8> {
+    let mut node = Vec::new();
+    for input in inputs {
+        proto_bytes(&mut node, 1, input.as_bytes());
+    }
+    for output in outputs {
+        proto_bytes(&mut node, 2, output.as_bytes());
+    }
+    proto_bytes(&mut node, 4, op_type.as_bytes());
+    for attribute in attributes {
+        proto_bytes(&mut node, 5, attribute);
+    }
+    node
+}
+
+/// Encode a ModelProto holding a single graph, using opset 13
+fn onnx_model(nodes: &[Vec<u8>], inputs: &[Vec<u8>], outputs: &[Vec<u8>]) -> Vec<u8> {
+    let mut graph = Vec::new();
+    for node in nodes {
+        proto_bytes(&mut graph, 1, node);
+    }
+    proto_bytes(&mut graph, 2, b"test");
+    for input in inputs {
+        proto_bytes(&mut graph, 11, input);
+    }
+    for output in outputs {
+        proto_bytes(&mut graph, 12, output);
+    }
+
+    let mut opset = Vec::new();
+    proto_int(&mut opset, 2, 13);
+
+    let mut model = Vec::new();
+    proto_int(&mut model, 1, 7);
+    proto_bytes(&mut model, 7, &graph);
+    proto_bytes(&mut model, 8, &opset);
+    model
+}
+
+/// A model returning the token ids as already pooled embeddings, in an output with the given name
+fn token_ids_model(output_name: &str) -> Vec<u8> {
+    onnx_model(
+        &[onnx_node(
+            "Cast",
+            &["input_ids"],
+            &[output_name],
+            &[onnx_int_attribute("to", ONNX_FLOAT as i64)],
+        )],
+        &[
+            onnx_tensor_info("input_ids", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("attention_mask", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("token_type_ids", ONNX_INT64, &["batch", "sequence"]),
+        ],
+        &[onnx_tensor_info(
+            output_name,
+            ONNX_FLOAT,
+            &["batch", "sequence"],
+        )],
+    )
+}
+
+#[test]
+fn test_output_key() {
+    let text = "Hello, World!";
+    let model_with_output = |output_name: &str, output_key: Option<&str>| {
+        TextEmbedding::try_new_from_user_defined(
+            UserDefinedEmbeddingModel {
+                onnx_file: token_ids_model(output_name),
+                tokenizer_files: model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+            },
+            InitOptionsUserDefined {
+                normalize: Norm::None,
+                output_key: output_key.map(String::from),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    let model = model_with_output("sentence_embedding", Some("sentence_embedding"));
+    let token_ids: Vec<f32> = model
+        .tokenizer
+        .encode(text, true)
+        .unwrap()
+        .get_ids()
+        .iter()
+        .map(|&id| id as f32)
+        .collect();
+
+    // The 2D output is used as is, without pooling
+    assert_eq!(
+        model.embed(vec![text], None).unwrap(),
+        vec![token_ids.clone()]
+    );
+
+    // Without an output key, the first output is used
+    let model = model_with_output("sentence_embedding", None);
+    assert_eq!(model.embed(vec![text], None).unwrap(), vec![token_ids]);
+
+    // A missing output is an error, not a panic
+    let model = model_with_output("output_0", Some("sentence_embedding"));
+    let error = model.embed(vec![text], None).unwrap_err();
+    assert!(error.to_string().contains("sentence_embedding"));
+}
+
+#[test]
+fn test_pooled_2d_output() {
+    let model = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file: token_ids_model("sentence_embedding"),
+            tokenizer_files: model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+        },
+        InitOptionsUserDefined {
+            pooling: Pooling::Mean,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let documents = vec!["Hello, World!", "This is a longer example passage."];
+    let embeddings = model.embed(documents.clone(), None).unwrap();
+
+    // The rows are used directly whatever the pooling, but still normalized
+    let encodings = model.tokenizer.encode_batch(documents, true).unwrap();
+    for (embedding, encoding) in embeddings.iter().zip(encodings) {
+        let mut token_ids: Vec<f32> = encoding.get_ids().iter().map(|&id| id as f32).collect();
+        normalize(&mut token_ids);
+        assert_eq!(embedding, &token_ids);
+    }
+}
+
+#[test]
+fn test_quantization() {
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::BGESmallENV15);
+    assert_eq!(
+        model_info.model_file_for(ModelQuantization::Int8),
+        Some("onnx/model_quantized.onnx")
+    );
+
+    let model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::BGESmallENV15,
+        quantization: ModelQuantization::Int8,
+        ..Default::default()
+    })
+    .unwrap();
+    let embeddings = model.embed(vec!["Hello, World!"], None).unwrap();
+    assert_eq!(embeddings[0].len(), model_info.dim);
+
+    // The models without a quantized variant fail with an explicit error
+    let Err(error) = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        quantization: ModelQuantization::Int8,
+        ..Default::default()
+    }) else {
+        panic!("AllMiniLML6V2 has no quantized variant");
+    };
+    assert!(error.to_string().contains("Int8"));
+}
+
+#[cfg(feature = "cuda")]
+#[test]
+fn test_with_cuda() {
+    let options = InitOptions::default().with_cuda(0);
+    assert!(options
+        .execution_providers
+        .iter()
+        .any(|provider| matches!(provider, ExecutionProviderDispatch::CUDA(_))));
+
+    // The model still loads, falling back to the CPU if CUDA is not available
+    let warnings: Arc<Mutex<Vec<String>>> = Default::default();
+    let fallback_callback: FallbackCallback = {
+        let warnings = Arc::clone(&warnings);
+        Arc::new(move |warning| warnings.lock().unwrap().push(warning.to_string()))
+    };
+    let model = TextEmbedding::try_new(InitOptions {
+        fallback_callback: Some(fallback_callback),
+        ..options
+    })
+    .unwrap();
+    assert_eq!(model.embed(vec!["Hello, World!"], None).unwrap().len(), 1);
+    assert!(warnings
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|warning| warning.contains("CUDAExecutionProvider")));
+}
+
+#[test]
+fn test_active_execution_providers() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    assert_eq!(
+        model.active_execution_providers(),
+        vec!["CPUExecutionProvider".to_string()]
+    );
+
+    let model = TextEmbedding::try_new(InitOptions {
+        execution_providers: vec![ort::CPUExecutionProvider::default().build()],
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        model.active_execution_providers(),
+        vec!["CPUExecutionProvider".to_string()]
+    );
+}
+
+#[test]
+fn test_additional_files() {
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::MultilingualE5Large);
+    assert_eq!(model_info.additional_files, vec!["model.onnx_data"]);
+
+    // Use an empty cache to check that the additional file is downloaded along with the model file
+    let cache_dir = std::env::temp_dir().join("fastembed_test_additional_files");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    let model_code = EmbeddingModel::AllMiniLML6V2.to_string();
+    let model_repo = retrieve_model(
+        model_code.clone(),
+        cache_dir.clone(),
+        false,
+        false,
+        HubConfig::default(),
+        None,
+    )
+    .unwrap();
+    model_repo
+        .get_model_files(
+            "config.json",
+            &["tokenizer_config.json".to_string()],
+            EmbeddingModel::AllMiniLML6V2,
+        )
+        .unwrap();
+
+    let cache = hf_hub::Cache::new(cache_dir.clone()).model(model_code);
+    assert!(cache.get("config.json").is_some());
+    assert!(cache.get("tokenizer_config.json").is_some());
+
+    // A missing additional file is reported by name
+    let error = model_repo
+        .get_model_files(
+            "config.json",
+            &["missing.onnx_data".to_string()],
+            EmbeddingModel::AllMiniLML6V2,
+        )
+        .unwrap_err();
+    assert!(error.to_string().contains("missing.onnx_data"));
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_save_load_embeddings() {
+    let path = std::env::temp_dir().join("fastembed_test_embeddings.bin");
+    let embeddings = vec![vec![0.5, -1.0, 2.25], vec![0.0, 1e-7, f32::MAX]];
+
+    save_embeddings(&embeddings, &path).unwrap();
+    assert_eq!(load_embeddings(&path).unwrap(), embeddings);
+
+    // Truncated files are rejected
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+    assert!(load_embeddings(&path).is_err());
+    std::fs::write(&path, &bytes[..8]).unwrap();
+    assert!(load_embeddings(&path).is_err());
+
+    // The embeddings must all have the same dimension
+    assert!(save_embeddings(&[vec![1.0, 2.0], vec![1.0]], &path).is_err());
+
+    save_embeddings(&[], &path).unwrap();
+    assert!(load_embeddings(&path).unwrap().is_empty());
+
+    // A header of empty embeddings would let any count through
+    assert!(save_embeddings(&[vec![], vec![]], &path).is_err());
+    let mut header = 0u64.to_le_bytes().to_vec();
+    header.extend(u64::MAX.to_le_bytes());
+    std::fs::write(&path, header).unwrap();
+    assert!(load_embeddings(&path).is_err());
+
+    // A header whose size overflows is as invalid as the other ones
+    let mut header = u64::MAX.to_le_bytes().to_vec();
+    header.extend(2u64.to_le_bytes());
+    std::fs::write(&path, header).unwrap();
+    match load_embeddings(&path) {
+        Err(FastEmbedError::InvalidInput(error)) => {
+            assert!(error.to_string().contains("Invalid header"), "{}", error)
+        }
+        result => panic!("Expected an invalid input error, got {:?}", result),
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "half")]
+#[test]
+fn test_embed_f16() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let embeddings = model.embed(documents.clone(), None).unwrap();
+    let embeddings_f16 = model.embed_f16(documents, None).unwrap();
+
+    assert_eq!(embeddings_f16.len(), embeddings.len());
+    for (embedding_f16, embedding) in embeddings_f16.iter().zip(&embeddings) {
+        assert_eq!(embedding_f16.len(), embedding.len());
+        for (value_f16, value) in embedding_f16.iter().zip(embedding) {
+            assert!((value_f16.to_f32() - value).abs() < 1e-3);
+        }
+    }
+}
+
+#[test]
+fn test_token_budget_batches() {
+    let lengths = [4, 100, 5, 3, 90, 6];
+    let batches = token_budget_batches(&lengths, 150);
+
+    // Every text is in exactly one batch
+    let mut indices: Vec<usize> = batches.iter().flatten().copied().collect();
+    indices.sort();
+    assert_eq!(indices, (0..lengths.len()).collect::<Vec<_>>());
+
+    // The padded size of every batch fits in the budget
+    for batch in &batches {
+        let longest = batch.iter().map(|&index| lengths[index]).max().unwrap();
+        assert!(batch.len() * longest <= 150);
+    }
+
+    // The short texts share a batch, the long ones are split
+    assert_eq!(batches, vec![vec![3, 0, 2, 5], vec![4], vec![1]]);
+
+    // A text longer than the budget gets a batch of its own
+    assert_eq!(token_budget_batches(&[500, 2], 200), vec![vec![1], vec![0]]);
+}
+
+#[test]
+fn test_embed_auto_batch() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let long_text = "This is a much longer passage, repeated to use many tokens. ".repeat(20);
+    let documents = vec![
+        "Hi",
+        long_text.as_str(),
+        "Hello, World!",
+        long_text.as_str(),
+        "Short one",
+    ];
+    let embeddings = model.embed_auto_batch(documents.clone(), 512).unwrap();
+    assert_eq!(embeddings.len(), documents.len());
+
+    // The embeddings are in the order of the inputs, whatever the batches they were computed in
+    for (document, embedding) in documents.iter().zip(&embeddings) {
+        let expected = model.embed_one(document).unwrap();
+        assert!(cosine_similarity(embedding, &expected) > 0.9999);
+    }
+
+    assert!(model.embed_auto_batch(documents, 0).is_err());
+}
+
+#[test]
+fn test_length_sorted_batches() {
+    let lengths = [4, 100, 5, 3, 90, 6];
+    assert_eq!(
+        length_sorted_batches(&lengths, 4),
+        vec![vec![3, 0, 2, 5], vec![4, 1]]
+    );
+    assert_eq!(length_sorted_batches(&[], 4), Vec::<Vec<usize>>::new());
+}
+
+#[test]
+fn test_sort_by_length() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let sorted_model = TextEmbedding::try_new(InitOptions {
+        sort_by_length: true,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let long_text = "This is a much longer passage, repeated to use many tokens. ".repeat(20);
+    let documents = vec![
+        long_text.as_str(),
+        "Hi",
+        "Hello, World!",
+        long_text.as_str(),
+        "Short one",
+    ];
+
+    let embeddings = model.embed(documents.clone(), Some(2)).unwrap();
+    let sorted_embeddings = sorted_model.embed(documents.clone(), Some(2)).unwrap();
+    assert_eq!(sorted_embeddings.len(), documents.len());
+
+    // The embeddings are in the order of the inputs, and match the unsorted ones
+    for ((document, embedding), sorted_embedding) in
+        documents.iter().zip(&embeddings).zip(&sorted_embeddings)
+    {
+        let expected = model.embed_one(document).unwrap();
+        assert!(cosine_similarity(sorted_embedding, &expected) > 0.9999);
+        assert!(cosine_similarity(sorted_embedding, embedding) > 0.9999);
+    }
+}
+
+#[test]
+fn test_embed_similarity_matrix() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents = vec![
+        "The cat sits on the mat.",
+        "The weather is sunny and warm today.",
+        "A cat is sitting on the mat.",
+    ];
+    let matrix = model
+        .embed_similarity_matrix(documents.clone(), None)
+        .unwrap();
+    assert_eq!(matrix.len(), documents.len());
+
+    let embeddings = model.embed(documents.clone(), None).unwrap();
+    for (i, row) in matrix.iter().enumerate() {
+        assert_eq!(row.len(), embeddings.len());
+        assert!((row[i] - 1.0).abs() < 1e-4);
+        for (j, similarity) in row.iter().enumerate() {
+            assert!((similarity - matrix[j][i]).abs() < 1e-6);
+            assert!((similarity - cosine_similarity(&embeddings[i], &embeddings[j])).abs() < 1e-4);
+        }
+    }
+
+    // The near-duplicates are the most similar pair
+    assert!(matrix[0][2] > matrix[0][1]);
+    assert!(matrix[0][2] > matrix[1][2]);
+
+    // The skipped texts would leave their row out of the matrix
+    let skipping_model = TextEmbedding::try_new(InitOptions {
+        on_empty_text: OnEmptyText::Skip,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        skipping_model
+            .embed_similarity_matrix(documents.clone(), None)
+            .unwrap(),
+        matrix
+    );
+    match skipping_model.embed_similarity_matrix(vec![documents[0], " "], None) {
+        Err(FastEmbedError::InvalidInput(error)) => {
+            assert!(
+                error.to_string().contains("The text 1 is empty"),
+                "{}",
+                error
+            )
+        }
+        result => panic!("Expected an invalid input error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_user_defined_embedding_model_from_files() {
+    // Constitute the model in order to ensure it's downloaded and cached
+    TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
+    let model_files = UserDefinedEmbeddingModelFiles::from_dir(&model_files_dir);
+
+    let user_defined_model = UserDefinedEmbeddingModel {
+        onnx_file: read_file_to_bytes(&model_files.onnx_file).unwrap(),
+        tokenizer_files: model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+    };
+    let from_bytes = TextEmbedding::try_new_from_user_defined(
+        user_defined_model,
+        InitOptionsUserDefined::default(),
+    )
+    .unwrap();
+    let from_files =
+        TextEmbedding::try_new_from_files(model_files, InitOptionsUserDefined::default()).unwrap();
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    assert_eq!(
+        from_files.embed(documents.clone(), None).unwrap(),
+        from_bytes.embed(documents, None).unwrap()
+    );
+
+    // A missing file is reported with its path
+    let missing_files = UserDefinedEmbeddingModelFiles::from_dir(Path::new("missing"));
+    let error = TextEmbedding::try_new_from_files(missing_files, Default::default())
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("tokenizer.json"));
+}
+
+#[test]
+fn test_tokenize() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let encodings = model.tokenize(vec!["Hello, World!", "Hi"]).unwrap();
+    assert_eq!(encodings.len(), 2);
+
+    let encoding = &encodings[0];
+    assert_eq!(
+        encoding.get_tokens(),
+        ["[CLS]", "hello", ",", "world", "!", "[SEP]"]
+    );
+    assert_eq!(encoding.get_special_tokens_mask(), [1, 0, 0, 0, 0, 1]);
+    assert_eq!(encoding.get_offsets()[1], (0, 5));
+
+    // The shorter text is padded to the length of the batch, like in embed
+    assert_eq!(encodings[1].len(), encoding.len());
+    assert_eq!(encodings[1].get_attention_mask(), [1, 1, 1, 0, 0, 0]);
+}
+
+#[test]
+fn test_on_truncation() {
+    let model_with_policy = |on_truncation| {
+        TextEmbedding::try_new(InitOptions {
+            max_length: 8,
+            on_truncation,
+            ..Default::default()
+        })
+        .unwrap()
+    };
+    // "Hello, World!" is 6 tokens with [CLS] and [SEP], the long text is 14
+    let documents = vec![
+        "Hello, World!",
+        "This text is much longer than the maximum length of eight tokens",
+    ];
+
+    let silent = model_with_policy(OnTruncation::Silent);
+    let embeddings = silent.embed(documents.clone(), None).unwrap();
+    assert_eq!(embeddings.len(), documents.len());
+
+    let truncated = Arc::new(Mutex::new(Vec::new()));
+    let warnings = Arc::clone(&truncated);
+    let warn = model_with_policy(OnTruncation::Warn(Arc::new(move |index, length| {
+        warnings.lock().unwrap().push((index, length));
+    })));
+    assert_eq!(warn.embed(documents.clone(), Some(1)).unwrap(), embeddings);
+    assert_eq!(*truncated.lock().unwrap(), vec![(1, 14)]);
+    assert_eq!(warn.tokenize(vec![documents[1]]).unwrap()[0].len(), 8);
+
+    let error = model_with_policy(OnTruncation::Error);
+    let message = error
+        .embed(documents.clone(), None)
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("1 (14 tokens)"), "{}", message);
+    assert!(message.contains("8 tokens"), "{}", message);
+
+    // The inputs that fit are embedded as usual
+    assert!(error.embed(vec![documents[0]], None).is_ok());
+}
+
+#[test]
+fn test_list_supported_models_filtered() {
+    let small_models: Vec<EmbeddingModel> =
+        TextEmbedding::list_supported_models_filtered(|model_info| model_info.dim <= 384)
+            .into_iter()
+            .map(|model_info| model_info.model)
+            .collect();
+    assert_eq!(
+        small_models,
+        vec![
+            EmbeddingModel::AllMiniLML6V2,
+            EmbeddingModel::AllMiniLML6V2Q,
+            EmbeddingModel::BGESmallENV15,
+            EmbeddingModel::BGESmallENV15Q,
+            EmbeddingModel::ParaphraseMLMiniLML12V2Q,
+            EmbeddingModel::ParaphraseMLMiniLML12V2,
+            EmbeddingModel::MultilingualE5Small,
+        ]
+    );
+
+    let chinese_models = TextEmbedding::list_supported_models_filtered(|model_info| {
+        model_info.languages.iter().any(|language| language == "zh")
+    });
+    assert_eq!(chinese_models.len(), 1);
+    assert_eq!(chinese_models[0].model, EmbeddingModel::BGESmallZHV15);
+
+    // Every model declares its languages
+    assert!(
+        TextEmbedding::list_supported_models_filtered(|model_info| model_info.languages.is_empty())
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_pad_token_override() {
+    // Constitute the model in order to ensure it's downloaded and cached
+    TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
+    let onnx_file = read_file_to_bytes(&model_files_dir.join("model.onnx")).unwrap();
+
+    // Remove the pad token from the tokenizer configuration
+    let mut tokenizer_files = model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2);
+    let mut tokenizer_config: serde_json::Value =
+        serde_json::from_slice(&tokenizer_files.tokenizer_config_file).unwrap();
+    tokenizer_config
+        .as_object_mut()
+        .unwrap()
+        .remove("pad_token");
+    tokenizer_files.tokenizer_config_file = serde_json::to_vec(&tokenizer_config).unwrap();
+
+    let model = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file,
+            tokenizer_files,
+        },
+        InitOptionsUserDefined {
+            pad_token: Some(String::from("[MASK]")),
+            pad_id: Some(103),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let encodings = model.tokenize(vec!["Hello, World!", "Hi"]).unwrap();
+    assert_eq!(&encodings[1].get_ids()[3..], [103, 103, 103]);
+    assert_eq!(
+        &encodings[1].get_tokens()[3..],
+        ["[MASK]", "[MASK]", "[MASK]"]
+    );
+
+    assert_eq!(
+        model
+            .embed(vec!["Hello, World!", "Hi"], None)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn test_missing_model_max_length() {
+    // Constitute the model in order to ensure it's downloaded and cached
+    TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
+    let onnx_file = read_file_to_bytes(&model_files_dir.join("model.onnx")).unwrap();
+
+    // Remove model_max_length from the tokenizer configuration
+    let mut tokenizer_files = model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2);
+    let mut tokenizer_config: serde_json::Value =
+        serde_json::from_slice(&tokenizer_files.tokenizer_config_file).unwrap();
+    tokenizer_config
+        .as_object_mut()
+        .unwrap()
+        .remove("model_max_length");
+    tokenizer_files.tokenizer_config_file = serde_json::to_vec(&tokenizer_config).unwrap();
+
+    let model = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file,
+            tokenizer_files,
+        },
+        InitOptionsUserDefined {
+            max_length: 8,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // The configured max_length is applied
+    let long_text = "This text is much longer than the maximum length of eight tokens";
+    assert_eq!(model.tokenize(vec![long_text]).unwrap()[0].len(), 8);
+    assert_eq!(model.embed(vec![long_text], None).unwrap().len(), 1);
+}
+
+#[test]
+fn test_dedicated_thread_pool() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let pooled_model = TextEmbedding::try_new(InitOptions {
+        threads: Some(2),
+        dedicated_thread_pool: true,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let documents = vec![
+        "Hello, World!",
+        "This is an example passage.",
+        "fastembed-rs is licensed under Apache-2.0",
+        "Some other short text here blah blah blah",
+    ];
+    let embeddings = model.embed(documents.clone(), Some(1)).unwrap();
+    let pooled_embeddings = pooled_model.embed(documents, Some(1)).unwrap();
+
+    assert_eq!(pooled_embeddings.len(), embeddings.len());
+    for (pooled_embedding, embedding) in pooled_embeddings.iter().zip(&embeddings) {
+        assert!(cosine_similarity(pooled_embedding, embedding) > 0.9999);
+    }
+}
+
+#[test]
+fn test_embed_array() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents = vec![
+        "Hello, World!",
+        "This is an example passage.",
+        "fastembed-rs is licensed under Apache-2.0",
+    ];
+    let array = model.embed_array(documents.clone(), Some(2)).unwrap();
+    let embeddings = model.embed(documents.clone(), Some(2)).unwrap();
+
+    assert_eq!(array.shape(), [documents.len(), 384]);
+    for (row, embedding) in array.rows().into_iter().zip(&embeddings) {
+        assert_eq!(row.to_vec(), *embedding);
+    }
+
+    assert_eq!(
+        model.embed_array(Vec::<&str>::new(), None).unwrap().len(),
+        0
+    );
+}
+
+#[test]
+fn test_embed_trait() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let documents = vec!["Hello, World!", "This is an example passage."];
+
+    let dyn_model: &dyn Embed<Output = Embedding> = &model;
+    assert_eq!(
+        dyn_model.embed(documents.clone(), None).unwrap(),
+        model.embed(documents.clone(), None).unwrap()
+    );
+
+    // Sparse models implement the same interface
+    fn embed_all<M: Embed>(model: &M, texts: Vec<&str>) -> Vec<M::Output> {
+        model.embed(texts, None).unwrap()
+    }
+    let sparse_model = SparseTextEmbedding::try_new(Default::default()).unwrap();
+    assert_eq!(
+        embed_all(&sparse_model, documents.clone()),
+        sparse_model.embed(documents, None).unwrap()
+    );
+}
+
+#[test]
+fn test_add_special_tokens() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let model_without_special_tokens = TextEmbedding::try_new(InitOptions {
+        add_special_tokens: false,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let with_special_tokens = model.tokenize(vec!["Hello, World!"]).unwrap();
+    let without_special_tokens = model_without_special_tokens
+        .tokenize(vec!["Hello, World!"])
+        .unwrap();
+    assert_eq!(with_special_tokens[0].len(), 6);
+    assert_eq!(
+        without_special_tokens[0].get_tokens(),
+        ["hello", ",", "world", "!"]
+    );
+
+    let embeddings = model_without_special_tokens
+        .embed(vec!["Hello, World!"], None)
+        .unwrap();
+    assert_eq!(embeddings[0].len(), 384);
+}
+
+#[test]
+fn test_verify_sha256() {
+    let path = std::env::temp_dir().join("fastembed_test_verify_sha256.onnx");
+    std::fs::write(&path, b"abc").unwrap();
+
+    // The well-known SHA-256 of "abc"
+    let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    verify_sha256(&path, sha256).unwrap();
+    verify_sha256(&path, &sha256.to_uppercase()).unwrap();
+
+    // A tampered file is reported with both checksums
+    std::fs::write(&path, b"abd").unwrap();
+    let message = verify_sha256(&path, sha256).unwrap_err().to_string();
+    assert!(message.contains("Checksum mismatch"), "{}", message);
+    assert!(message.contains(sha256), "{}", message);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_verify_model_file() {
+    let path = std::env::temp_dir().join("fastembed_test_verify_model_file.onnx");
+    std::fs::write(&path, b"abc").unwrap();
+    let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    let wrong_sha256 = "0".repeat(64);
+    let mut model_info = TextEmbedding::get_model_info(&EmbeddingModel::BGESmallENV15);
+    let model_file = model_info.model_file.clone();
+    let quantized_file = "onnx/model_quantized.onnx";
+
+    // The file is outside of a cache, without a declared checksum it is left unverified
+    model_info.sha256 = None;
+    model_info.sha256_q8 = None;
+    verify_model_file(&model_info, &model_file, &path).unwrap();
+
+    model_info.sha256 = Some(sha256.to_string());
+    verify_model_file(&model_info, &model_file, &path).unwrap();
+    model_info.sha256 = Some(wrong_sha256.clone());
+    let message = verify_model_file(&model_info, &model_file, &path)
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("Checksum mismatch"), "{}", message);
+
+    // The quantized variant is verified against its own checksum
+    verify_model_file(&model_info, quantized_file, &path).unwrap();
+    model_info.sha256_q8 = Some(wrong_sha256);
+    assert!(verify_model_file(&model_info, quantized_file, &path).is_err());
+    model_info.sha256_q8 = Some(sha256.to_string());
+    verify_model_file(&model_info, quantized_file, &path).unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_verify_checksums() {
+    // The model file is verified against the SHA-256 recorded by the cache, if it has one
+    let model = TextEmbedding::try_new(InitOptions {
+        verify_checksums: true,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(model.embed(vec!["Hello, World!"], None).unwrap().len(), 1);
+}
+
+/// Copy a directory of the cache, recreating its relative symlinks so that they point to the copied blobs
+#[cfg(unix)]
+fn copy_cache_dir(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in from.read_dir().unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type().unwrap();
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path()).unwrap(), target).unwrap();
+        } else if file_type.is_dir() {
+            copy_cache_dir(&entry.path(), &target);
+        } else {
+            std::fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_verify_checksums_tampered_cache() {
+    // Populate the default cache first, then copy the model into a cache of its own
+    TextEmbedding::try_new(Default::default()).unwrap();
+    let model_info = TextEmbedding::get_model_info(&InitOptions::default().model_name);
+    let model_dir = format!("models--{}", model_info.model_code.replace('/', "--"));
+    let cache_dir = std::env::temp_dir().join("fastembed_test_tampered_cache");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    copy_cache_dir(
+        &default_cache_dir().join(&model_dir),
+        &cache_dir.join(&model_dir),
+    );
+
+    let options = || InitOptions {
+        cache_dir: cache_dir.clone(),
+        offline: true,
+        verify_checksums: true,
+        ..Default::default()
+    };
+    TextEmbedding::try_new(options()).unwrap();
+
+    // Append a byte to the blob of the model file
+    let model_file = cache_dir
+        .join(&model_dir)
+        .join("snapshots")
+        .read_dir()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path()
+        .join(&model_info.model_file);
+    let blob = std::fs::canonicalize(model_file).unwrap();
+    let mut bytes = std::fs::read(&blob).unwrap();
+    bytes.push(0);
+    std::fs::write(&blob, bytes).unwrap();
+
+    match TextEmbedding::try_new(options()) {
+        Err(FastEmbedError::Download(error)) => {
+            assert!(error.to_string().contains("Checksum mismatch"), "{}", error)
+        }
+        Err(error) => panic!("Expected a download error, got {:?}", error),
+        Ok(_) => panic!("Expected the tampered model file to be rejected"),
+    }
+
+    std::fs::remove_dir_all(cache_dir).unwrap();
+}
+
+#[test]
+fn test_pooler_output() {
+    let text = "Hello, World!";
+    // The pooler output is the attention mask, to tell it apart from the token ids
+    let pooler_model = onnx_model(
+        &[
+            onnx_node(
+                "Cast",
+                &["input_ids"],
+                &["last_hidden_state"],
+                &[onnx_int_attribute("to", ONNX_FLOAT as i64)],
+            ),
+            onnx_node(
+                "Cast",
+                &["attention_mask"],
+                &["pooler_output"],
+                &[onnx_int_attribute("to", ONNX_FLOAT as i64)],
+            ),
+        ],
+        &[
+            onnx_tensor_info("input_ids", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("attention_mask", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("token_type_ids", ONNX_INT64, &["batch", "sequence"]),
+        ],
+        &[
+            onnx_tensor_info("last_hidden_state", ONNX_FLOAT, &["batch", "sequence"]),
+            onnx_tensor_info("pooler_output", ONNX_FLOAT, &["batch", "sequence"]),
+        ],
+    );
+    let model_with_pooler = |onnx_file: Vec<u8>, has_pooler: bool, pooling: Pooling| {
+        TextEmbedding::try_new_from_user_defined(
+            UserDefinedEmbeddingModel {
+                onnx_file,
+                tokenizer_files: model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+            },
+            InitOptionsUserDefined {
+                normalize: Norm::None,
+                has_pooler,
+                pooling,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    let model = model_with_pooler(pooler_model.clone(), true, Pooling::Cls);
+    let token_ids: Vec<f32> = model
+        .tokenizer
+        .encode(text, true)
+        .unwrap()
+        .get_ids()
+        .iter()
+        .map(|&id| id as f32)
+        .collect();
+    assert_eq!(model.embed_one(text).unwrap(), vec![1.0; token_ids.len()]);
+
+    // The pooler output is only used for CLS pooling of models flagged with a pooler
+    let model = model_with_pooler(pooler_model.clone(), false, Pooling::Cls);
+    assert_eq!(model.embed_one(text).unwrap(), token_ids);
+    let model = model_with_pooler(pooler_model, true, Pooling::Mean);
+    assert_eq!(model.embed_one(text).unwrap(), token_ids);
+
+    // Models without a pooler output fall back to the hidden states
+    let model = model_with_pooler(token_ids_model("last_hidden_state"), true, Pooling::Cls);
+    assert_eq!(model.embed_one(text).unwrap(), token_ids);
+}
+
+#[test]
+fn test_embed_pairs() {
+    // BGE models take token_type_ids as input
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let query = "What is the capital of France?";
+    let document = "Paris is the capital of France.";
+    let embeddings = model
+        .embed_pairs(vec![(query, document), (document, query)], None)
+        .unwrap();
+    assert_eq!(embeddings.len(), 2);
+    assert_eq!(embeddings[0].len(), 384);
+
+    // The second segment is marked by the token type ids
+    let pair_encoding = model.tokenizer.encode((query, document), true).unwrap();
+    let single_encoding = model
+        .tokenizer
+        .encode(format!("{} {}", query, document), true)
+        .unwrap();
+    assert!(pair_encoding.get_type_ids().contains(&1));
+    assert!(!single_encoding.get_type_ids().contains(&1));
+
+    let single_embedding = model.embed_one(format!("{} {}", query, document)).unwrap();
+    assert!(cosine_similarity(&embeddings[0], &single_embedding) < 0.9999);
+
+    assert!(model.embed_pairs(vec![(query, document)], Some(0)).is_err());
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy {
+        retries: 3,
+        backoff: Duration::from_millis(1),
+    };
+
+    let connection_reset = |attempts| {
+        anyhow::Error::new(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            format!("Connection reset on attempt {}", attempts),
+        ))
+    };
+    let status_error = |status| {
+        let response = ureq::Response::new(status, "Status", "").unwrap();
+        anyhow::Error::new(hf_hub::api::sync::ApiError::RequestError(Box::new(
+            ureq::Error::Status(status, response),
+        )))
+        .context("Failed to download onnx/model.onnx")
+    };
+
+    // A fetcher that fails twice, then succeeds
+    let mut attempts = 0;
+    let path = policy
+        .run(|| {
+            attempts += 1;
+            if attempts <= 2 {
+                return Err(connection_reset(attempts));
+            }
+            Ok(PathBuf::from("model.onnx"))
+        })
+        .unwrap();
+    assert_eq!(path, PathBuf::from("model.onnx"));
+    assert_eq!(attempts, 3);
+
+    // The last error is returned once the retries are exhausted
+    let mut attempts = 0;
+    let error = policy
+        .run(|| -> anyhow::Result<()> {
+            attempts += 1;
+            Err(connection_reset(attempts))
+        })
+        .unwrap_err();
+    assert_eq!(attempts, 4);
+    assert_eq!(error.to_string(), "Connection reset on attempt 4");
+
+    // Without retries, the first error is returned
+    let mut attempts = 0;
+    assert!(RetryPolicy::default()
+        .run(|| -> anyhow::Result<()> {
+            attempts += 1;
+            Err(connection_reset(attempts))
+        })
+        .is_err());
+    assert_eq!(attempts, 1);
+
+    // The server errors and the rate limits are retried, the other HTTP errors are not
+    for (status, expected_attempts) in [(500, 4), (503, 4), (429, 4), (404, 1), (401, 1)] {
+        let mut attempts = 0;
+        assert!(policy
+            .run(|| -> anyhow::Result<()> {
+                attempts += 1;
+                Err(status_error(status))
+            })
+            .is_err());
+        assert_eq!(attempts, expected_attempts, "HTTP {}", status);
+    }
+    assert!(!is_transient(&anyhow::anyhow!("Invalid model file")));
+}
+
+#[test]
+fn test_cache_management() {
+    let cache_dir = std::env::temp_dir().join("fastembed_test_cache_management");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    // Mimic the layout of the Hugging Face cache
+    let model_dir = cache_dir.join("models--Xenova--bge-small-en-v1.5");
+    let snapshot_dir = model_dir.join("snapshots").join("0123abcd");
+    std::fs::create_dir_all(&snapshot_dir).unwrap();
+    std::fs::create_dir_all(model_dir.join("blobs")).unwrap();
+    std::fs::write(model_dir.join("blobs").join("0a1b2c"), [0u8; 100]).unwrap();
+    std::fs::write(snapshot_dir.join("config.json"), [0u8; 20]).unwrap();
+    std::fs::write(cache_dir.join("token"), [0u8; 5]).unwrap();
+
+    assert_eq!(TextEmbedding::cache_size(&cache_dir).unwrap(), 125);
+    assert_eq!(
+        TextEmbedding::cached_models(&cache_dir),
+        vec![EmbeddingModel::BGESmallENV15]
+    );
+
+    // Only the model directories are removed
+    TextEmbedding::clear_cache(&cache_dir).unwrap();
+    assert!(!model_dir.exists());
+    assert!(cache_dir.join("token").exists());
+    assert_eq!(TextEmbedding::cache_size(&cache_dir).unwrap(), 5);
+    assert!(TextEmbedding::cached_models(&cache_dir).is_empty());
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+    assert_eq!(TextEmbedding::cache_size(&cache_dir).unwrap(), 0);
+    TextEmbedding::clear_cache(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_format_instruction() {
+    assert_eq!(
+        format_instruction(
+            "Instruct: {instruction}\nQuery: {text}",
+            "Retrieve relevant passages",
+            "What is {text}?"
+        ),
+        "Instruct: Retrieve relevant passages\nQuery: What is {text}?"
+    );
+    assert_eq!(format_instruction("{text}", "Ignored", "Hello"), "Hello");
+}
+
+#[test]
+fn test_embed_with_instruction() {
+    // Only the instruction-tuned models declare a template
+    let instruct_info = TextEmbedding::get_model_info(&EmbeddingModel::MultilingualE5LargeInstruct);
+    assert!(instruct_info.instruction_template.is_some());
+    assert!(
+        TextEmbedding::get_model_info(&EmbeddingModel::BGESmallENV15)
+            .instruction_template
+            .is_none()
+    );
+
+    let model_with_template = |instruction_template| {
+        TextEmbedding::try_new_from_user_defined(
+            UserDefinedEmbeddingModel {
+                onnx_file: token_ids_model("sentence_embedding"),
+                tokenizer_files: model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+            },
+            InitOptionsUserDefined {
+                normalize: Norm::None,
+                instruction_template,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    // The model returns the token ids, which show the text that reached the tokenizer
+    let model = model_with_template(instruct_info.instruction_template);
+    let embeddings = model
+        .embed_with_instruction("Retrieve relevant passages", vec!["Hello, World!"], None)
+        .unwrap();
+    let expected = model
+        .embed_one("Instruct: Retrieve relevant passages\nQuery: Hello, World!")
+        .unwrap();
+    assert_eq!(embeddings, vec![expected]);
+
+    match model_with_template(None).embed_with_instruction("find the answer", vec!["Hello"], None) {
+        Err(FastEmbedError::InvalidInput(error)) => {
+            assert!(
+                error.to_string().contains("no instruction template"),
+                "{}",
+                error
+            )
+        }
+        result => panic!("Expected an invalid input error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_normalize() {
+    // A zero vector is left unchanged instead of being divided by an epsilon
+    let mut zero = vec![0.0_f32; 4];
+    normalize(&mut zero);
+    assert_eq!(zero, vec![0.0; 4]);
+
+    let mut unit = vec![0.0_f32, 1.0, 0.0];
+    normalize(&mut unit);
+    assert_eq!(unit, vec![0.0, 1.0, 0.0]);
+
+    // The squares of these values overflow f32
+    let mut large = vec![3e30_f32, -4e30];
+    normalize(&mut large);
+    assert!((large[0] - 0.6).abs() < 1e-6);
+    assert!((large[1] + 0.8).abs() < 1e-6);
+
+    // The squares of these values underflow f32
+    let mut small = vec![3e-30_f32, 4e-30];
+    normalize(&mut small);
+    assert!((small[0] - 0.6).abs() < 1e-6);
+    assert!((small[1] - 0.8).abs() < 1e-6);
+
+    let mut values = vec![1.0_f32, 2.0, 3.0];
+    normalize(&mut values);
+    let norm = values.iter().map(|x| x * x).sum::<f32>().sqrt();
+    assert!((norm - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_embed_tokens() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents = vec!["Hello, World!", "This is a longer example passage."];
+    let token_batches: Vec<Vec<u32>> = documents
+        .iter()
+        .map(|document| {
+            model
+                .tokenizer
+                .encode(*document, true)
+                .unwrap()
+                .get_ids()
+                .to_vec()
+        })
+        .collect();
+    assert_ne!(token_batches[0].len(), token_batches[1].len());
+
+    let embeddings = model.embed_tokens(token_batches, None).unwrap();
+    let expected = model.embed(documents, None).unwrap();
+    assert_eq!(embeddings.len(), expected.len());
+    for (embedding, expected) in embeddings.iter().zip(&expected) {
+        assert!(cosine_similarity(embedding, expected) > 0.9999);
+    }
+
+    // The sequences longer than max_length are rejected
+    assert!(model.embed_tokens(vec![vec![101; 513]], None).is_err());
+    // And so are the empty ones, rather than reaching the pooling
+    match model.embed_tokens(vec![vec![101, 102], Vec::new()], None) {
+        Err(FastEmbedError::InvalidInput(error)) => {
+            assert!(
+                error.to_string().contains("sequence 1 is empty"),
+                "{}",
+                error
+            )
+        }
+        result => panic!("Expected an invalid input error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_from_session() {
+    // Constitute the model in order to ensure it's downloaded and cached
+    let model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
+
+    let session = ort::Session::builder()
+        .unwrap()
+        .with_intra_threads(1)
+        .unwrap()
+        .with_model_from_file(model_files_dir.join("model.onnx"))
+        .unwrap();
+    let session_model = TextEmbedding::from_session(
+        session,
+        model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+        512,
+    )
+    .unwrap();
+    assert!(session_model.active_execution_providers().is_empty());
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let embeddings = session_model.embed(documents.clone(), None).unwrap();
+    for (embedding, expected) in embeddings.iter().zip(model.embed(documents, None).unwrap()) {
+        assert!(cosine_similarity(embedding, &expected) > 0.9999);
+    }
+}
+
+#[test]
+fn test_embed_with_cancel() {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let processed = Arc::new(Mutex::new(Vec::new()));
+
+    // The truncation warning of the first batch requests the cancellation. With a single
+    // thread, the batches run one after the other, so the following ones are never processed
+    let cancel_after_first_batch = Arc::clone(&cancel);
+    let processed_batches = Arc::clone(&processed);
+    let model = TextEmbedding::try_new(InitOptions {
+        max_length: 4,
+        threads: Some(1),
+        dedicated_thread_pool: true,
+        on_truncation: OnTruncation::Warn(Arc::new(move |index, _| {
+            processed_batches.lock().unwrap().push(index);
+            cancel_after_first_batch.store(true, Ordering::Relaxed);
+        })),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let documents = vec!["This text is longer than four tokens"; 8];
+    let error = model
+        .embed_with_cancel(documents.clone(), Some(1), Arc::clone(&cancel))
+        .unwrap_err();
+    assert!(error.to_string().contains("cancelled"));
+    assert_eq!(*processed.lock().unwrap(), vec![0]);
+
+    // Without cancellation, every text is embedded
+    let never_cancelled = Arc::new(AtomicBool::new(false));
+    let default_model = TextEmbedding::try_new(Default::default()).unwrap();
+    let embeddings = default_model
+        .embed_with_cancel(documents, Some(1), never_cancelled)
+        .unwrap();
+    assert_eq!(embeddings.len(), 8);
+}
+
+#[test]
+fn test_warmup() {
+    let model = TextEmbedding::try_new(InitOptions {
+        warmup: true,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let embeddings = model.embed(documents.clone(), None).unwrap();
+    assert_eq!(embeddings.len(), documents.len());
+
+    // The dummy inference does not change the results
+    let cold_model = TextEmbedding::try_new(Default::default()).unwrap();
+    let expected = cold_model.embed(documents, None).unwrap();
+    for (embedding, expected) in embeddings.iter().zip(&expected) {
+        for (value, expected) in embedding.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn test_max_length() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    assert_eq!(model.max_length(), DEFAULT_MAX_LENGTH);
+
+    // BGESmallENV15 accepts at most 512 tokens
+    let model = TextEmbedding::try_new(InitOptions {
+        max_length: 100_000,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(model.max_length(), 512);
+}
+
+#[test]
+fn test_model_info_prefixes() {
+    // The prefixes are known without downloading the models
+    for model in [
+        EmbeddingModel::MultilingualE5Small,
+        EmbeddingModel::MultilingualE5Base,
+        EmbeddingModel::MultilingualE5Large,
+    ] {
+        let model_info = TextEmbedding::get_model_info(&model);
+        assert!(!model_info.query_prefix.is_empty(), "{}", model);
+        assert!(!model_info.passage_prefix.is_empty(), "{}", model);
+    }
+
+    // BGE only instructs the queries, the passages are embedded as is
+    for model in [
+        EmbeddingModel::BGEBaseENV15,
+        EmbeddingModel::BGEBaseENV15Q,
+        EmbeddingModel::BGELargeENV15,
+        EmbeddingModel::BGELargeENV15Q,
+        EmbeddingModel::BGESmallENV15,
+        EmbeddingModel::BGESmallENV15Q,
+        EmbeddingModel::BGESmallZHV15,
+    ] {
+        let model_info = TextEmbedding::get_model_info(&model);
+        assert!(!model_info.query_prefix.is_empty(), "{}", model);
+        assert!(model_info.passage_prefix.is_empty(), "{}", model);
+    }
+
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::BGEBaseENV15);
+    assert_eq!(
+        model_info.query_prefix,
+        "Represent this sentence for searching relevant passages: "
+    );
+
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::ParaphraseMLMpnetBaseV2);
+    assert!(model_info.query_prefix.is_empty());
+    assert!(model_info.passage_prefix.is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::BGESmallENV15);
+    let json = serde_json::to_value(&model_info).unwrap();
+    assert_eq!(json["model"], "Xenova/bge-small-en-v1.5");
+    assert_eq!(json["dim"], 384);
+    assert_eq!(json["model_code"], "Xenova/bge-small-en-v1.5");
+
+    // Every model round trips, including the quantized variants sharing a repository
+    for model_info in TextEmbedding::list_supported_models() {
+        let json = serde_json::to_string(&model_info.model).unwrap();
+        let model: EmbeddingModel = serde_json::from_str(&json).unwrap();
+        assert_eq!(model, model_info.model);
+    }
+    assert!(serde_json::from_str::<EmbeddingModel>("\"unknown/model\"").is_err());
+
+    let embeddings = crate::Embeddings {
+        model: EmbeddingModel::AllMiniLML6V2,
+        embeddings: vec![vec![0.5, -0.25], vec![1.0, 0.0]],
+    };
+    let json = serde_json::to_string(&embeddings).unwrap();
+    assert_eq!(
+        serde_json::from_str::<crate::Embeddings>(&json).unwrap(),
+        embeddings
+    );
+}
+
+#[test]
+fn test_halve_on_oom() {
+    let texts: Vec<usize> = (0..7).collect();
+    let attempts = Mutex::new(Vec::new());
+
+    // The batches of more than 2 texts run out of memory
+    let embeddings: Vec<Embedding> = halve_on_oom(&texts, 0, &|batch: &[usize], offset| {
+        attempts.lock().unwrap().push((offset, batch.len()));
+        if batch.len() > 2 {
+            anyhow::bail!("Failed to allocate memory for requested buffer of size 1073741824");
+        }
+        Ok(batch.iter().map(|&text| vec![text as f32]).collect())
+    })
+    .unwrap();
+    let expected: Vec<Embedding> = (0..7).map(|text| vec![text as f32]).collect();
+    assert_eq!(embeddings, expected);
+    assert_eq!(
+        *attempts.lock().unwrap(),
+        vec![(0, 7), (0, 3), (0, 1), (1, 2), (3, 4), (3, 2), (5, 2)]
+    );
+
+    // The halves of the pooled arrays are concatenated in the same order
+    let array: Array2<f32> = halve_on_oom(&texts, 0, &|batch: &[usize], _| {
+        if batch.len() > 2 {
+            anyhow::bail!("Failed to allocate memory for requested buffer of size 1073741824");
+        }
+        Ok(Array2::from_shape_fn((batch.len(), 1), |(row, _)| {
+            batch[row] as f32
+        }))
+    })
+    .unwrap();
+    assert_eq!(array.column(0).to_vec(), expected.concat());
+
+    // The other errors are not retried
+    let attempts = Mutex::new(0);
+    let error = halve_on_oom::<_, Vec<Embedding>>(&texts, 0, &|_: &[usize], _| {
+        *attempts.lock().unwrap() += 1;
+        anyhow::bail!("Invalid input name: input_ids")
+    })
+    .unwrap_err();
+    assert!(!is_out_of_memory(&error));
+    assert_eq!(*attempts.lock().unwrap(), 1);
+
+    // A single text is not split any further
+    let error = halve_on_oom::<_, Vec<Embedding>>(&texts[..1], 0, &|_: &[usize], _| {
+        anyhow::bail!("CUDA out of memory")
+    })
+    .unwrap_err();
+    assert!(is_out_of_memory(&error));
+}
+
+#[test]
+fn test_adaptive_oom_retry() {
+    let model = TextEmbedding::try_new(InitOptions {
+        adaptive_oom_retry: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let embeddings = model.embed(documents.clone(), None).unwrap();
+
+    let default_model = TextEmbedding::try_new(Default::default()).unwrap();
+    assert_eq!(embeddings, default_model.embed(documents, None).unwrap());
+}
+
+#[test]
+fn test_embed_tokens_output() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    // Texts of different lengths, so that the shorter one is padded in the batch
+    let documents = vec!["Hello, World!", "This is a much longer example passage."];
+    let token_embeddings = model.embed_tokens_output(documents.clone(), None).unwrap();
+    let encodings = model.tokenize(documents.clone()).unwrap();
+    assert_eq!(token_embeddings.len(), documents.len());
+    for (embeddings, encoding) in token_embeddings.iter().zip(&encodings) {
+        let token_count = encoding.get_attention_mask().iter().sum::<u32>() as usize;
+        assert_eq!(embeddings.dim(), (token_count, 384));
+    }
+
+    // The tokens do not depend on the padding of the batch
+    let alone = model.embed_tokens_output(vec![documents[0]], None).unwrap();
+    for (value, expected) in token_embeddings[0].iter().zip(&alone[0]) {
+        assert!((value - expected).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn test_default_cache_dir() {
+    let cache_dir = default_cache_dir();
+    assert!(cache_dir.is_absolute());
+    assert!(cache_dir.starts_with(dirs::cache_dir().unwrap()));
+    assert!(cache_dir.ends_with("fastembed"));
+
+    // The default can still be overridden
+    assert_eq!(InitOptions::default().cache_dir, cache_dir);
+    let options = InitOptions::builder()
+        .cache_dir(PathBuf::from("custom_cache"))
+        .build();
+    assert_eq!(options.cache_dir, PathBuf::from("custom_cache"));
+}
+
+#[test]
+fn test_on_empty_text() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    assert!(model.embed(Vec::<&str>::new(), None).unwrap().is_empty());
+
+    let documents = vec!["Hello, World!", "", "This is an example passage.", "  "];
+    // Embedded one by one, so that the padding does not change the results
+    let embeddings = model.embed(documents.clone(), Some(1)).unwrap();
+    assert!(embeddings.iter().all(|embedding| embedding.len() == 384));
+
+    let skipping_model = TextEmbedding::try_new(InitOptions {
+        on_empty_text: OnEmptyText::Skip,
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(skipping_model
+        .embed(Vec::<&str>::new(), None)
+        .unwrap()
+        .is_empty());
+    let skipped = skipping_model.embed(documents.clone(), Some(1)).unwrap();
+    assert_eq!(skipped.len(), documents.len());
+    assert!(skipped[1].is_empty() && skipped[3].is_empty());
+    assert_eq!(skipped[0], embeddings[0]);
+    assert_eq!(skipped[2], embeddings[2]);
+    // The other embedding methods apply the same policy
+    assert!(skipping_model.embed_one("").unwrap().is_empty());
+    let iterated: Vec<Embedding> = skipping_model
+        .embed_iter(documents.clone(), Some(2))
+        .collect::<Result<Vec<_>, FastEmbedError>>()
+        .unwrap()
+        .concat();
+    assert_eq!(iterated, skipped);
+    let array = skipping_model
+        .embed_array(documents.clone(), Some(1))
+        .unwrap();
+    assert_eq!(array.dim(), (documents.len(), 384));
+    assert!(array.row(1).iter().all(|&value| value == 0.0));
+    assert_eq!(array.row(2).to_vec(), embeddings[2]);
+
+    let failing_model = TextEmbedding::try_new(InitOptions {
+        on_empty_text: OnEmptyText::Error,
+        ..Default::default()
+    })
+    .unwrap();
+    let error = failing_model.embed(documents.clone(), None).unwrap_err();
+    assert!(error.to_string().contains("The text 1 is empty"));
+    let error = failing_model
+        .embed_iter(documents.clone(), Some(2))
+        .find_map(Result::err)
+        .unwrap();
+    assert!(error.to_string().contains("The text 1 is empty"));
+    assert!(failing_model.embed_one("  ").is_err());
+    assert!(failing_model.embed_flat(documents, None).is_err());
+    assert_eq!(
+        failing_model
+            .embed(vec!["Hello, World!"], None)
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_execution_provider_chain() {
+    let chain = ExecutionProviderChain::new()
+        .then(ort::CUDAExecutionProvider::default())
+        .then(ort::CPUExecutionProvider::default());
+    let names: Vec<&str> = chain
+        .providers()
+        .iter()
+        .map(|provider| provider.as_str())
+        .collect();
+    assert_eq!(names, vec!["CUDAExecutionProvider", "CPUExecutionProvider"]);
+
+    let options = InitOptions::builder()
+        .execution_provider_chain(chain)
+        .build();
+    let names: Vec<&str> = options
+        .execution_providers
+        .iter()
+        .map(|provider| provider.as_str())
+        .collect();
+    assert_eq!(names, vec!["CUDAExecutionProvider", "CPUExecutionProvider"]);
+
+    // The CPU is selected when it is the only provider of the chain
+    let model = TextEmbedding::try_new(
+        InitOptions::builder()
+            .execution_provider_chain(
+                ExecutionProviderChain::new().then(ort::CPUExecutionProvider::default()),
+            )
+            .build(),
+    )
+    .unwrap();
+    assert_eq!(
+        model.selected_execution_provider(),
+        Some("CPUExecutionProvider")
+    );
+}
+
+#[test]
+fn test_embed_dedup() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents = vec![
+        "Hello, World!",
+        "This is an example passage.",
+        "Hello, World!",
+        "  Hello,   World! ",
+        "This is an example passage.",
+        "fastembed-rs is licensed under Apache 2.0",
+    ];
+    let embeddings = model.embed_dedup(documents.clone(), None).unwrap();
+    assert_eq!(embeddings.len(), documents.len());
+    assert_eq!(embeddings[0], embeddings[2]);
+    assert_eq!(embeddings[0], embeddings[3]);
+    assert_eq!(embeddings[1], embeddings[4]);
+    assert_ne!(embeddings[0], embeddings[1]);
+    assert_ne!(embeddings[0], embeddings[5]);
+
+    // Every unique text gets the embedding embed computes for it
+    let unique = model
+        .embed(vec![documents[0], documents[1], documents[5]], None)
+        .unwrap();
+    assert_eq!(embeddings[0], unique[0]);
+    assert_eq!(embeddings[1], unique[1]);
+    assert_eq!(embeddings[5], unique[2]);
+
+    assert!(model
+        .embed_dedup(Vec::<&str>::new(), None)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_optimization_level() {
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let expected = TextEmbedding::try_new(Default::default())
+        .unwrap()
+        .embed(documents.clone(), None)
+        .unwrap();
+
+    let model = TextEmbedding::try_new(InitOptions {
+        optimization_level: OptimizationLevel::Level1,
+        ..Default::default()
+    })
+    .unwrap();
+    let embeddings = model.embed(documents, None).unwrap();
+    for (embedding, expected) in embeddings.iter().zip(&expected) {
+        assert_eq!(embedding.len(), 384);
+        for (value, expected) in embedding.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-4);
+        }
+    }
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn test_enable_profiling() {
+    let profiling_file = std::env::temp_dir().join("fastembed_test_profile.json");
+    let model = TextEmbedding::try_new(InitOptions {
+        enable_profiling: Some(profiling_file.clone()),
+        ..Default::default()
+    })
+    .unwrap();
+    model.embed(vec!["Hello, World!"], None).unwrap();
+
+    assert_eq!(model.end_profiling().unwrap(), profiling_file);
+    let profile = std::fs::read_to_string(&profiling_file).unwrap();
+    assert!(!profile.is_empty());
+    assert!(serde_json::from_str::<serde_json::Value>(&profile).is_ok());
+
+    std::fs::remove_file(&profiling_file).unwrap();
+}
+
+#[cfg(not(feature = "profiling"))]
+#[test]
+fn test_enable_profiling_without_feature() {
+    let error = TextEmbedding::try_new(InitOptions {
+        enable_profiling: Some(std::env::temp_dir().join("fastembed_test_profile.json")),
+        ..Default::default()
+    })
+    .err()
+    .expect("Expected an error without the profiling feature");
+    assert!(error
+        .to_string()
+        .contains("requires the `profiling` feature"));
+}
+
+/// A model returning `input_id + position_id` for the attended tokens, as already pooled embeddings
+fn position_ids_model() -> Vec<u8> {
+    let cast_to_float = || vec![onnx_int_attribute("to", ONNX_FLOAT as i64)];
+    onnx_model(
+        &[
+            onnx_node("Cast", &["input_ids"], &["ids"], &cast_to_float()),
+            onnx_node("Cast", &["position_ids"], &["positions"], &cast_to_float()),
+            onnx_node("Cast", &["attention_mask"], &["mask"], &cast_to_float()),
+            onnx_node("Add", &["ids", "positions"], &["sum"], &[]),
+            onnx_node("Mul", &["sum", "mask"], &["embeddings"], &[]),
+        ],
+        &[
+            onnx_tensor_info("input_ids", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("attention_mask", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("position_ids", ONNX_INT64, &["batch", "sequence"]),
+        ],
+        &[onnx_tensor_info(
+            "embeddings",
+            ONNX_FLOAT,
+            &["batch", "sequence"],
+        )],
+    )
+}
+
+#[test]
+fn test_position_ids() {
+    let user_defined_model = UserDefinedEmbeddingModel {
+        onnx_file: position_ids_model(),
+        tokenizer_files: model_tokenizer_files(&EmbeddingModel::BGESmallENV15),
+    };
+    let model = TextEmbedding::try_new_from_user_defined(
+        user_defined_model,
+        InitOptionsUserDefined {
+            normalize: Norm::None,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // The shorter text is padded, the positions of every sequence start at 0
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let embeddings = model.embed(documents.clone(), None).unwrap();
+    let encodings = model.tokenize(documents).unwrap();
+    for (embedding, encoding) in embeddings.iter().zip(&encodings) {
+        let expected: Vec<f32> = encoding
+            .get_ids()
+            .iter()
+            .zip(encoding.get_attention_mask())
+            .enumerate()
+            .map(|(position, (&id, &mask))| ((id as usize + position) * mask as usize) as f32)
+            .collect();
+        assert_eq!(*embedding, expected);
+    }
+}
+
+#[test]
+fn test_batch_limiter() {
+    assert!(BatchLimiter::new(0).is_err());
+
+    let limiter = BatchLimiter::new(2).unwrap();
+    let running = Mutex::new(0);
+    let max_running = Mutex::new(0);
+    (0..16).collect::<Vec<_>>().par_iter().for_each(|_| {
+        limiter.run(|| {
+            let now_running = {
+                let mut running = running.lock().unwrap();
+                *running += 1;
+                *running
+            };
+            let mut max = max_running.lock().unwrap();
+            *max = (*max).max(now_running);
+            drop(max);
+            std::thread::sleep(Duration::from_millis(10));
+            *running.lock().unwrap() -= 1;
+        })
+    });
+    assert!(*max_running.lock().unwrap() <= 2);
+}
+
+#[test]
+fn test_max_concurrent_batches() {
+    let documents = vec![
+        "Hello, World!",
+        "This is an example passage.",
+        "fastembed-rs is licensed under Apache 2.0",
+        "Some other short text here blah blah blah",
+    ];
+    let unbounded = TextEmbedding::try_new(Default::default())
+        .unwrap()
+        .embed(documents.clone(), Some(1))
+        .unwrap();
+
+    let model = TextEmbedding::try_new(InitOptions {
+        max_concurrent_batches: Some(1),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(model.embed(documents.clone(), Some(1)).unwrap(), unbounded);
+    // The other embedding methods run their batches through the same limiter
+    assert_eq!(model.embed_one(documents[0]).unwrap(), unbounded[0]);
+    let (flat, dim) = model.embed_flat(documents, Some(1)).unwrap();
+    assert_eq!(flat, unbounded.concat());
+    assert_eq!(dim, unbounded[0].len());
+
+    assert!(TextEmbedding::try_new(InitOptions {
+        max_concurrent_batches: Some(0),
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn test_estimate_batch_memory() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let documents = vec!["This is an example passage."; 8];
+    let token_count = model.tokenize(vec![documents[0]]).unwrap()[0].len();
+
+    // BGESmallENV15 takes the ids, the mask and the token type ids, and has a hidden size of 384
+    let estimate = model.estimate_batch_memory(&documents, Some(2)).unwrap();
+    assert_eq!(estimate, 2 * token_count * (3 * 8 + 384 * 4));
+
+    // The estimate of the largest batch doubles with the batch size
+    let doubled = model.estimate_batch_memory(&documents, Some(4)).unwrap();
+    assert_eq!(doubled, 2 * estimate);
+    let all = model.estimate_batch_memory(&documents, None).unwrap();
+    assert_eq!(all, 4 * estimate);
+
+    // The longest text of a batch sets its padded length
+    let mixed = vec![
+        "Hi",
+        "This is a much longer example passage than the other one.",
+    ];
+    let lengths: Vec<usize> = model
+        .tokenize(mixed.clone())
+        .unwrap()
+        .iter()
+        .map(|encoding| encoding.len())
+        .collect();
+    assert_eq!(
+        model.estimate_batch_memory(&mixed, Some(1)).unwrap(),
+        lengths[1] * (3 * 8 + 384 * 4)
+    );
+
+    assert_eq!(
+        model
+            .estimate_batch_memory(&Vec::<&str>::new(), None)
+            .unwrap(),
+        0
+    );
+    assert!(model.estimate_batch_memory(&documents, Some(0)).is_err());
+}
+
+#[test]
+fn test_allow_exceed_model_max_length() {
+    // BGESmallENV15 declares a model_max_length of 512
+    let clamped = TextEmbedding::try_new(InitOptions {
+        max_length: 1024,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(clamped.max_length(), 512);
+
+    let model = TextEmbedding::try_new(InitOptions {
+        max_length: 1024,
+        allow_exceed_model_max_length: true,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(model.max_length(), 1024);
+
+    // The inputs within the limit of the model still embed
+    let embeddings = model.embed(vec!["Hello, World!"], None).unwrap();
+    assert_eq!(embeddings[0].len(), 384);
+}
+
+#[test]
+fn test_embed_with() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let items = vec![
+        (7, "Hello, World!"),
+        (3, "This is an example passage."),
+        (42, "fastembed-rs is licensed under Apache 2.0"),
+    ];
+    let texts: Vec<&str> = items.iter().map(|(_, text)| *text).collect();
+    let embeddings = model.embed(texts, Some(2)).unwrap();
+
+    let embedded = model.embed_with(items, Some(2)).unwrap();
+    let ids: Vec<i32> = embedded.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids, vec![7, 3, 42]);
+    for ((_, embedding), expected) in embedded.iter().zip(&embeddings) {
+        assert_eq!(embedding, expected);
+    }
+
+    assert!(model
+        .embed_with(Vec::<(i32, &str)>::new(), None)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_normalize_before_pooling() {
+    let token_embeddings =
+        Array3::from_shape_vec((1, 3, 2), vec![3.0, 4.0, 1.0, 0.0, 9.0, 9.0]).unwrap();
+    // The last token is padding
+    let attention_mask = array![[1_i64, 1, 0]];
+
+    // Pool then normalize: the mean of [3, 4] and [1, 0] is [2, 2]
+    let mut pooled = pooling::mean(&token_embeddings.view(), &attention_mask.view());
+    normalize(pooled.as_slice_mut().unwrap());
+    let expected = [0.5_f32.sqrt(), 0.5_f32.sqrt()];
+    for (value, expected) in pooled.iter().zip(expected) {
+        assert!((value - expected).abs() < 1e-6);
+    }
+
+    // Normalize then pool: the mean of [0.6, 0.8] and [1, 0] is [0.8, 0.4]
+    let normalized_tokens = pooling::normalize_tokens(&token_embeddings.view());
+    assert_eq!(normalized_tokens.slice(s![0, 0, ..]), array![0.6, 0.8]);
+    let mut pooled_normalized = pooling::mean(&normalized_tokens.view(), &attention_mask.view());
+    normalize(pooled_normalized.as_slice_mut().unwrap());
+    let expected = [2.0 / 5.0_f32.sqrt(), 1.0 / 5.0_f32.sqrt()];
+    for (value, expected) in pooled_normalized.iter().zip(expected) {
+        assert!((value - expected).abs() < 1e-6);
+    }
+    assert_ne!(pooled, pooled_normalized);
+
+    // The option changes the embeddings of a mean pooled model
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let options = || InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        pooling: Pooling::Mean,
+        ..Default::default()
+    };
+    let embeddings = TextEmbedding::try_new(options())
+        .unwrap()
+        .embed(documents.clone(), None)
+        .unwrap();
+    let normalized_first = TextEmbedding::try_new(InitOptions {
+        normalize_before_pooling: true,
+        ..options()
+    })
+    .unwrap()
+    .embed(documents, None)
+    .unwrap();
+    assert_ne!(embeddings, normalized_first);
+    for embedding in &normalized_first {
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn test_self_contained_tokenizer_file() {
+    let model = TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
+    let model_files = UserDefinedEmbeddingModelFiles::from_dir(&model_files_dir);
+    let onnx_file = read_file_to_bytes(&model_files.onnx_file).unwrap();
+
+    // Only tokenizer.json is provided, the companion files are left empty
+    let tokenizer_files = TokenizerFiles {
+        tokenizer_file: read_file_to_bytes(&model_files_dir.join("tokenizer.json")).unwrap(),
+        config_file: Vec::new(),
+        special_tokens_map_file: Vec::new(),
+        tokenizer_config_file: Vec::new(),
+    };
+    let self_contained = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file: onnx_file.clone(),
+            tokenizer_files: tokenizer_files.clone(),
+        },
+        InitOptionsUserDefined::default(),
+    )
+    .unwrap();
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let expected = model.embed(documents.clone(), None).unwrap();
+    let embeddings = self_contained.embed(documents, None).unwrap();
+    for (embedding, expected) in embeddings.iter().zip(&expected) {
+        for (value, expected) in embedding.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-5);
+        }
+    }
+
+    // The max_length option still truncates the inputs
+    let truncated = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file,
+            tokenizer_files,
+        },
+        InitOptionsUserDefined {
+            max_length: 4,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let encodings = truncated
+        .tokenize(vec!["This is an example passage."])
+        .unwrap();
+    assert_eq!(encodings[0].len(), 4);
+}
+
+#[test]
+fn test_embed_with_progress() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents: Vec<String> = (0..10).map(|index| format!("Document {}", index)).collect();
+    let mut updates = Vec::new();
+    let embeddings = model
+        .embed_with_progress(documents.clone(), Some(3), |completed, total| {
+            updates.push((completed, total))
+        })
+        .unwrap();
+
+    assert_eq!(embeddings, model.embed(documents, Some(3)).unwrap());
+    assert_eq!(updates, vec![(1, 4), (2, 4), (3, 4), (4, 4)]);
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_image_embedding() {
+    use crate::{image_embedding::ImagePreprocessing, ImageEmbedding, ImageEmbeddingModel};
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    // A red image, not square so that it is both resized and cropped
+    let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 32, Rgb([255, 0, 0])));
+
+    let preprocessing = ImagePreprocessing::default();
+    let pixel_values = preprocessing
+        .pixel_values(std::slice::from_ref(&image))
+        .unwrap();
+    assert_eq!(pixel_values.shape(), &[1, 3, 224, 224]);
+    let red = (1.0 - preprocessing.mean[0]) / preprocessing.std[0];
+    assert!((pixel_values[[0, 0, 112, 112]] - red).abs() < 1e-4);
+
+    let model = ImageEmbedding::try_new(Default::default()).unwrap();
+    let embeddings = model.embed(&[image.clone(), image], None).unwrap();
+
+    let model_info = ImageEmbedding::get_model_info(&ImageEmbeddingModel::ClipVitB32);
+    assert_eq!(embeddings.len(), 2);
+    for embedding in &embeddings {
+        assert_eq!(embedding.len(), model_info.dim);
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn test_special_token_ids() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    assert!(model.vocab_size() > 0);
+
+    // BGESmallENV15 uses the BERT uncased vocabulary
+    assert_eq!(
+        model.special_token_ids(),
+        SpecialTokenIds {
+            pad: Some(0),
+            cls: Some(101),
+            sep: Some(102),
+            unk: Some(100),
+            mask: Some(103),
+        }
+    );
+
+    // The pad id is the one configured when loading the tokenizer
+    let model_files_dir = cached_model_dir(&EmbeddingModel::BGESmallENV15);
+    let model_files = UserDefinedEmbeddingModelFiles::from_dir(&model_files_dir);
+    let user_defined = TextEmbedding::try_new_from_files(
+        model_files,
+        InitOptionsUserDefined {
+            pad_id: Some(7),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(user_defined.vocab_size() > 0);
+    assert_eq!(user_defined.special_token_ids().pad, Some(7));
+}
+
+#[test]
+fn test_tokenizer_normalizer() {
+    TextEmbedding::try_new(InitOptions {
+        model_name: EmbeddingModel::AllMiniLML6V2,
+        ..Default::default()
+    })
+    .unwrap();
+    let model_files_dir = cached_model_dir(&EmbeddingModel::AllMiniLML6V2);
+
+    // A word level tokenizer that lowercases its input, and only knows lowercase words
+    let tokenizer_file = br#"{
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": {"type": "Lowercase"},
+        "pre_tokenizer": {"type": "Whitespace"},
+        "post_processor": null,
+        "decoder": null,
+        "model": {"type": "WordLevel", "vocab": {"[PAD]": 0, "[UNK]": 1, "hello": 2}, "unk_token": "[UNK]"}
+    }"#;
+
+    let user_defined_model = UserDefinedEmbeddingModel {
+        onnx_file: read_file_to_bytes(&model_files_dir.join("model.onnx")).unwrap(),
+        tokenizer_files: TokenizerFiles {
+            tokenizer_file: tokenizer_file.to_vec(),
+            config_file: b"{}".to_vec(),
+            special_tokens_map_file: b"{}".to_vec(),
+            tokenizer_config_file: br#"{"model_max_length": 512, "pad_token": "[PAD]"}"#.to_vec(),
+        },
+    };
+    let model = TextEmbedding::try_new_from_user_defined(
+        user_defined_model,
+        InitOptionsUserDefined {
+            max_length: 8,
+            padding: Padding::Fixed,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // The normalizer is kept along with the padding and truncation
+    let encodings = model.tokenize(vec!["HELLO"]).unwrap();
+    assert_eq!(&encodings[0].get_ids()[..1], &[2]);
+    assert_eq!(encodings[0].len(), 8);
+
+    let embeddings = model.embed(vec!["HELLO", "hello"], None).unwrap();
+    assert_eq!(embeddings[0], embeddings[1]);
+}
+
+#[cfg(feature = "cuda")]
+#[test]
+fn test_gpu_mem_limit() {
+    use crate::common::with_gpu_mem_limit;
+    use ort::{CPUExecutionProvider, CUDAExecutionProvider, ExecutionProviderDispatch};
+
+    let options = InitOptions {
+        gpu_mem_limit: Some(1 << 30),
+        ..Default::default()
+    }
+    .with_cuda(0);
+    let mut execution_providers = options.execution_providers;
+    execution_providers.push(CPUExecutionProvider::default().build());
+
+    let execution_providers = with_gpu_mem_limit(execution_providers, options.gpu_mem_limit);
+    match &execution_providers[0] {
+        ExecutionProviderDispatch::CUDA(cuda) => {
+            let expected = CUDAExecutionProvider::default()
+                .with_device_id(0)
+                .with_memory_limit(1 << 30);
+            assert_eq!(format!("{:?}", cuda), format!("{:?}", expected));
+        }
+        provider => panic!("Expected the CUDA provider, got {:?}", provider),
+    }
+    assert!(matches!(
+        execution_providers[1],
+        ExecutionProviderDispatch::CPU(_)
+    ));
+}
+
+#[test]
+fn test_embed_into() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let dim = TextEmbedding::get_model_info(&EmbeddingModel::BGESmallENV15).dim;
+
+    let documents: Vec<String> = (0..5).map(|index| format!("Document {}", index)).collect();
+    let expected = model.embed(documents.clone(), Some(2)).unwrap();
+
+    // The values past the last embedding are left untouched
+    let mut out = vec![-1.0; documents.len() * dim + 3];
+    model
+        .embed_into(documents.clone(), Some(2), &mut out, dim)
+        .unwrap();
+    for (embedding, expected) in out.chunks(dim).zip(&expected) {
+        assert_eq!(embedding, expected.as_slice());
+    }
+    assert_eq!(&out[documents.len() * dim..], &[-1.0; 3]);
+
+    let mut too_small = vec![0.0; dim];
+    assert!(model
+        .embed_into(documents.clone(), None, &mut too_small, dim)
+        .is_err());
+    // The dimension must match the one of the model
+    let mut out = vec![0.0; documents.len() * 8];
+    assert!(model.embed_into(documents, None, &mut out, 8).is_err());
+}
+
+#[test]
+fn test_is_model_cached() {
+    let cache_dir = std::env::temp_dir().join("fastembed_test_is_model_cached");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let model = EmbeddingModel::BGESmallENV15;
+    assert!(!TextEmbedding::is_model_cached(&model, &cache_dir));
+
+    // Mimic the layout of the Hugging Face cache, the main ref pointing to the snapshot
+    let model_dir = cache_dir.join("models--Xenova--bge-small-en-v1.5");
+    let snapshot_dir = model_dir.join("snapshots").join("0123abcd");
+    std::fs::create_dir_all(snapshot_dir.join("onnx")).unwrap();
+    std::fs::create_dir_all(model_dir.join("refs")).unwrap();
+    std::fs::write(model_dir.join("refs").join("main"), "0123abcd").unwrap();
+
+    let model_info = TextEmbedding::get_model_info(&model);
+    let files = [
+        model_info.model_file.as_str(),
+        "tokenizer.json",
+        "config.json",
+        "special_tokens_map.json",
+        "tokenizer_config.json",
+    ];
+    for file in files {
+        assert!(!TextEmbedding::is_model_cached(&model, &cache_dir));
+        std::fs::write(snapshot_dir.join(file), b"{}").unwrap();
+    }
+    assert!(TextEmbedding::is_model_cached(&model, &cache_dir));
+    assert!(!TextEmbedding::is_model_cached(
+        &EmbeddingModel::AllMiniLML6V2,
+        &cache_dir
+    ));
+
+    // The model file is retrieved from its alternate path when it is missing, it is cached as well
+    std::fs::remove_file(snapshot_dir.join(&model_info.model_file)).unwrap();
+    assert!(!TextEmbedding::is_model_cached(&model, &cache_dir));
+    std::fs::write(
+        snapshot_dir.join(alternate_model_file(&model_info.model_file)),
+        b"{}",
+    )
+    .unwrap();
+    assert!(TextEmbedding::is_model_cached(&model, &cache_dir));
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_alternate_model_file() {
+    assert_eq!(alternate_model_file("onnx/model.onnx"), "model.onnx");
+    assert_eq!(alternate_model_file("model.onnx"), "onnx/model.onnx");
+
+    // Mimic the layout of the Hugging Face cache, with the model file at the root of the repository
+    let cache_dir = std::env::temp_dir().join("fastembed_test_alternate_model_file");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let model_code = EmbeddingModel::BGESmallENV15.to_string();
+    let model_dir = cache_dir.join("models--Xenova--bge-small-en-v1.5");
+    let snapshot_dir = model_dir.join("snapshots").join("0123abcd");
+    std::fs::create_dir_all(&snapshot_dir).unwrap();
+    std::fs::create_dir_all(model_dir.join("refs")).unwrap();
+    std::fs::write(model_dir.join("refs").join("main"), "0123abcd").unwrap();
+    std::fs::write(snapshot_dir.join("model.onnx"), b"").unwrap();
+
+    let model_repo = retrieve_model(
+        model_code,
+        cache_dir.clone(),
+        false,
+        true,
+        HubConfig::default(),
+        None,
+    )
+    .unwrap();
+    let model_file = model_repo
+        .get_model_files("onnx/model.onnx", &[], EmbeddingModel::BGESmallENV15)
+        .unwrap();
+    assert_eq!(model_file, snapshot_dir.join("model.onnx"));
+
+    // Both paths are reported when neither exists
+    let error = model_repo
+        .get_model_files("onnx/missing.onnx", &[], EmbeddingModel::BGESmallENV15)
+        .unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("onnx/missing.onnx"));
+    assert!(message.contains("missing.onnx is not available either"));
+    // The error of the alternate path is kept along with the one of the file
+    assert_eq!(
+        message.matches("offline mode is enabled").count(),
+        2,
+        "{}",
+        message
+    );
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_embed_raw_with_norms() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let raw = model.embed_raw(documents.clone(), None).unwrap();
+    let with_norms = model.embed_raw_with_norms(documents.clone(), None).unwrap();
+    let normalized = model.embed(documents, None).unwrap();
+
+    for ((embedding, norm), (raw, normalized)) in with_norms.iter().zip(raw.iter().zip(&normalized))
+    {
+        assert_eq!(embedding, raw);
+        let expected = raw.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - expected).abs() < 1e-4 * expected);
+        for (value, normalized) in embedding.iter().zip(normalized) {
+            assert!((value / norm - normalized).abs() < 1e-5);
+        }
+    }
+}
+
+#[test]
+fn test_embedding_model_from_str() {
+    // The model code and the variant name, in any case
+    assert_eq!(
+        "Xenova/bge-small-en-v1.5"
+            .parse::<EmbeddingModel>()
+            .unwrap(),
+        EmbeddingModel::BGESmallENV15
+    );
+    assert_eq!(
+        "BGESmallENV15".parse::<EmbeddingModel>().unwrap(),
+        EmbeddingModel::BGESmallENV15
+    );
+    assert_eq!(
+        "allminilml6v2".parse::<EmbeddingModel>().unwrap(),
+        EmbeddingModel::AllMiniLML6V2
+    );
+
+    // The quantized variants sharing a model code are parsed from their model file
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::NomicEmbedTextV15Q);
+    let name = format!("{}/{}", model_info.model_code, model_info.model_file);
+    assert_eq!(
+        name.parse::<EmbeddingModel>().unwrap(),
+        EmbeddingModel::NomicEmbedTextV15Q
+    );
+    assert_eq!(
+        model_info.model_code.parse::<EmbeddingModel>().unwrap(),
+        EmbeddingModel::NomicEmbedTextV15
+    );
+
+    // Every model can be parsed back from its variant name
+    for model_info in TextEmbedding::list_supported_models() {
+        let name = format!("{:?}", model_info.model);
+        assert_eq!(name.parse::<EmbeddingModel>().unwrap(), model_info.model);
+    }
+
+    let error = "not-a-model".parse::<EmbeddingModel>().unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains("not-a-model"));
+    assert!(message.contains("BGESmallENV15 (Xenova/bge-small-en-v1.5)"));
+}
+
+#[test]
+fn test_embedding_model_all() {
+    let all = EmbeddingModel::all();
+    let models_list = TextEmbedding::list_supported_models();
+    assert_eq!(all.len(), EmbeddingModel::VARIANT_COUNT);
+    assert_eq!(all.len(), models_list.len());
+
+    // Every variant has a ModelInfo and every ModelInfo is listed, exactly once
+    for model in all {
+        assert_eq!(
+            models_list
+                .iter()
+                .filter(|model_info| model_info.model == *model)
+                .count(),
+            1,
+            "{:?}",
+            model
+        );
+    }
+    for model_info in &models_list {
+        assert_eq!(
+            all.iter()
+                .filter(|model| **model == model_info.model)
+                .count(),
+            1,
+            "{:?}",
+            model_info.model
+        );
+    }
+}
+
+#[test]
+fn test_model_manager() {
+    let manager = ModelManager::new(InitOptions {
+        show_download_progress: false,
+        ..Default::default()
+    });
+    assert!(manager.loaded_models().is_empty());
+
+    let documents = vec!["Hello, World!", "This is an example passage."];
+    let small = manager
+        .embed(&EmbeddingModel::BGESmallENV15, documents.clone(), None)
+        .unwrap();
+    let mini = manager
+        .embed(&EmbeddingModel::AllMiniLML6V2, documents.clone(), None)
+        .unwrap();
+    assert_eq!(small.len(), 2);
+    assert_eq!(mini.len(), 2);
+
+    let mut loaded = manager.loaded_models();
+    loaded.sort_by_key(|model| model.to_string());
+    assert_eq!(
+        loaded,
+        vec![EmbeddingModel::AllMiniLML6V2, EmbeddingModel::BGESmallENV15]
+    );
+
+    // The models are initialized once and shared afterwards
+    let model = manager.get(&EmbeddingModel::BGESmallENV15).unwrap();
+    assert!(Arc::ptr_eq(
+        &model,
+        &manager.get(&EmbeddingModel::BGESmallENV15).unwrap()
+    ));
+    assert_eq!(model.embed(documents, None).unwrap(), small);
+}
+
+#[test]
+fn test_model_manager_initializing_model() {
+    let manager = ModelManager::new(InitOptions {
+        show_download_progress: false,
+        ..Default::default()
+    });
+    manager.get(&EmbeddingModel::BGESmallENV15).unwrap();
+
+    let slot = manager.slot(&EmbeddingModel::AllMiniLML6V2);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        // Hold the slot of a model as its initialization does, for as long as its download lasts.
+        // It is released by a failing assertion too, before the thread is joined
+        let initializing = slot.lock().unwrap();
+        scope.spawn(|| {
+            let loaded = manager.loaded_models();
+            let embeddings =
+                manager.embed(&EmbeddingModel::BGESmallENV15, vec!["Hello, World!"], None);
+            sender
+                .send((loaded, embeddings.map(|embeddings| embeddings.len())))
+                .unwrap();
+        });
+        let (loaded, embeddings) = receiver
+            .recv_timeout(std::time::Duration::from_secs(60))
+            .expect("The loaded model waited for the one being initialized");
+        assert_eq!(loaded, vec![EmbeddingModel::BGESmallENV15]);
+        assert_eq!(embeddings.unwrap(), 1);
+        drop(initializing);
+    });
+}
+
+#[test]
+fn test_embed_flat() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents: Vec<String> = (0..5).map(|index| format!("Document {}", index)).collect();
+    let expected = model.embed(documents.clone(), Some(2)).unwrap();
+    let (flat, dim) = model.embed_flat(documents.clone(), Some(2)).unwrap();
+
+    assert_eq!(dim, 384);
+    assert_eq!(flat.len(), documents.len() * dim);
+    for (index, embedding) in expected.iter().enumerate() {
+        assert_eq!(&flat[index * dim..(index + 1) * dim], embedding.as_slice());
+    }
+
+    let (flat, dim) = model.embed_flat(Vec::<&str>::new(), None).unwrap();
+    assert!(flat.is_empty());
+    assert_eq!(dim, 0);
+}
+
+#[test]
+fn test_decompress_model() {
+    // Uncompressed onnx files are used as is
+    let onnx_file = token_ids_model("sentence_embedding");
+    assert!(matches!(
+        decompress_model(&onnx_file).unwrap(),
+        Cow::Borrowed(bytes) if bytes == onnx_file.as_slice()
+    ));
+
+    #[cfg(not(feature = "compression"))]
+    {
+        let error = decompress_model(&[0x1f, 0x8b, 0x08, 0x00]).unwrap_err();
+        assert!(error.to_string().contains("`compression` feature"));
+        let error = decompress_model(&[0x28, 0xb5, 0x2f, 0xfd]).unwrap_err();
+        assert!(error.to_string().contains("zstd"));
+    }
+    #[cfg(feature = "compression")]
+    assert!(decompress_model(&[0x1f, 0x8b, 0x08, 0x00]).is_err());
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_compressed_user_defined_model() {
+    use std::io::Write;
+
+    let onnx_file = token_ids_model("sentence_embedding");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&onnx_file).unwrap();
+    let gzip = encoder.finish().unwrap();
+    let zstd = ruzstd::encoding::compress_to_vec(
+        onnx_file.as_slice(),
+        ruzstd::encoding::CompressionLevel::Fastest,
+    );
+
+    let embed = |onnx_file: Vec<u8>| {
+        TextEmbedding::try_new_from_user_defined(
+            UserDefinedEmbeddingModel {
+                onnx_file,
+                tokenizer_files: model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+            },
+            Default::default(),
+        )
+        .unwrap()
+        .embed(vec!["Hello, World!", "This is an example passage."], None)
+        .unwrap()
+    };
+
+    let expected = embed(onnx_file);
+    assert_eq!(embed(gzip), expected);
+    assert_eq!(embed(zstd), expected);
+}
+
+#[test]
+fn test_embed_with_max_length() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let text =
+        "This is a long passage, which is embedded with different maximum lengths. ".repeat(8);
+    let token_count = |max_length: usize| {
+        with_max_length(&model.tokenizer, max_length)
+            .unwrap()
+            .encode(text.as_str(), true)
+            .unwrap()
+            .len()
+    };
+    assert_eq!(token_count(8), 8);
+    assert_eq!(token_count(32), 32);
+
+    let short = model.embed_with_max_length(vec![&text], None, 8).unwrap();
+    let long = model.embed_with_max_length(vec![&text], None, 32).unwrap();
+    assert_ne!(short, long);
+
+    // The tokenizer of the model keeps its own truncation
+    assert!(model.tokenize(vec![&text]).unwrap()[0].len() > 32);
+    assert_eq!(
+        model
+            .embed_with_max_length(vec![&text], None, model.max_length())
+            .unwrap(),
+        model.embed(vec![&text], None).unwrap()
+    );
+
+    assert!(model.embed_with_max_length(vec![&text], None, 0).is_err());
+    assert!(model
+        .embed_with_max_length(vec![&text], None, model.max_length() + 1)
+        .is_err());
+}
+
+#[test]
+fn test_model_licenses() {
+    // Every model declares its license and where it comes from, for the attribution
+    let attributions = TextEmbedding::list_supported_models()
+        .into_iter()
+        .map(|model_info| {
+            (
+                model_info.model_code,
+                model_info.license,
+                model_info.source_url,
+            )
+        })
+        .chain(
+            SparseTextEmbedding::list_supported_models()
+                .into_iter()
+                .map(|model_info| {
+                    (
+                        model_info.model_code,
+                        model_info.license,
+                        model_info.source_url,
+                    )
+                }),
+        );
+    for (model_code, license, source_url) in attributions {
+        assert!(!license.is_empty(), "{}", model_code);
+        assert!(source_url.starts_with("https://"), "{}", model_code);
+    }
+
+    let model_info = TextEmbedding::get_model_info(&EmbeddingModel::BGESmallENV15);
+    assert_eq!(model_info.license, "MIT");
+    assert_eq!(
+        model_info.source_url,
+        "https://huggingface.co/BAAI/bge-small-en-v1.5"
+    );
+}
+
+#[test]
+fn test_model_max_length_overflow() {
+    let model_max_length = |config: &str| model_max_length(&serde_json::from_str(config).unwrap());
+    assert_eq!(model_max_length(r#"{"model_max_length": 512}"#), Some(512));
+    assert_eq!(
+        model_max_length(r#"{"model_max_length": 1e30}"#),
+        Some(MAX_MODEL_MAX_LENGTH)
+    );
+    assert_eq!(
+        model_max_length(r#"{"model_max_length": 1000000000000000019884624838656}"#),
+        Some(MAX_MODEL_MAX_LENGTH)
+    );
+    assert_eq!(model_max_length(r#"{"model_max_length": -1}"#), None);
+    assert_eq!(model_max_length("{}"), None);
+
+    // The configured max_length is kept below the clamped model_max_length
+    let mut tokenizer_files = model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2);
+    tokenizer_files.tokenizer_config_file = br#"{"model_max_length": 1e30}"#.to_vec();
+    let model = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file: token_ids_model("sentence_embedding"),
+            tokenizer_files,
+        },
+        InitOptionsUserDefined {
+            max_length: 2048,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(model.max_length(), 2048);
+}
+
+#[test]
+fn test_par_embed() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents: Vec<String> = (0..7).map(|index| format!("Document {}", index)).collect();
+    let expected = model.embed(documents.clone(), Some(2)).unwrap();
+    let embeddings: Vec<Embedding> = model
+        .par_embed(&documents, Some(2))
+        .unwrap()
+        .collect::<Result<_, FastEmbedError>>()
+        .unwrap();
+    assert_eq!(embeddings, expected);
+
+    assert!(model.par_embed(&documents, Some(0)).is_err());
+}
+
+#[test]
+fn test_norms() {
+    let normed = |norm: Norm| {
+        let mut embedding = vec![3.0, -4.0, 0.0, 1.0];
+        pooling::apply_norm(norm, &mut embedding);
+        embedding
+    };
+    let l2 = 26f32.sqrt();
+    for (value, expected) in normed(Norm::L2)
+        .iter()
+        .zip([3.0 / l2, -4.0 / l2, 0.0, 1.0 / l2])
+    {
+        assert!((value - expected).abs() < 1e-6);
+    }
+    assert_eq!(normed(Norm::L1), vec![0.375, -0.5, 0.0, 0.125]);
+    assert_eq!(normed(Norm::Max), vec![0.75, -1.0, 0.0, 0.25]);
+    assert_eq!(normed(Norm::None), vec![3.0, -4.0, 0.0, 1.0]);
+
+    // Zero vectors are left as they are rather than divided by zero
+    for norm in [Norm::L2, Norm::L1, Norm::Max, Norm::None] {
+        let mut zeros = vec![0.0; 4];
+        pooling::apply_norm(norm, &mut zeros);
+        assert_eq!(zeros, vec![0.0; 4]);
+    }
+
+    assert_eq!(Norm::from(true), Norm::L2);
+    assert_eq!(Norm::from(false), Norm::None);
+    assert_eq!(
+        InitOptions::builder().normalize(false).build().normalize,
+        Norm::None
+    );
+    assert_eq!(InitOptions::default().normalize, Norm::L2);
+
+    // The norm is applied to the pooled embeddings of the model
+    let model = TextEmbedding::try_new(InitOptions {
+        normalize: Norm::L1,
+        ..Default::default()
+    })
+    .unwrap();
+    let embeddings = model.embed(vec!["Hello, World!"], None).unwrap();
+    let l1: f32 = embeddings[0].iter().map(|x| x.abs()).sum();
+    assert!((l1 - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_typed_errors() {
+    // A model file missing from the cache in offline mode
+    let empty_cache_dir = std::env::temp_dir().join("fastembed_typed_errors_cache");
+    std::fs::create_dir_all(&empty_cache_dir).unwrap();
+    let error = TextEmbedding::try_new(InitOptions {
+        offline: true,
+        cache_dir: empty_cache_dir,
+        ..Default::default()
+    })
+    .err()
+    .unwrap();
+    assert!(matches!(error, FastEmbedError::Download(_)));
+
+    // A tokenizer.json which is not valid JSON, the error of the tokenizers is kept as the source
+    let mut tokenizer_files = model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2);
+    tokenizer_files.tokenizer_file = b"not a tokenizer".to_vec();
+    let error = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file: token_ids_model("sentence_embedding"),
+            tokenizer_files,
+        },
+        Default::default(),
+    )
+    .err()
+    .unwrap();
+    match &error {
+        FastEmbedError::TokenizerLoad(_) => {
+            assert!(error.to_string().contains("tokenizer.json"));
+            assert!(std::error::Error::source(&error).is_some());
+        }
+        other => panic!("Expected a TokenizerLoad error, got {:?}", other),
+    }
+
+    // An unknown model name
+    let error = "not-a-model".parse::<EmbeddingModel>().unwrap_err();
+    assert!(matches!(error, FastEmbedError::UnsupportedModel(_)));
+
+    // A buffer too small for the embeddings
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let error = model
+        .embed_into(vec!["Hello, World!"], None, &mut [0.0; 8], 384)
+        .unwrap_err();
+    assert!(matches!(error, FastEmbedError::ShapeMismatch(_)));
+
+    // Invalid arguments
+    let error = model.embed(vec!["Hello, World!"], Some(0)).unwrap_err();
+    assert!(matches!(error, FastEmbedError::InvalidInput(_)));
+
+    // An error of ONNX Runtime the crate adds no kind to, the output of the model is not a float tensor
+    let int_output_model = onnx_model(
+        &[onnx_node(
+            "Identity",
+            &["input_ids"],
+            &["sentence_embedding"],
+            &[],
+        )],
+        &[
+            onnx_tensor_info("input_ids", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("attention_mask", ONNX_INT64, &["batch", "sequence"]),
+            onnx_tensor_info("token_type_ids", ONNX_INT64, &["batch", "sequence"]),
+        ],
+        &[onnx_tensor_info(
+            "sentence_embedding",
+            ONNX_INT64,
+            &["batch", "sequence"],
+        )],
+    );
+    let error = TextEmbedding::try_new_from_user_defined(
+        UserDefinedEmbeddingModel {
+            onnx_file: int_output_model,
+            tokenizer_files: model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2),
+        },
+        Default::default(),
+    )
+    .unwrap()
+    .embed(vec!["Hello, World!"], None)
+    .unwrap_err();
+    match &error {
+        FastEmbedError::Inference(inner) => assert!(inner.is::<ort::Error>()),
+        other => panic!("Expected an Inference error, got {:?}", other),
+    }
+
+    // Kinds are also given to the anyhow errors by the type of their causes
+    let error = FastEmbedError::from(
+        anyhow::Error::from(std::io::Error::other("disk full")).context("Failed to save"),
+    );
+    assert!(matches!(error, FastEmbedError::Io(_)));
+    assert_eq!(error.to_string(), "Failed to save");
+    assert_eq!(format!("{:#}", error), "Failed to save: disk full");
+    let error = FastEmbedError::from(anyhow::Error::from(std::io::Error::other("disk full")));
+    assert!(error.inner().is::<std::io::Error>());
+}
+
+#[test]
+fn test_embed_with_scratch() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+
+    let documents: Vec<String> = (0..5)
+        .map(|index| format!("Document number {} of the corpus", index))
+        .collect();
+    let expected = model.embed(documents.clone(), Some(2)).unwrap();
+
+    let mut scratch = EmbedScratch::new();
+    assert_eq!(scratch.capacity(), 0);
+    let embeddings = model
+        .embed_with_scratch(documents.clone(), Some(2), &mut scratch)
+        .unwrap();
+    assert_eq!(embeddings, expected);
+
+    // The buffers sized by the first call are reused by the following ones
+    let capacity = scratch.capacity();
+    assert!(capacity > 0);
+    for _ in 0..10 {
+        let embeddings = model
+            .embed_with_scratch(documents.clone(), Some(2), &mut scratch)
+            .unwrap();
+        assert_eq!(embeddings, expected);
+        assert_eq!(scratch.capacity(), capacity);
+    }
+
+    assert!(model
+        .embed_with_scratch(documents, Some(0), &mut scratch)
+        .is_err());
+}
+
+#[test]
+fn test_token_filter() {
+    let tokenizer_files = model_tokenizer_files(&EmbeddingModel::AllMiniLML6V2);
+    let documents = vec!["Hello, World!", "This is a longer example passage, World!"];
+    let model_with_filter = |token_filter: Option<TokenFilter>| {
+        TextEmbedding::try_new_from_user_defined(
+            UserDefinedEmbeddingModel {
+                onnx_file: token_ids_model("sentence_embedding"),
+                tokenizer_files: tokenizer_files.clone(),
+            },
+            InitOptionsUserDefined {
+                normalize: Norm::None,
+                token_filter,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    let model = model_with_filter(None);
+    let world = model.tokenizer.token_to_id("world").unwrap();
+    let unfiltered = model.embed(documents.clone(), None).unwrap();
+    assert!(unfiltered
+        .iter()
+        .all(|embedding| embedding.contains(&(world as f32))));
+
+    // The model outputs the token ids it is fed
+    let model = model_with_filter(Some(Arc::new(move |id| id != world)));
+    let filtered = model.embed(documents.clone(), None).unwrap();
+    assert_eq!(filtered.len(), documents.len());
+    for (filtered, unfiltered) in filtered.iter().zip(&unfiltered) {
+        assert!(!filtered.contains(&(world as f32)));
+        // The longest sequence lost one token, the rows are padded again to its length
+        assert_eq!(filtered.len(), unfiltered.len() - 1);
+        let expected: Vec<f32> = unfiltered
+            .iter()
+            .copied()
+            .filter(|&id| id != world as f32 && id != 0.0)
+            .collect();
+        assert_eq!(
+            filtered
+                .iter()
+                .copied()
+                .filter(|&id| id != 0.0)
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    // A sequence without any token left would be fed with no length to the pooling
+    let model = model_with_filter(Some(Arc::new(|_| false)));
+    let error = model.embed(documents, None).unwrap_err();
+    assert!(error.to_string().contains("input 0"));
+}
+
+#[test]
+fn test_model_signature() {
+    let model = TextEmbedding::try_new(Default::default()).unwrap();
+    let signature = model.model_signature();
+
+    let input = |name: &str| {
+        signature
+            .inputs
+            .iter()
+            .find(|input| input.name == name)
+            .unwrap_or_else(|| panic!("Missing input {}", name))
+    };
+    for name in ["input_ids", "attention_mask"] {
+        let input = input(name);
+        assert_eq!(input.shape.as_ref().map(Vec::len), Some(2));
+        assert!(matches!(
+            input.value_type,
+            ValueType::Tensor {
+                ty: TensorElementType::Int64,
+                ..
+            }
+        ));
+    }
+
+    // The hidden states are pooled by the crate
+    assert!(signature
+        .outputs
+        .iter()
+        .any(|output| output.name == "last_hidden_state"
+            && output.shape.as_ref().map(Vec::len) == Some(3)));
+}
@@ -0,0 +1,2993 @@
+use crate::{
+    common::{
+        self, cached_model_dir, cached_sha256, decompress_model, default_cache_dir, dir_size,
+        files_cached, load_tokenizer, load_tokenizer_hf_hub, read_file_to_bytes, retrieve_model,
+        session_builder, verify_sha256, with_gpu_mem_limit, with_profiling, Embed, Embedding,
+        ExecutionProviderChain, FallbackCallback, HubConfig, ModelSignature, OnEmptyText,
+        OnTruncation, OptimizationLevel, Padding, ProgressCallback, RetryPolicy, SpecialTokenIds,
+        TokenFilter, Tokenizer, TokenizerFiles, DEFAULT_BATCH_SIZE, DEFAULT_MAX_LENGTH,
+        TOKENIZER_FILE_NAMES,
+    },
+    error::{inference_error, invalid_input, tokenization_error},
+    models::models_list,
+    pooling::{self, Activation, Norm},
+    similarity, EmbeddingModel, FastEmbedError, ModelInfo, ModelQuantization, Pooling,
+};
+use anyhow::{bail, Context, Result};
+use ndarray::{s, ArcArray2, Array, Array2, Axis, Ix2, Ix3};
+use ort::{ExecutionProviderDispatch, Session, SessionBuilder, SessionOutputs, Value};
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    slice::{ParallelSlice, ParallelSliceMut},
+    ThreadPool, ThreadPoolBuilder,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+use tokenizers::{EncodeInput, Encoding, PaddingStrategy, TruncationDirection};
+
+const DEFAULT_EMBEDDING_MODEL: EmbeddingModel = EmbeddingModel::BGESmallENV15;
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Options for initializing the TextEmbedding model
+#[derive(Clone)]
+pub struct InitOptions {
+    pub model_name: EmbeddingModel,
+    pub execution_providers: Vec<ExecutionProviderDispatch>,
+    pub max_length: usize,
+    /// Directory the models are downloaded to - Defaults to [default_cache_dir](crate::default_cache_dir)
+    pub cache_dir: PathBuf,
+    pub show_download_progress: bool,
+    pub pooling: Pooling,
+    /// Norm the pooled embeddings are divided by - Defaults to [Norm::L2]
+    ///
+    /// `true` and `false` convert to [Norm::L2] and [Norm::None]
+    pub normalize: Norm,
+    /// Truncate the embeddings to the first N dimensions, for models trained with Matryoshka representation learning
+    pub output_dimension: Option<usize>,
+    /// Number of intra-op threads, defaults to the number of available CPUs
+    pub threads: Option<usize>,
+    /// Number of inter-op threads, used to run independent branches of the graph in parallel
+    ///
+    /// Setting it enables ONNX Runtime's parallel execution mode. The inter-op pool only schedules
+    /// the nodes assigned to CPU, nodes placed on other execution providers such as CUDA are unaffected
+    pub inter_threads: Option<usize>,
+    /// Only load the model files from `cache_dir`, without making any network request
+    pub offline: bool,
+    /// Hugging Face API token for gated models, falls back to the `HF_TOKEN` environment variable
+    pub hf_token: Option<String>,
+    /// Hugging Face Hub endpoint to download the models from, such as a mirror. Defaults to https://huggingface.co
+    pub endpoint: Option<String>,
+    /// Application name and version sent in the User-Agent of the downloads, such as `my-app/1.2.0`.
+    /// Defaults to `fastembed/<crate version>`
+    pub user_agent: Option<String>,
+    pub padding: Padding,
+    /// Side from which the inputs longer than `max_length` are truncated - Defaults to `Right`
+    pub truncation_direction: TruncationDirection,
+    /// Called with the progress of the model files downloads, replaces the default progress bar
+    pub progress_callback: Option<ProgressCallback>,
+    /// Load a quantized variant of the model, which must be provided by the model
+    pub quantization: ModelQuantization,
+    /// Called with a warning for each execution provider that could not be registered
+    pub fallback_callback: Option<FallbackCallback>,
+    /// Sort the texts by token length before batching them in `embed`, to reduce the padding
+    ///
+    /// The embeddings are still returned in the order of the texts
+    pub sort_by_length: bool,
+    /// What to do with the inputs longer than `max_length` - Defaults to `Silent` truncation
+    pub on_truncation: OnTruncation,
+    /// Run the parallel sections of `embed` on a thread pool of the model, sized by `threads`,
+    /// instead of the global rayon pool shared with the rest of the application
+    pub dedicated_thread_pool: bool,
+    /// Add the special tokens of the model, such as `[CLS]` and `[SEP]`, to the texts - Defaults to `true`
+    pub add_special_tokens: bool,
+    /// Check the SHA-256 of the model file against the one declared in its ModelInfo, or else against the one
+    /// the Hub recorded for it in the cache - The files of unknown checksum are left unverified
+    pub verify_checksums: bool,
+    /// Number of times a failed download of a model file is retried - Defaults to 0
+    pub download_retries: usize,
+    /// Delay before the first retry of a failed download, doubled after every retry - Defaults to 1 second
+    pub retry_backoff: Duration,
+    /// Run a dummy inference when the model is created, so that the first call to `embed` does not
+    /// pay for the lazy initialization of ONNX Runtime - Defaults to `false`
+    pub warmup: bool,
+    /// Split a batch in halves and retry them when it fails with an out of memory error,
+    /// down to batches of one text - Defaults to `false`
+    pub adaptive_oom_retry: bool,
+    /// What to do with the empty or whitespace-only texts given to `embed` - Defaults to embedding them
+    pub on_empty_text: OnEmptyText,
+    /// Level of the graph optimizations applied by ONNX Runtime - Defaults to `Level3`
+    pub optimization_level: OptimizationLevel,
+    /// Enable the profiler of ONNX Runtime, `TextEmbedding::end_profiling` writes its JSON trace to this path
+    ///
+    /// Requires the `profiling` feature
+    pub enable_profiling: Option<PathBuf>,
+    /// Maximum number of batches running at the same time, across all the embedding methods, to bound
+    /// the threads a model occupies when many of them share a process - Defaults to unbounded
+    pub max_concurrent_batches: Option<usize>,
+    /// Use `max_length` as is, even above the `model_max_length` of the tokenizer config it is clamped to otherwise
+    ///
+    /// Only meant for the models whose config understates their context. The inputs longer than the position
+    /// embeddings of the model fail at inference or produce meaningless embeddings - Defaults to `false`
+    pub allow_exceed_model_max_length: bool,
+    /// L2-normalize the hidden state of every token before pooling them, like some exports do
+    ///
+    /// The pooled embeddings are still normalized afterwards with the `normalize` norm - Defaults to `false`
+    pub normalize_before_pooling: bool,
+    /// Maximum size of the memory arena of the CUDA execution providers, in bytes - Defaults to the whole device
+    ///
+    /// Lets the model share the device with other models. The other execution providers are left as is
+    pub gpu_mem_limit: Option<usize>,
+    /// Activation applied to the pooled output, before the normalization - Defaults to [Activation::None]
+    ///
+    /// Set `normalize` to [Norm::None] to get the probabilities of the classifiers as they are
+    pub activation: Activation,
+    /// Filter applied to the token ids of the texts after tokenization, keeping the tokens it returns `true` for
+    ///
+    /// The rejected tokens, such as stopwords or numerals, are removed from the sequences before the inference,
+    /// which are padded again to the longest one. The special tokens are passed to the filter too, and `embed` fails
+    /// on the texts left without any token - Defaults to `None`
+    pub token_filter: Option<TokenFilter>,
+}
+
+// Implemented by hand to keep the Hugging Face token out of the logs
+impl std::fmt::Debug for InitOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InitOptions")
+            .field("model_name", &self.model_name)
+            .field("execution_providers", &self.execution_providers)
+            .field("max_length", &self.max_length)
+            .field("cache_dir", &self.cache_dir)
+            .field("show_download_progress", &self.show_download_progress)
+            .field("pooling", &self.pooling)
+            .field("normalize", &self.normalize)
+            .field("output_dimension", &self.output_dimension)
+            .field("threads", &self.threads)
+            .field("inter_threads", &self.inter_threads)
+            .field("offline", &self.offline)
+            .field("hf_token", &self.hf_token.as_ref().map(|_| "<redacted>"))
+            .field("endpoint", &self.endpoint)
+            .field("user_agent", &self.user_agent)
+            .field("padding", &self.padding)
+            .field("truncation_direction", &self.truncation_direction)
+            .field(
+                "progress_callback",
+                &self.progress_callback.as_ref().map(|_| "<callback>"),
+            )
+            .field("quantization", &self.quantization)
+            .field(
+                "fallback_callback",
+                &self.fallback_callback.as_ref().map(|_| "<callback>"),
+            )
+            .field("sort_by_length", &self.sort_by_length)
+            .field("on_truncation", &self.on_truncation)
+            .field("dedicated_thread_pool", &self.dedicated_thread_pool)
+            .field("add_special_tokens", &self.add_special_tokens)
+            .field("verify_checksums", &self.verify_checksums)
+            .field("download_retries", &self.download_retries)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("warmup", &self.warmup)
+            .field("adaptive_oom_retry", &self.adaptive_oom_retry)
+            .field("on_empty_text", &self.on_empty_text)
+            .field("optimization_level", &self.optimization_level)
+            .field("enable_profiling", &self.enable_profiling)
+            .field("max_concurrent_batches", &self.max_concurrent_batches)
+            .field(
+                "allow_exceed_model_max_length",
+                &self.allow_exceed_model_max_length,
+            )
+            .field("normalize_before_pooling", &self.normalize_before_pooling)
+            .field("gpu_mem_limit", &self.gpu_mem_limit)
+            .field("activation", &self.activation)
+            .field(
+                "token_filter",
+                &self.token_filter.as_ref().map(|_| "<callback>"),
+            )
+            .finish()
+    }
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        Self {
+            model_name: DEFAULT_EMBEDDING_MODEL,
+            execution_providers: Default::default(),
+            max_length: DEFAULT_MAX_LENGTH,
+            cache_dir: default_cache_dir(),
+            show_download_progress: true,
+            pooling: Pooling::default(),
+            normalize: Norm::L2,
+            output_dimension: None,
+            threads: None,
+            inter_threads: None,
+            offline: false,
+            hf_token: None,
+            endpoint: None,
+            user_agent: None,
+            padding: Padding::default(),
+            truncation_direction: TruncationDirection::default(),
+            progress_callback: None,
+            quantization: ModelQuantization::default(),
+            fallback_callback: None,
+            sort_by_length: false,
+            on_truncation: OnTruncation::default(),
+            dedicated_thread_pool: false,
+            add_special_tokens: true,
+            verify_checksums: false,
+            download_retries: 0,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            warmup: false,
+            adaptive_oom_retry: false,
+            on_empty_text: OnEmptyText::default(),
+            optimization_level: OptimizationLevel::default(),
+            enable_profiling: None,
+            max_concurrent_batches: None,
+            allow_exceed_model_max_length: false,
+            normalize_before_pooling: false,
+            gpu_mem_limit: None,
+            activation: Activation::default(),
+            token_filter: None,
+        }
+    }
+}
+
+impl InitOptions {
+    /// Create a builder for InitOptions, starting from the default options
+    pub fn builder() -> InitOptionsBuilder {
+        InitOptionsBuilder::default()
+    }
+
+    /// Run the model on the CUDA device with the given id
+    ///
+    /// The CPU is used if CUDA can not be registered, which is reported through the `fallback_callback`
+    #[cfg(feature = "cuda")]
+    pub fn with_cuda(mut self, device_id: i32) -> Self {
+        self.execution_providers.push(
+            ort::CUDAExecutionProvider::default()
+                .with_device_id(device_id)
+                .build(),
+        );
+        self
+    }
+
+    /// Run the model with CoreML, on Apple devices
+    ///
+    /// The CPU is used if CoreML can not be registered, which is reported through the `fallback_callback`
+    #[cfg(feature = "coreml")]
+    pub fn with_coreml(mut self) -> Self {
+        self.execution_providers
+            .push(ort::CoreMLExecutionProvider::default().build());
+        self
+    }
+}
+
+/// Builder for [InitOptions](crate::InitOptions)
+///
+/// Options that are not set keep their default value
+#[derive(Debug, Clone, Default)]
+pub struct InitOptionsBuilder {
+    options: InitOptions,
+}
+
+impl InitOptionsBuilder {
+    pub fn model_name(mut self, model_name: EmbeddingModel) -> Self {
+        self.options.model_name = model_name;
+        self
+    }
+
+    pub fn execution_providers(
+        mut self,
+        execution_providers: Vec<ExecutionProviderDispatch>,
+    ) -> Self {
+        self.options.execution_providers = execution_providers;
+        self
+    }
+
+    /// Set the execution providers from a chain, in its order of priority
+    pub fn execution_provider_chain(mut self, chain: ExecutionProviderChain) -> Self {
+        self.options.execution_providers = chain.into();
+        self
+    }
+
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.options.max_length = max_length;
+        self
+    }
+
+    pub fn cache_dir(mut self, cache_dir: PathBuf) -> Self {
+        self.options.cache_dir = cache_dir;
+        self
+    }
+
+    pub fn show_download_progress(mut self, show_download_progress: bool) -> Self {
+        self.options.show_download_progress = show_download_progress;
+        self
+    }
+
+    pub fn pooling(mut self, pooling: Pooling) -> Self {
+        self.options.pooling = pooling;
+        self
+    }
+
+    pub fn normalize(mut self, normalize: impl Into<Norm>) -> Self {
+        self.options.normalize = normalize.into();
+        self
+    }
+
+    pub fn output_dimension(mut self, output_dimension: usize) -> Self {
+        self.options.output_dimension = Some(output_dimension);
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = Some(threads);
+        self
+    }
+
+    pub fn inter_threads(mut self, inter_threads: usize) -> Self {
+        self.options.inter_threads = Some(inter_threads);
+        self
+    }
+
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.options.offline = offline;
+        self
+    }
+
+    pub fn hf_token(mut self, hf_token: String) -> Self {
+        self.options.hf_token = Some(hf_token);
+        self
+    }
+
+    pub fn endpoint(mut self, endpoint: String) -> Self {
+        self.options.endpoint = Some(endpoint);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: String) -> Self {
+        self.options.user_agent = Some(user_agent);
+        self
+    }
+
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.options.padding = padding;
+        self
+    }
+
+    pub fn truncation_direction(mut self, truncation_direction: TruncationDirection) -> Self {
+        self.options.truncation_direction = truncation_direction;
+        self
+    }
+
+    pub fn progress_callback(mut self, progress_callback: ProgressCallback) -> Self {
+        self.options.progress_callback = Some(progress_callback);
+        self
+    }
+
+    pub fn quantization(mut self, quantization: ModelQuantization) -> Self {
+        self.options.quantization = quantization;
+        self
+    }
+
+    pub fn fallback_callback(mut self, fallback_callback: FallbackCallback) -> Self {
+        self.options.fallback_callback = Some(fallback_callback);
+        self
+    }
+
+    pub fn sort_by_length(mut self, sort_by_length: bool) -> Self {
+        self.options.sort_by_length = sort_by_length;
+        self
+    }
+
+    pub fn on_truncation(mut self, on_truncation: OnTruncation) -> Self {
+        self.options.on_truncation = on_truncation;
+        self
+    }
+
+    pub fn dedicated_thread_pool(mut self, dedicated_thread_pool: bool) -> Self {
+        self.options.dedicated_thread_pool = dedicated_thread_pool;
+        self
+    }
+
+    pub fn add_special_tokens(mut self, add_special_tokens: bool) -> Self {
+        self.options.add_special_tokens = add_special_tokens;
+        self
+    }
+
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.options.verify_checksums = verify_checksums;
+        self
+    }
+
+    pub fn download_retries(mut self, download_retries: usize) -> Self {
+        self.options.download_retries = download_retries;
+        self
+    }
+
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.options.retry_backoff = retry_backoff;
+        self
+    }
+
+    pub fn warmup(mut self, warmup: bool) -> Self {
+        self.options.warmup = warmup;
+        self
+    }
+
+    pub fn adaptive_oom_retry(mut self, adaptive_oom_retry: bool) -> Self {
+        self.options.adaptive_oom_retry = adaptive_oom_retry;
+        self
+    }
+
+    pub fn on_empty_text(mut self, on_empty_text: OnEmptyText) -> Self {
+        self.options.on_empty_text = on_empty_text;
+        self
+    }
+
+    pub fn optimization_level(mut self, optimization_level: OptimizationLevel) -> Self {
+        self.options.optimization_level = optimization_level;
+        self
+    }
+
+    pub fn enable_profiling(mut self, profiling_file: PathBuf) -> Self {
+        self.options.enable_profiling = Some(profiling_file);
+        self
+    }
+
+    pub fn max_concurrent_batches(mut self, max_concurrent_batches: usize) -> Self {
+        self.options.max_concurrent_batches = Some(max_concurrent_batches);
+        self
+    }
+
+    pub fn allow_exceed_model_max_length(mut self, allow_exceed_model_max_length: bool) -> Self {
+        self.options.allow_exceed_model_max_length = allow_exceed_model_max_length;
+        self
+    }
+
+    pub fn normalize_before_pooling(mut self, normalize_before_pooling: bool) -> Self {
+        self.options.normalize_before_pooling = normalize_before_pooling;
+        self
+    }
+
+    pub fn gpu_mem_limit(mut self, gpu_mem_limit: usize) -> Self {
+        self.options.gpu_mem_limit = Some(gpu_mem_limit);
+        self
+    }
+
+    pub fn activation(mut self, activation: Activation) -> Self {
+        self.options.activation = activation;
+        self
+    }
+
+    pub fn token_filter(mut self, token_filter: TokenFilter) -> Self {
+        self.options.token_filter = Some(token_filter);
+        self
+    }
+
+    /// Consume the builder and return the InitOptions
+    pub fn build(self) -> InitOptions {
+        self.options
+    }
+}
+
+/// Options for initializing UserDefinedEmbeddingModel
+///
+/// Model files are held by the UserDefinedEmbeddingModel struct
+#[derive(Clone)]
+pub struct InitOptionsUserDefined {
+    pub execution_providers: Vec<ExecutionProviderDispatch>,
+    pub max_length: usize,
+    pub pooling: Pooling,
+    /// Norm the pooled embeddings are divided by - Defaults to [Norm::L2]
+    ///
+    /// `true` and `false` convert to [Norm::L2] and [Norm::None]
+    pub normalize: Norm,
+    /// Number of intra-op threads, defaults to the number of available CPUs
+    pub threads: Option<usize>,
+    /// Number of inter-op threads, used to run independent branches of the graph in parallel
+    ///
+    /// Setting it enables ONNX Runtime's parallel execution mode. The inter-op pool only schedules
+    /// the nodes assigned to CPU, nodes placed on other execution providers such as CUDA are unaffected
+    pub inter_threads: Option<usize>,
+    pub padding: Padding,
+    /// Side from which the inputs longer than `max_length` are truncated - Defaults to `Right`
+    pub truncation_direction: TruncationDirection,
+    /// Name of the output tensor holding the embeddings
+    ///
+    /// Defaults to `last_hidden_state`, or to the first output of the model if it has none with this name
+    pub output_key: Option<String>,
+    /// Sort the texts by token length before batching them in `embed`, to reduce the padding
+    ///
+    /// The embeddings are still returned in the order of the texts
+    pub sort_by_length: bool,
+    /// What to do with the inputs longer than `max_length` - Defaults to `Silent` truncation
+    pub on_truncation: OnTruncation,
+    /// Run the parallel sections of `embed` on a thread pool of the model, sized by `threads`,
+    /// instead of the global rayon pool shared with the rest of the application
+    pub dedicated_thread_pool: bool,
+    /// Add the special tokens of the model, such as `[CLS]` and `[SEP]`, to the texts - Defaults to `true`
+    pub add_special_tokens: bool,
+    /// Pad token, overriding the `pad_token` of tokenizer_config.json - Defaults to `[PAD]` if neither is set
+    pub pad_token: Option<String>,
+    /// Id of the pad token, overriding the `pad_token_id` of config.json - Defaults to 0 if neither is set
+    pub pad_id: Option<u32>,
+    /// Use the `pooler_output` tensor of the model for CLS pooling, if it has one - Defaults to `false`
+    ///
+    /// BERT-style models apply a dense layer and a tanh activation to the CLS token in their pooler
+    pub has_pooler: bool,
+    /// Template used by `embed_with_instruction`, holding the `{instruction}` and `{text}` placeholders
+    ///
+    /// `embed_with_instruction` fails if unset, as the model was not trained with any instruction
+    pub instruction_template: Option<String>,
+    /// Run a dummy inference when the model is created, so that the first call to `embed` does not
+    /// pay for the lazy initialization of ONNX Runtime - Defaults to `false`
+    pub warmup: bool,
+    /// Split a batch in halves and retry them when it fails with an out of memory error,
+    /// down to batches of one text - Defaults to `false`
+    pub adaptive_oom_retry: bool,
+    /// What to do with the empty or whitespace-only texts given to `embed` - Defaults to embedding them
+    pub on_empty_text: OnEmptyText,
+    /// Level of the graph optimizations applied by ONNX Runtime - Defaults to `Level3`
+    pub optimization_level: OptimizationLevel,
+    /// Enable the profiler of ONNX Runtime, `TextEmbedding::end_profiling` writes its JSON trace to this path
+    ///
+    /// Requires the `profiling` feature
+    pub enable_profiling: Option<PathBuf>,
+    /// Maximum number of batches running at the same time, across all the embedding methods, to bound
+    /// the threads a model occupies when many of them share a process - Defaults to unbounded
+    pub max_concurrent_batches: Option<usize>,
+    /// Use `max_length` as is, even above the `model_max_length` of the tokenizer config it is clamped to otherwise
+    ///
+    /// Only meant for the models whose config understates their context. The inputs longer than the position
+    /// embeddings of the model fail at inference or produce meaningless embeddings - Defaults to `false`
+    pub allow_exceed_model_max_length: bool,
+    /// L2-normalize the hidden state of every token before pooling them, like some exports do
+    ///
+    /// The pooled embeddings are still normalized afterwards with the `normalize` norm - Defaults to `false`
+    pub normalize_before_pooling: bool,
+    /// Maximum size of the memory arena of the CUDA execution providers, in bytes - Defaults to the whole device
+    ///
+    /// Lets the model share the device with other models. The other execution providers are left as is
+    pub gpu_mem_limit: Option<usize>,
+    /// Activation applied to the pooled output, before the normalization - Defaults to [Activation::None]
+    ///
+    /// Set `normalize` to [Norm::None] to get the probabilities of the classifiers as they are
+    pub activation: Activation,
+    /// Filter applied to the token ids of the texts after tokenization, keeping the tokens it returns `true` for
+    ///
+    /// The rejected tokens, such as stopwords or numerals, are removed from the sequences before the inference,
+    /// which are padded again to the longest one. The special tokens are passed to the filter too, and `embed` fails
+    /// on the texts left without any token - Defaults to `None`
+    pub token_filter: Option<TokenFilter>,
+}
+
+impl std::fmt::Debug for InitOptionsUserDefined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InitOptionsUserDefined")
+            .field("execution_providers", &self.execution_providers)
+            .field("max_length", &self.max_length)
+            .field("pooling", &self.pooling)
+            .field("normalize", &self.normalize)
+            .field("threads", &self.threads)
+            .field("inter_threads", &self.inter_threads)
+            .field("padding", &self.padding)
+            .field("truncation_direction", &self.truncation_direction)
+            .field("output_key", &self.output_key)
+            .field("sort_by_length", &self.sort_by_length)
+            .field("on_truncation", &self.on_truncation)
+            .field("dedicated_thread_pool", &self.dedicated_thread_pool)
+            .field("add_special_tokens", &self.add_special_tokens)
+            .field("pad_token", &self.pad_token)
+            .field("pad_id", &self.pad_id)
+            .field("has_pooler", &self.has_pooler)
+            .field("instruction_template", &self.instruction_template)
+            .field("warmup", &self.warmup)
+            .field("adaptive_oom_retry", &self.adaptive_oom_retry)
+            .field("on_empty_text", &self.on_empty_text)
+            .field("optimization_level", &self.optimization_level)
+            .field("enable_profiling", &self.enable_profiling)
+            .field("max_concurrent_batches", &self.max_concurrent_batches)
+            .field(
+                "allow_exceed_model_max_length",
+                &self.allow_exceed_model_max_length,
+            )
+            .field("normalize_before_pooling", &self.normalize_before_pooling)
+            .field("gpu_mem_limit", &self.gpu_mem_limit)
+            .field("activation", &self.activation)
+            .field(
+                "token_filter",
+                &self.token_filter.as_ref().map(|_| "<callback>"),
+            )
+            .finish()
+    }
+}
+
+impl Default for InitOptionsUserDefined {
+    fn default() -> Self {
+        Self {
+            execution_providers: Default::default(),
+            max_length: DEFAULT_MAX_LENGTH,
+            pooling: Pooling::default(),
+            normalize: Norm::L2,
+            threads: None,
+            inter_threads: None,
+            padding: Padding::default(),
+            truncation_direction: TruncationDirection::default(),
+            output_key: None,
+            sort_by_length: false,
+            on_truncation: OnTruncation::default(),
+            dedicated_thread_pool: false,
+            add_special_tokens: true,
+            pad_token: None,
+            pad_id: None,
+            has_pooler: false,
+            instruction_template: None,
+            warmup: false,
+            adaptive_oom_retry: false,
+            on_empty_text: OnEmptyText::default(),
+            optimization_level: OptimizationLevel::default(),
+            enable_profiling: None,
+            max_concurrent_batches: None,
+            allow_exceed_model_max_length: false,
+            normalize_before_pooling: false,
+            gpu_mem_limit: None,
+            activation: Activation::default(),
+            token_filter: None,
+        }
+    }
+}
+
+/// Convert InitOptions to InitOptionsUserDefined
+///
+/// This is useful for when the user wants to use the same options for both the default and user-defined models
+impl From<InitOptions> for InitOptionsUserDefined {
+    fn from(options: InitOptions) -> Self {
+        InitOptionsUserDefined {
+            execution_providers: options.execution_providers,
+            max_length: options.max_length,
+            pooling: options.pooling,
+            normalize: options.normalize,
+            threads: options.threads,
+            inter_threads: options.inter_threads,
+            padding: options.padding,
+            truncation_direction: options.truncation_direction,
+            output_key: None,
+            sort_by_length: options.sort_by_length,
+            on_truncation: options.on_truncation,
+            dedicated_thread_pool: options.dedicated_thread_pool,
+            add_special_tokens: options.add_special_tokens,
+            pad_token: None,
+            pad_id: None,
+            has_pooler: false,
+            instruction_template: None,
+            warmup: options.warmup,
+            adaptive_oom_retry: options.adaptive_oom_retry,
+            on_empty_text: options.on_empty_text,
+            optimization_level: options.optimization_level,
+            enable_profiling: options.enable_profiling,
+            max_concurrent_batches: options.max_concurrent_batches,
+            allow_exceed_model_max_length: options.allow_exceed_model_max_length,
+            normalize_before_pooling: options.normalize_before_pooling,
+            gpu_mem_limit: options.gpu_mem_limit,
+            activation: options.activation,
+            token_filter: options.token_filter,
+        }
+    }
+}
+
+/// Struct for "bring your own" embedding models
+///
+/// The onnx_file and tokenizer_files are expecting the files' bytes.
+/// The onnx_file can be compressed with gzip or zstd, which requires the `compression` feature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserDefinedEmbeddingModel {
+    pub onnx_file: Vec<u8>,
+    pub tokenizer_files: TokenizerFiles,
+}
+
+/// Paths of the files of a "bring your own" embedding model
+///
+/// Unlike with UserDefinedEmbeddingModel, the onnx file is loaded by ONNX Runtime from its path,
+/// without being read into memory first. This is preferable for large models
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserDefinedEmbeddingModelFiles {
+    pub onnx_file: PathBuf,
+    pub tokenizer_file: PathBuf,
+    pub config_file: PathBuf,
+    pub special_tokens_map_file: PathBuf,
+    pub tokenizer_config_file: PathBuf,
+}
+
+impl UserDefinedEmbeddingModelFiles {
+    /// Use the files of a model directory laid out like the Hugging Face repositories
+    ///
+    /// The onnx file is expected at `model.onnx`, next to `tokenizer.json`, `config.json`,
+    /// `special_tokens_map.json` and `tokenizer_config.json`
+    pub fn from_dir(dir: &Path) -> Self {
+        Self {
+            onnx_file: dir.join("model.onnx"),
+            tokenizer_file: dir.join("tokenizer.json"),
+            config_file: dir.join("config.json"),
+            special_tokens_map_file: dir.join("special_tokens_map.json"),
+            tokenizer_config_file: dir.join("tokenizer_config.json"),
+        }
+    }
+
+    /// Read the tokenizer files, which are small enough to be held in memory
+    fn read_tokenizer_files(&self) -> Result<TokenizerFiles> {
+        let read = |file: &PathBuf| {
+            read_file_to_bytes(file).with_context(|| format!("Failed to read {}", file.display()))
+        };
+        Ok(TokenizerFiles {
+            tokenizer_file: read(&self.tokenizer_file)?,
+            config_file: read(&self.config_file)?,
+            special_tokens_map_file: read(&self.special_tokens_map_file)?,
+            tokenizer_config_file: read(&self.tokenizer_config_file)?,
+        })
+    }
+}
+
+/// Reusable buffers of the token ids, attention masks and token type ids of the batches
+///
+/// Passed to TextEmbedding::embed_with_scratch, the buffers grow to the largest batch and are reused
+/// by the following batches and calls, instead of being allocated for every batch
+#[derive(Debug, Default)]
+pub struct EmbedScratch {
+    ids: Vec<i64>,
+    mask: Vec<i64>,
+    type_ids: Vec<i64>,
+}
+
+impl EmbedScratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of tokens of a batch the buffers can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.ids
+            .capacity()
+            .min(self.mask.capacity())
+            .min(self.type_ids.capacity())
+    }
+
+    /// Move the buffers into arrays of the given shape, leaving the scratch empty until they are restored
+    fn arrays(
+        &mut self,
+        shape: (usize, usize),
+    ) -> Result<(ArcArray2<i64>, ArcArray2<i64>, ArcArray2<i64>)> {
+        let array = |buffer: &mut Vec<i64>| -> Result<ArcArray2<i64>> {
+            Ok(Array::from_shape_vec(shape, std::mem::take(buffer))?.into_shared())
+        };
+        Ok((
+            array(&mut self.ids)?,
+            array(&mut self.mask)?,
+            array(&mut self.type_ids)?,
+        ))
+    }
+
+    /// Take the buffers back from the arrays, which are not copied once ONNX Runtime released them
+    fn restore(&mut self, ids: ArcArray2<i64>, mask: ArcArray2<i64>, type_ids: ArcArray2<i64>) {
+        self.ids = ids.into_owned().into_raw_vec();
+        self.mask = mask.into_owned().into_raw_vec();
+        self.type_ids = type_ids.into_owned().into_raw_vec();
+    }
+}
+
+/// Rust representation of the TextEmbedding model
+pub struct TextEmbedding {
+    pub(crate) tokenizer: Tokenizer,
+    session: Session,
+    need_token_type_ids: bool,
+    need_position_ids: bool,
+    pooling: Pooling,
+    normalize: Norm,
+    output_dimension: Option<usize>,
+    query_prefix: String,
+    passage_prefix: String,
+    output_key: Option<String>,
+    active_execution_providers: Vec<String>,
+    sort_by_length: bool,
+    on_truncation: OnTruncation,
+    thread_pool: Option<ThreadPool>,
+    add_special_tokens: bool,
+    has_pooler: bool,
+    instruction_template: Option<String>,
+    max_length: usize,
+    adaptive_oom_retry: bool,
+    on_empty_text: OnEmptyText,
+    profiling_file: Option<PathBuf>,
+    batch_limiter: Option<BatchLimiter>,
+    normalize_before_pooling: bool,
+    activation: Activation,
+    token_filter: Option<TokenFilter>,
+}
+
+impl TextEmbedding {
+    /// Try to generate a new TextEmbedding Instance
+    ///
+    /// Uses the highest level of Graph optimization, unless `optimization_level` is set
+    ///
+    /// Uses the total number of CPUs available as the number of intra-threads, unless `threads` is set
+    pub fn try_new(options: InitOptions) -> Result<Self, FastEmbedError> {
+        let InitOptions {
+            model_name,
+            execution_providers,
+            max_length,
+            cache_dir,
+            show_download_progress,
+            pooling,
+            normalize,
+            output_dimension,
+            threads,
+            inter_threads,
+            offline,
+            hf_token,
+            endpoint,
+            user_agent,
+            padding,
+            truncation_direction,
+            progress_callback,
+            quantization,
+            fallback_callback,
+            sort_by_length,
+            on_truncation,
+            dedicated_thread_pool,
+            add_special_tokens,
+            verify_checksums,
+            download_retries,
+            retry_backoff,
+            warmup,
+            adaptive_oom_retry,
+            on_empty_text,
+            optimization_level,
+            enable_profiling,
+            max_concurrent_batches,
+            allow_exceed_model_max_length,
+            normalize_before_pooling,
+            gpu_mem_limit,
+            activation,
+            token_filter,
+        } = options;
+
+        let model_info = TextEmbedding::get_model_info(&model_name);
+        if let Some(dim) = output_dimension {
+            if dim == 0 || dim > model_info.dim {
+                return Err(invalid_input(format!(
+                    "Invalid output_dimension {} for {}, expected a value between 1 and {}",
+                    dim, model_name, model_info.dim
+                )));
+            }
+        }
+
+        let model_repo = retrieve_model(
+            model_info.model_code.clone(),
+            cache_dir.clone(),
+            show_download_progress,
+            offline,
+            HubConfig {
+                hf_token,
+                endpoint,
+                user_agent,
+            },
+            progress_callback,
+        )?
+        .with_retry_policy(RetryPolicy {
+            retries: download_retries,
+            backoff: retry_backoff,
+        });
+
+        let model_file_name = model_info
+            .model_file_for(quantization)
+            .with_context(|| {
+                FastEmbedError::UnsupportedModel(
+                    format!(
+                        "{} does not provide a {:?} quantized variant",
+                        model_name, quantization
+                    )
+                    .into(),
+                )
+            })?
+            .to_string();
+        let model_file_reference = model_repo.get_model_files(
+            &model_file_name,
+            &model_info.additional_files,
+            &model_name,
+        )?;
+        if verify_checksums {
+            verify_model_file(&model_info, &model_file_name, &model_file_reference)?;
+        }
+
+        let (session_builder, active_execution_providers) = session_builder(
+            with_gpu_mem_limit(execution_providers, gpu_mem_limit),
+            threads,
+            inter_threads,
+            optimization_level,
+            fallback_callback.as_ref(),
+        )?;
+        let session = with_profiling(session_builder, enable_profiling.as_deref())?
+            .with_model_from_file(model_file_reference)?;
+
+        let tokenizer = load_tokenizer_hf_hub(
+            model_repo,
+            max_length,
+            padding,
+            truncation_direction,
+            allow_exceed_model_max_length,
+        )?;
+        let mut text_embedding = Self::new(tokenizer, session, pooling, normalize);
+        text_embedding.output_dimension = output_dimension;
+        text_embedding.active_execution_providers = active_execution_providers;
+        text_embedding.query_prefix = model_info.query_prefix;
+        text_embedding.passage_prefix = model_info.passage_prefix;
+        text_embedding.has_pooler = model_info.has_pooler;
+        text_embedding.instruction_template = model_info.instruction_template;
+        text_embedding.sort_by_length = sort_by_length;
+        text_embedding.on_truncation = on_truncation;
+        text_embedding.thread_pool = build_thread_pool(dedicated_thread_pool, threads)?;
+        text_embedding.add_special_tokens = add_special_tokens;
+        text_embedding.adaptive_oom_retry = adaptive_oom_retry;
+        text_embedding.on_empty_text = on_empty_text;
+        text_embedding.profiling_file = enable_profiling;
+        text_embedding.normalize_before_pooling = normalize_before_pooling;
+        text_embedding.activation = activation;
+        text_embedding.token_filter = token_filter;
+        text_embedding.batch_limiter = max_concurrent_batches.map(BatchLimiter::new).transpose()?;
+        if warmup {
+            text_embedding.warmup()?;
+        }
+        Ok(text_embedding)
+    }
+
+    /// Create a TextEmbedding instance from model files provided by the user.
+    ///
+    /// This can be used for 'bring your own' embedding models
+    pub fn try_new_from_user_defined(
+        model: UserDefinedEmbeddingModel,
+        options: InitOptionsUserDefined,
+    ) -> Result<Self, FastEmbedError> {
+        let onnx_file = decompress_model(&model.onnx_file)?;
+        Ok(Self::try_new_from_user_defined_parts(
+            model.tokenizer_files,
+            options,
+            |session_builder| session_builder.with_model_from_memory(&onnx_file),
+        )?)
+    }
+
+    /// Create a TextEmbedding instance from the paths of model files provided by the user.
+    ///
+    /// The onnx file is loaded from its path rather than from bytes, which avoids holding a copy of large models in memory
+    pub fn try_new_from_files(
+        model: UserDefinedEmbeddingModelFiles,
+        options: InitOptionsUserDefined,
+    ) -> Result<Self, FastEmbedError> {
+        let tokenizer_files = model.read_tokenizer_files()?;
+        Ok(Self::try_new_from_user_defined_parts(
+            tokenizer_files,
+            options,
+            |session_builder| session_builder.with_model_from_file(&model.onnx_file),
+        )?)
+    }
+
+    /// Create a TextEmbedding instance from an ONNX Runtime session built by the user
+    ///
+    /// This allows configuring the session with options that InitOptionsUserDefined does not cover,
+    /// such as memory arenas, profiling or custom operators. The other options keep their default values.
+    /// The execution providers of the session are unknown, so `active_execution_providers` is empty
+    pub fn from_session(
+        session: Session,
+        tokenizer_files: TokenizerFiles,
+        max_length: usize,
+    ) -> Result<Self, FastEmbedError> {
+        let tokenizer = load_tokenizer(
+            tokenizer_files,
+            max_length,
+            Padding::default(),
+            TruncationDirection::default(),
+            None,
+            None,
+            false,
+        )?;
+        Ok(Self::new(tokenizer, session, Pooling::default(), Norm::L2))
+    }
+
+    /// Shared by the constructors of user-defined models, which only differ in how the onnx file is loaded
+    fn try_new_from_user_defined_parts(
+        tokenizer_files: TokenizerFiles,
+        options: InitOptionsUserDefined,
+        load_session: impl FnOnce(SessionBuilder) -> ort::Result<Session>,
+    ) -> Result<Self> {
+        let InitOptionsUserDefined {
+            execution_providers,
+            max_length,
+            pooling,
+            normalize,
+            threads,
+            inter_threads,
+            padding,
+            truncation_direction,
+            output_key,
+            sort_by_length,
+            on_truncation,
+            dedicated_thread_pool,
+            add_special_tokens,
+            pad_token,
+            pad_id,
+            has_pooler,
+            instruction_template,
+            warmup,
+            adaptive_oom_retry,
+            on_empty_text,
+            optimization_level,
+            enable_profiling,
+            max_concurrent_batches,
+            allow_exceed_model_max_length,
+            normalize_before_pooling,
+            gpu_mem_limit,
+            activation,
+            token_filter,
+        } = options;
+
+        let (session_builder, active_execution_providers) = session_builder(
+            with_gpu_mem_limit(execution_providers, gpu_mem_limit),
+            threads,
+            inter_threads,
+            optimization_level,
+            None,
+        )?;
+        let session = load_session(with_profiling(
+            session_builder,
+            enable_profiling.as_deref(),
+        )?)?;
+
+        let tokenizer = load_tokenizer(
+            tokenizer_files,
+            max_length,
+            padding,
+            truncation_direction,
+            pad_token,
+            pad_id,
+            allow_exceed_model_max_length,
+        )?;
+        let mut text_embedding = Self::new(tokenizer, session, pooling, normalize);
+        text_embedding.output_key = output_key;
+        text_embedding.has_pooler = has_pooler;
+        text_embedding.instruction_template = instruction_template;
+        text_embedding.sort_by_length = sort_by_length;
+        text_embedding.on_truncation = on_truncation;
+        text_embedding.thread_pool = build_thread_pool(dedicated_thread_pool, threads)?;
+        text_embedding.add_special_tokens = add_special_tokens;
+        text_embedding.adaptive_oom_retry = adaptive_oom_retry;
+        text_embedding.on_empty_text = on_empty_text;
+        text_embedding.profiling_file = enable_profiling;
+        text_embedding.normalize_before_pooling = normalize_before_pooling;
+        text_embedding.activation = activation;
+        text_embedding.token_filter = token_filter;
+        text_embedding.batch_limiter = max_concurrent_batches.map(BatchLimiter::new).transpose()?;
+        text_embedding.active_execution_providers = active_execution_providers;
+        if warmup {
+            text_embedding.warmup()?;
+        }
+        Ok(text_embedding)
+    }
+
+    /// Private method to return an instance
+    fn new(tokenizer: Tokenizer, session: Session, pooling: Pooling, normalize: Norm) -> Self {
+        let need_token_type_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+        let need_position_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "position_ids");
+        // The tokenizer truncates to max_length, once clamped to the model_max_length of the model
+        let max_length = tokenizer
+            .get_truncation()
+            .map_or(DEFAULT_MAX_LENGTH, |truncation| truncation.max_length);
+        Self {
+            tokenizer,
+            session,
+            need_token_type_ids,
+            need_position_ids,
+            pooling,
+            normalize,
+            output_dimension: None,
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+            output_key: None,
+            active_execution_providers: Vec::new(),
+            sort_by_length: false,
+            on_truncation: OnTruncation::default(),
+            thread_pool: None,
+            add_special_tokens: true,
+            has_pooler: false,
+            instruction_template: None,
+            max_length,
+            adaptive_oom_retry: false,
+            on_empty_text: OnEmptyText::default(),
+            profiling_file: None,
+            batch_limiter: None,
+            normalize_before_pooling: false,
+            activation: Activation::default(),
+            token_filter: None,
+        }
+    }
+
+    /// Run a dummy inference, which triggers the allocations and the graph finalization of ONNX Runtime
+    fn warmup(&self) -> Result<()> {
+        self.embed_batch(&["warmup"], |index| index, Norm::None, &self.tokenizer)
+            .context("Failed to warm up the model")?;
+        Ok(())
+    }
+
+    /// Maximum number of tokens of the inputs, after clamping the requested max_length
+    /// to the model_max_length of the tokenizer config
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Number of tokens in the vocabulary of the tokenizer, including the added tokens
+    pub fn vocab_size(&self) -> usize {
+        self.tokenizer.get_vocab_size(true)
+    }
+
+    /// Ids of the padding, classification, separator, unknown and mask tokens of the tokenizer
+    pub fn special_token_ids(&self) -> SpecialTokenIds {
+        SpecialTokenIds::from_tokenizer(&self.tokenizer)
+    }
+
+    /// Names, shapes and types of the inputs and outputs of the model, to diagnose the exports of user-defined models
+    pub fn model_signature(&self) -> ModelSignature {
+        ModelSignature::from_session(&self.session)
+    }
+
+    /// Estimate of the memory needed by the tensors of the largest batch `embed` runs for the texts, in bytes
+    ///
+    /// Counts the padded input tensors, 8 bytes per token for each input of the model, and the f32 output tensor.
+    /// The intermediate results of the graph in ONNX Runtime come on top of it, and so do the other batches running
+    /// in parallel. The output is left out for the models that do not declare their hidden size
+    pub fn estimate_batch_memory<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: &[S],
+        batch_size: Option<usize>,
+    ) -> Result<usize, FastEmbedError> {
+        // Determine the batch size, default if not specified
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+
+        // Number of texts and padded length of each batch
+        let lengths = self.token_lengths(texts)?;
+        let batch_shapes: Vec<(usize, usize)> = if self.sort_by_length {
+            length_sorted_batches(&lengths, batch_size)
+                .iter()
+                .map(|batch| {
+                    let longest = batch.iter().map(|&index| lengths[index]).max();
+                    (batch.len(), longest.unwrap_or(0))
+                })
+                .collect()
+        } else {
+            lengths
+                .chunks(batch_size)
+                .map(|batch| (batch.len(), batch.iter().copied().max().unwrap_or(0)))
+                .collect()
+        };
+
+        let inputs = 2 + self.need_token_type_ids as usize + self.need_position_ids as usize;
+        let output_dimensions = self.output_dimensions();
+        let hidden_size = output_dimensions
+            .and_then(|dimensions| dimensions.last())
+            .filter(|&&size| size > 0)
+            .map_or(0, |&size| size as usize);
+        // The models exported with their pooling output one embedding per text instead of one per token
+        let pooled_output = output_dimensions.is_some_and(|dimensions| dimensions.len() == 2);
+
+        Ok(batch_shapes
+            .into_iter()
+            .map(|(texts, length)| {
+                let input_size = texts * length * std::mem::size_of::<i64>() * inputs;
+                let output_values = if pooled_output {
+                    texts * hidden_size
+                } else {
+                    texts * length * hidden_size
+                };
+                input_size + output_values * std::mem::size_of::<f32>()
+            })
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// Declared dimensions of the output holding the embeddings, -1 for the dynamic ones
+    fn output_dimensions(&self) -> Option<&Vec<i64>> {
+        let outputs = &self.session.outputs;
+        let output = match &self.output_key {
+            Some(output_key) => outputs.iter().find(|output| &output.name == output_key),
+            None => outputs
+                .iter()
+                .find(|output| output.name == "last_hidden_state")
+                .or_else(|| outputs.first()),
+        }?;
+        output.output_type.tensor_dimensions()
+    }
+
+    /// Names of the execution providers registered for the model, in order of priority
+    ///
+    /// The CPU provider is always included, as ONNX Runtime falls back to it for the nodes
+    /// or the models the other providers can not run. Models created with `from_session` report none
+    pub fn active_execution_providers(&self) -> Vec<String> {
+        self.active_execution_providers.clone()
+    }
+
+    /// Stop the profiler enabled by `enable_profiling` and write its JSON trace, returning the path of the trace
+    ///
+    /// The trace holds the timings of the inferences run since the model was created
+    #[cfg(feature = "profiling")]
+    pub fn end_profiling(&self) -> Result<PathBuf, FastEmbedError> {
+        let profiling_file = self
+            .profiling_file
+            .clone()
+            .context("Profiling is not enabled for this model")?;
+        let trace_file = self.session.end_profiling()?;
+        std::fs::rename(&trace_file, &profiling_file).with_context(|| {
+            format!(
+                "Failed to move the profile {} to {}",
+                trace_file,
+                profiling_file.display()
+            )
+        })?;
+        Ok(profiling_file)
+    }
+
+    /// Name of the preferred execution provider that was registered, which runs all the nodes it supports
+    ///
+    /// The first provider of the chain that could be registered, or the CPU if none could.
+    /// None for the models created with `from_session`
+    pub fn selected_execution_provider(&self) -> Option<&str> {
+        self.active_execution_providers.first().map(String::as_str)
+    }
+
+    /// Retrieve a list of supported models
+    pub fn list_supported_models() -> Vec<ModelInfo> {
+        models_list()
+    }
+
+    /// Retrieve the supported models matching the predicate
+    ///
+    /// For example, `TextEmbedding::list_supported_models_filtered(|info| info.dim <= 384)` lists the small models
+    pub fn list_supported_models_filtered(
+        predicate: impl Fn(&ModelInfo) -> bool,
+    ) -> Vec<ModelInfo> {
+        models_list()
+            .into_iter()
+            .filter(|model_info| predicate(model_info))
+            .collect()
+    }
+
+    /// Total size in bytes of the files in the model cache
+    ///
+    /// Returns 0 if the cache directory does not exist
+    pub fn cache_size(cache_dir: &Path) -> Result<u64, FastEmbedError> {
+        if !cache_dir.exists() {
+            return Ok(0);
+        }
+        Ok(dir_size(cache_dir)
+            .with_context(|| format!("Failed to read the cache at {}", cache_dir.display()))?)
+    }
+
+    /// Remove the models downloaded to the cache
+    ///
+    /// Only the model directories are removed, any other file in `cache_dir` is kept
+    pub fn clear_cache(cache_dir: &Path) -> Result<(), FastEmbedError> {
+        if !cache_dir.exists() {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(cache_dir)? {
+            let path = entry?.path();
+            let is_model_dir = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("models--"));
+            if is_model_dir && path.is_dir() {
+                std::fs::remove_dir_all(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// List the supported models with files in the cache
+    ///
+    /// A model is listed as soon as one of its files was downloaded, even if others are missing
+    pub fn cached_models(cache_dir: &Path) -> Vec<EmbeddingModel> {
+        models_list()
+            .into_iter()
+            .filter(|model_info| {
+                cached_model_dir(cache_dir, &model_info.model_code)
+                    .join("snapshots")
+                    .read_dir()
+                    .is_ok_and(|mut snapshots| snapshots.next().is_some())
+            })
+            .map(|model_info| model_info.model)
+            .collect()
+    }
+
+    /// Whether the model file, its `additional_files` and the tokenizer files are all in the cache
+    ///
+    /// Checks the full precision model file, without any download or network access. The model files
+    /// retrieved from their alternate path in the repository count as cached
+    pub fn is_model_cached(model: &EmbeddingModel, cache_dir: &Path) -> bool {
+        let model_info = TextEmbedding::get_model_info(model);
+        let model_files = std::iter::once(model_info.model_file.as_str())
+            .chain(model_info.additional_files.iter().map(String::as_str));
+        files_cached(
+            cache_dir,
+            &model_info.model_code,
+            model_files,
+            TOKENIZER_FILE_NAMES.into_iter(),
+        )
+    }
+
+    /// Get ModelInfo from EmbeddingModel
+    pub fn get_model_info(model: &EmbeddingModel) -> ModelInfo {
+        TextEmbedding::list_supported_models()
+            .into_iter()
+            .find(|m| &m.model == model)
+            .expect("Model not found.")
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts
+    ///
+    /// The embeddings are divided by the `normalize` norm of the options, L2 by default.
+    /// With `sort_by_length`, the batches are made of texts of similar lengths
+    // Generic type to accept String, &str, OsString, &OsStr
+    pub fn embed<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        Ok(self.embed_internal(
+            texts,
+            batch_size,
+            self.normalize,
+            &self.tokenizer,
+            None,
+            None,
+        )?)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts as the rows of a single array
+    ///
+    /// The texts are embedded like with `embed`, into an array of shape `[texts.len(), dim]` built from the
+    /// outputs of the model without collecting each embedding into a Vec, for further processing with ndarray.
+    /// The rows of the texts skipped by `on_empty_text` are zeros
+    pub fn embed_array<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Array2<f32>, FastEmbedError> {
+        Ok(self.embed_internal(
+            texts,
+            batch_size,
+            self.normalize,
+            &self.tokenizer,
+            None,
+            None,
+        )?)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts as a single contiguous buffer
+    ///
+    /// Returns the `texts.len() * dim` values of the embeddings in row-major order, along with `dim`,
+    /// to hand them over to C or numpy buffers. The embedding of the text `i` is at `[i * dim..(i + 1) * dim]`,
+    /// the values of the texts skipped by `on_empty_text` are zeros
+    pub fn embed_flat<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<(Vec<f32>, usize), FastEmbedError> {
+        // The values come from the pooled arrays of the batches, the array is only copied if it is not row-major
+        let embeddings = self.embed_array(texts, batch_size)?;
+        let dim = embeddings.ncols();
+        let embeddings = if embeddings.is_standard_layout() {
+            embeddings
+        } else {
+            embeddings.as_standard_layout().into_owned()
+        };
+        Ok((embeddings.into_raw_vec(), dim))
+    }
+
+    /// Method to generate embeddings for a Vec of pairs of texts, such as queries and documents
+    ///
+    /// Each pair is encoded as a single sequence of two segments, with the special tokens and the
+    /// `token_type_ids` of the model's pair template, then pooled and normalized like in `embed`
+    pub fn embed_pairs<S: AsRef<str> + Send + Sync>(
+        &self,
+        pairs: Vec<(S, S)>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        // Determine the batch size, default if not specified
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+
+        self.install(|| {
+            let output = pairs
+                .par_chunks(batch_size)
+                .enumerate()
+                .map(|(batch_index, batch)| {
+                    let inputs: Vec<(&str, &str)> = batch
+                        .iter()
+                        .map(|(first, second)| (first.as_ref(), second.as_ref()))
+                        .collect();
+                    let embeddings = self.embed_inputs(
+                        inputs,
+                        |index| batch_index * batch_size + index,
+                        self.normalize,
+                        &self.tokenizer,
+                    )?;
+                    Ok(embeddings
+                        .rows()
+                        .into_iter()
+                        .map(|row| row.to_vec())
+                        .collect())
+                })
+                .collect::<Result<Vec<Vec<_>>>>()?
+                .into_iter()
+                .flatten()
+                .collect();
+
+            Ok(output)
+        })
+    }
+
+    /// Method to generate the per-token embeddings of the texts, the hidden states of the model before pooling
+    ///
+    /// Every array holds one row per token of its text, padding excluded, as used for late interaction
+    /// such as ColBERT. The token embeddings are neither normalized nor truncated to `output_dimension`
+    pub fn embed_tokens_output<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Array2<f32>>, FastEmbedError> {
+        // Determine the batch size, default if not specified
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+
+        self.install(|| {
+            let output = texts
+                .par_chunks(batch_size)
+                .enumerate()
+                .map(|(batch_index, batch)| {
+                    let inputs: Vec<&str> = batch.iter().map(|text| text.as_ref()).collect();
+                    let (mut inputs_ids_array, mut attention_mask_array, mut token_type_ids_array) =
+                        self.encode_inputs(
+                            inputs,
+                            |index| batch_index * batch_size + index,
+                            &self.tokenizer,
+                        )?;
+                    let outputs = self.run_session(
+                        &mut inputs_ids_array,
+                        &mut attention_mask_array,
+                        &mut token_type_ids_array,
+                    )?;
+
+                    let output_data = self
+                        .select_output(&outputs, false)?
+                        .extract_tensor::<f32>()?;
+                    let output_view = output_data.view();
+                    let token_embeddings = output_view
+                        .view()
+                        .into_dimensionality::<Ix3>()
+                        .context(FastEmbedError::ShapeMismatch(
+                        String::from(
+                            "The model output is already pooled, it has no per-token embeddings",
+                        )
+                        .into(),
+                    ))?;
+
+                    // Keep the tokens of the attention mask, wherever the padding is
+                    Ok(token_embeddings
+                        .outer_iter()
+                        .zip(attention_mask_array.outer_iter())
+                        .map(|(embeddings, mask)| {
+                            let tokens: Vec<usize> = mask
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, &attended)| attended == 1)
+                                .map(|(index, _)| index)
+                                .collect();
+                            embeddings.select(Axis(0), &tokens)
+                        })
+                        .collect())
+                })
+                .collect::<Result<Vec<Vec<_>>>>()?
+                .into_iter()
+                .flatten()
+                .collect();
+
+            Ok(output)
+        })
+    }
+
+    /// Method to generate sentence embeddings for texts tokenized beforehand
+    ///
+    /// The token ids must include the special tokens expected by the model. The tokenizer is skipped,
+    /// the sequences of a batch are padded to the longest one with the pad token of the model
+    /// and masked accordingly. Sequences longer than `max_length` are rejected rather than truncated,
+    /// and so are the empty ones. The `token_filter` is not applied to them
+    pub fn embed_tokens(
+        &self,
+        token_batches: Vec<Vec<u32>>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        // Determine the batch size, default if not specified
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+        // A sequence without any token has nothing to pool
+        if let Some(index) = token_batches.iter().position(Vec::is_empty) {
+            return Err(invalid_input(format!(
+                "The token sequence {} is empty",
+                index
+            )));
+        }
+        if let Some(truncation) = self.tokenizer.get_truncation() {
+            if let Some(index) = token_batches
+                .iter()
+                .position(|tokens| tokens.len() > truncation.max_length)
+            {
+                return Err(invalid_input(format!(
+                    "The token sequence {} has {} tokens, more than the maximum length of {}",
+                    index,
+                    token_batches[index].len(),
+                    truncation.max_length
+                )));
+            }
+        }
+
+        let padding = self.tokenizer.get_padding();
+        let pad_id = padding.map_or(0, |padding| padding.pad_id) as i64;
+        let fixed_length = match padding.map(|padding| &padding.strategy) {
+            Some(PaddingStrategy::Fixed(length)) => *length,
+            _ => 0,
+        };
+
+        self.install(|| {
+            let output = token_batches
+                .par_chunks(batch_size)
+                .map(|batch| {
+                    let longest = batch.iter().map(Vec::len).max().unwrap_or(0);
+                    let encoding_length = longest.max(fixed_length);
+                    let shape = (batch.len(), encoding_length);
+
+                    let mut inputs_ids_array = Array2::from_elem(shape, pad_id);
+                    let mut attention_mask_array = Array2::zeros(shape);
+                    for (row, tokens) in batch.iter().enumerate() {
+                        for (column, &token) in tokens.iter().enumerate() {
+                            inputs_ids_array[[row, column]] = token as i64;
+                            attention_mask_array[[row, column]] = 1;
+                        }
+                    }
+
+                    let embeddings = self.run_inference(
+                        &mut inputs_ids_array.into_shared(),
+                        &mut attention_mask_array.into_shared(),
+                        &mut Array2::zeros(shape).into_shared(),
+                        self.normalize,
+                    )?;
+                    Ok(embeddings
+                        .rows()
+                        .into_iter()
+                        .map(|row| row.to_vec())
+                        .collect())
+                })
+                .collect::<Result<Vec<Vec<_>>>>()?
+                .into_iter()
+                .flatten()
+                .collect();
+
+            Ok(output)
+        })
+    }
+
+    /// Method to generate sentence embeddings for texts carrying a payload, such as an id or a source
+    ///
+    /// The embeddings are returned with the payload of their text, in the order of the items
+    pub fn embed_with<T, S: AsRef<str> + Send + Sync>(
+        &self,
+        items: Vec<(T, S)>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<(T, Embedding)>, FastEmbedError> {
+        let (payloads, texts): (Vec<T>, Vec<S>) = items.into_iter().unzip();
+        let embeddings = self.embed(texts, batch_size)?;
+        Ok(payloads.into_iter().zip(embeddings).collect())
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts, writing them into a preallocated buffer
+    ///
+    /// The embedding of the text `i` is written to `out[i * dim..(i + 1) * dim]`, batch by batch, so that
+    /// the embeddings are never all held in memory. `out` can be backed by a memory-mapped file to build
+    /// large indices. The texts skipped by `on_empty_text` are written as zeros, and the values of `out`
+    /// past the last embedding are left as they are. The batches are formed in the order of the texts,
+    /// regardless of `sort_by_length`
+    pub fn embed_into<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+        out: &mut [f32],
+        dim: usize,
+    ) -> Result<(), FastEmbedError> {
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+        if dim == 0 {
+            return Err(invalid_input("The embedding dimension must be at least 1"));
+        }
+        let needed = texts
+            .len()
+            .checked_mul(dim)
+            .filter(|&needed| needed <= out.len())
+            .with_context(|| {
+                FastEmbedError::ShapeMismatch(
+                    format!(
+                        "The output holds {} values, too few for {} embeddings of dimension {}",
+                        out.len(),
+                        texts.len(),
+                        dim
+                    )
+                    .into(),
+                )
+            })?;
+
+        let is_empty = |text: &S| text.as_ref().trim().is_empty();
+        if matches!(self.on_empty_text, OnEmptyText::Error) {
+            if let Some(index) = texts.iter().position(is_empty) {
+                return Err(invalid_input(format!("The text {} is empty", index)));
+            }
+        }
+        let skip_empty = matches!(self.on_empty_text, OnEmptyText::Skip);
+
+        Ok(self.install(|| {
+            texts
+                .par_chunks(batch_size)
+                .zip(out[..needed].par_chunks_mut(batch_size.saturating_mul(dim)))
+                .enumerate()
+                .try_for_each(|(batch_index, (batch, out))| {
+                    let kept: Vec<usize> = (0..batch.len())
+                        .filter(|&index| !(skip_empty && is_empty(&batch[index])))
+                        .collect();
+                    let kept_texts: Vec<&str> =
+                        kept.iter().map(|&index| batch[index].as_ref()).collect();
+                    let embeddings = if kept_texts.is_empty() {
+                        Vec::new()
+                    } else {
+                        self.embed_batch_retrying(
+                            &kept_texts,
+                            |index| batch_index * batch_size + kept[index],
+                            self.normalize,
+                            &self.tokenizer,
+                        )?
+                    };
+
+                    out.fill(0.0);
+                    for (&index, embedding) in kept.iter().zip(&embeddings) {
+                        if embedding.len() != dim {
+                            bail!(FastEmbedError::ShapeMismatch(
+                                format!(
+                                    "The model outputs embeddings of dimension {}, not {}",
+                                    embedding.len(),
+                                    dim
+                                )
+                                .into()
+                            ));
+                        }
+                        out[index * dim..(index + 1) * dim].copy_from_slice(embedding);
+                    }
+                    Ok(())
+                })
+        })?)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts, embedding the duplicates only once
+    ///
+    /// The texts that only differ by their whitespace are duplicates, the first of them is embedded and its
+    /// embedding is returned at the position of every duplicate. The indices given to the truncation callback
+    /// are the ones of the unique texts
+    pub fn embed_dedup<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        let mut unique_indices: HashMap<String, usize> = HashMap::new();
+        let mut unique_texts: Vec<&str> = Vec::new();
+        let positions: Vec<usize> = texts
+            .iter()
+            .map(|text| {
+                let key = text
+                    .as_ref()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                *unique_indices.entry(key).or_insert_with(|| {
+                    unique_texts.push(text.as_ref());
+                    unique_texts.len() - 1
+                })
+            })
+            .collect();
+
+        let embeddings = self.embed(unique_texts, batch_size)?;
+        Ok(positions
+            .into_iter()
+            .map(|index| embeddings[index].clone())
+            .collect())
+    }
+
+    /// Method to generate the sentence embedding of a single text
+    ///
+    /// An empty text is handled by `on_empty_text` like with `embed`, a skipped one has an empty embedding
+    pub fn embed_one(&self, text: impl AsRef<str>) -> Result<Embedding, FastEmbedError> {
+        let mut embeddings =
+            self.embed_batch_with_policy(&[text], |index| index, self.normalize, &self.tokenizer)?;
+        Ok(embeddings.remove(0))
+    }
+
+    /// Method to tokenize a Vec of texts, without running the inference
+    ///
+    /// The texts are encoded exactly like in `embed`, with the special tokens if enabled, the truncation to
+    /// `max_length` and the padding, so that the encodings hold the ids, tokens and offsets the model sees
+    pub fn tokenize<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+    ) -> Result<Vec<Encoding>, FastEmbedError> {
+        let inputs = texts.iter().map(|text| text.as_ref()).collect();
+        self.tokenizer
+            .encode_batch(inputs, self.add_special_tokens)
+            .map_err(FastEmbedError::Tokenization)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts, truncated to `max_length` tokens for this call only
+    ///
+    /// The truncation is applied to a copy of the tokenizer, so that other calls running concurrently keep
+    /// the `max_length` of the options. It can only be lowered, up to the `max_length` of the model
+    pub fn embed_with_max_length<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+        max_length: usize,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        if max_length == 0 || max_length > self.max_length {
+            return Err(invalid_input(format!(
+                "The max_length must be between 1 and the max_length of the model ({}), got {}",
+                self.max_length, max_length
+            )));
+        }
+        let tokenizer = common::with_max_length(&self.tokenizer, max_length)?;
+        Ok(self.embed_internal(texts, batch_size, self.normalize, &tokenizer, None, None)?)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts, reusing the buffers of the scratch
+    ///
+    /// The token ids, attention masks and token type ids of the batches are written into the buffers
+    /// of the scratch rather than into new ones, which saves the allocations of services embedding continuously.
+    /// A scratch holds a single batch, so the batches are embedded one at a time, in the order of the texts
+    pub fn embed_with_scratch<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+        scratch: &mut EmbedScratch,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+        let is_empty = |text: &S| text.as_ref().trim().is_empty();
+        if matches!(self.on_empty_text, OnEmptyText::Error) {
+            if let Some(index) = texts.iter().position(is_empty) {
+                return Err(invalid_input(format!("The text {} is empty", index)));
+            }
+        }
+        let skip_empty = matches!(self.on_empty_text, OnEmptyText::Skip);
+
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for (batch_index, batch) in texts.chunks(batch_size).enumerate() {
+            // The skipped texts keep an empty embedding, like with `embed`
+            let mut batch_embeddings = vec![Vec::new(); batch.len()];
+            let kept: Vec<usize> = (0..batch.len())
+                .filter(|&index| !(skip_empty && is_empty(&batch[index])))
+                .collect();
+            if !kept.is_empty() {
+                let kept_texts: Vec<&str> =
+                    kept.iter().map(|&index| batch[index].as_ref()).collect();
+                let shape = self.encode_into(
+                    kept_texts,
+                    |index| batch_index * batch_size + kept[index],
+                    &self.tokenizer,
+                    scratch,
+                )?;
+                let (mut ids, mut mask, mut type_ids) = scratch.arrays(shape)?;
+                let pooled = self.run_inference(&mut ids, &mut mask, &mut type_ids, self.normalize);
+                scratch.restore(ids, mask, type_ids);
+                for (&index, row) in kept.iter().zip(pooled?.rows()) {
+                    batch_embeddings[index] = row.to_vec();
+                }
+            }
+            embeddings.extend(batch_embeddings);
+        }
+        Ok(embeddings)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts, which can be cancelled from another thread
+    ///
+    /// The flag is checked before each batch, an error is returned once it is set.
+    /// The batches that already started are completed, but their embeddings are discarded
+    pub fn embed_with_cancel<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        Ok(self.embed_internal(
+            texts,
+            batch_size,
+            self.normalize,
+            &self.tokenizer,
+            Some(&cancel),
+            None,
+        )?)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts, reporting the progress of the embedding
+    ///
+    /// The callback receives the number of completed batches and the total number of batches after each batch.
+    /// The batches run in parallel on other threads, the callback is always called from the calling thread
+    pub fn embed_with_progress<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+        // The skipped empty texts are not part of any batch
+        let embedded = match self.on_empty_text {
+            OnEmptyText::Skip => texts
+                .iter()
+                .filter(|text| !text.as_ref().trim().is_empty())
+                .count(),
+            _ => texts.len(),
+        };
+        let total_batches = embedded.div_ceil(batch_size);
+
+        let (sender, receiver) = mpsc::channel();
+        Ok(std::thread::scope(|scope| {
+            // The sender is dropped with the embedding thread, which ends the loop below
+            let embedding = scope.spawn(move || {
+                self.embed_internal(
+                    texts,
+                    Some(batch_size),
+                    self.normalize,
+                    &self.tokenizer,
+                    None,
+                    Some(&sender),
+                )
+            });
+            for (completed, _) in (1..=total_batches).zip(receiver) {
+                progress(completed, total_batches);
+            }
+            embedding
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })?)
+    }
+
+    /// Method to generate the raw pooled sentence embeddings for a Vec of texts
+    ///
+    /// Normalization is always skipped, regardless of the `normalize` option
+    pub fn embed_raw<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        Ok(self.embed_internal(texts, batch_size, Norm::None, &self.tokenizer, None, None)?)
+    }
+
+    /// Method to generate the raw pooled sentence embeddings for a Vec of texts, along with their L2 norms
+    ///
+    /// Normalization is always skipped, dividing an embedding by its norm gives the normalized embedding
+    pub fn embed_raw_with_norms<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<(Embedding, f32)>, FastEmbedError> {
+        let embeddings = self.embed_raw(texts, batch_size)?;
+        Ok(embeddings
+            .into_iter()
+            .map(|embedding| {
+                let norm = common::l2_norm(&embedding) as f32;
+                (embedding, norm)
+            })
+            .collect())
+    }
+
+    /// Method to generate half-precision sentence embeddings for a Vec of texts
+    ///
+    /// The embeddings are computed in f32 then converted, which halves their memory footprint.
+    /// f16 keeps about 3 significant decimal digits, which is usually enough for similarity
+    /// search on normalized embeddings, but the scores may differ slightly from the f32 ones
+    #[cfg(feature = "half")]
+    pub fn embed_f16<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Vec<half::f16>>, FastEmbedError> {
+        let embeddings = self.embed(texts, batch_size)?;
+        Ok(embeddings
+            .iter()
+            .map(|embedding| embedding.iter().map(|&x| half::f16::from_f32(x)).collect())
+            .collect())
+    }
+
+    /// Method to generate sentence embeddings for a Vec of search queries
+    ///
+    /// The query prefix expected by the model, such as "query: " for E5 models, is prepended to the texts
+    pub fn embed_query<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        self.embed(with_prefix(&self.query_prefix, texts), batch_size)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of passages to search in
+    ///
+    /// The passage prefix expected by the model, such as "passage: " for E5 models, is prepended to the texts
+    pub fn embed_passage<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        self.embed(with_prefix(&self.passage_prefix, texts), batch_size)
+    }
+
+    /// Method to compute the cosine similarity of every pair of texts
+    ///
+    /// Returns a matrix where the value at `[i][j]` is the similarity of the texts `i` and `j`.
+    /// The embeddings are always normalized here, so that the similarities are plain dot products.
+    /// The matrix has a row for every text, so the empty texts fail with `OnEmptyText::Skip`
+    pub fn embed_similarity_matrix<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Vec<f32>>, FastEmbedError> {
+        if matches!(self.on_empty_text, OnEmptyText::Skip) {
+            if let Some(index) = texts
+                .iter()
+                .position(|text| text.as_ref().trim().is_empty())
+            {
+                return Err(invalid_input(format!(
+                    "The text {} is empty, it cannot be skipped from the similarity matrix",
+                    index
+                )));
+            }
+        }
+        let embeddings: Vec<Embedding> =
+            self.embed_internal(texts, batch_size, Norm::L2, &self.tokenizer, None, None)?;
+        Ok(similarity::dot_matrix(&embeddings)?)
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts, formatted with a task instruction
+    ///
+    /// Instruction-tuned models expect the task to be described along with the query, such as
+    /// "Given a web search query, retrieve relevant passages that answer the query".
+    /// The texts are formatted with the instruction template of the model before being tokenized,
+    /// the models without a template fail rather than getting an instruction they were not trained with
+    pub fn embed_with_instruction<S: AsRef<str> + Send + Sync>(
+        &self,
+        instruction: &str,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        let instruction_template = self.instruction_template.as_deref().ok_or_else(|| {
+            invalid_input("The model has no instruction template, it does not take instructions")
+        })?;
+        let texts: Vec<String> = texts
+            .iter()
+            .map(|text| format_instruction(instruction_template, instruction, text.as_ref()))
+            .collect();
+        self.embed(texts, batch_size)
+    }
+
+    /// Method to generate sentence embeddings with batches sized from the texts' token lengths
+    ///
+    /// The texts are tokenized first and grouped by length, so that each batch holds at most
+    /// `max_tokens_per_batch` tokens including the padding. Short texts are embedded in large batches
+    /// and long ones in small batches, which bounds the memory usage and keeps the padding overhead low.
+    /// A text longer than the budget is embedded alone. The embeddings are returned in the order of the texts
+    pub fn embed_auto_batch<S: AsRef<str> + Send + Sync>(
+        &self,
+        texts: Vec<S>,
+        max_tokens_per_batch: usize,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        if max_tokens_per_batch == 0 {
+            return Err(invalid_input(
+                "The maximum number of tokens per batch must be at least 1",
+            ));
+        }
+
+        Ok(self.install(|| {
+            let lengths = self.token_lengths(&texts)?;
+            let batches = token_budget_batches(&lengths, max_tokens_per_batch);
+            self.embed_index_batches(
+                &texts,
+                &batches,
+                self.normalize,
+                &self.tokenizer,
+                None,
+                None,
+            )
+        })?)
+    }
+
+    /// Run the operation on the dedicated thread pool of the model, if it has one
+    fn install<R: Send>(&self, operation: impl FnOnce() -> R + Send) -> R {
+        match &self.thread_pool {
+            Some(thread_pool) => thread_pool.install(operation),
+            None => operation(),
+        }
+    }
+
+    /// Embed the batches of texts given by their indices, and return the embeddings in the order of the texts
+    ///
+    /// The texts left out of the batches are the skipped ones
+    fn embed_index_batches<S: AsRef<str> + Send + Sync, E: BatchEmbeddings>(
+        &self,
+        texts: &[S],
+        batches: &[Vec<usize>],
+        normalize: Norm,
+        tokenizer: &Tokenizer,
+        cancel: Option<&AtomicBool>,
+        progress: Option<&Sender<()>>,
+    ) -> Result<E> {
+        let batch_embeddings = batches
+            .par_iter()
+            .map(|indices| {
+                check_cancelled(cancel)?;
+                let batch: Vec<&str> = indices.iter().map(|&index| texts[index].as_ref()).collect();
+                let embeddings = self.embed_batch_retrying(
+                    &batch,
+                    |index| indices[index],
+                    normalize,
+                    tokenizer,
+                )?;
+                report_batch(progress);
+                Ok(embeddings)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        E::restore_order(texts.len(), batches, batch_embeddings)
+    }
+
+    /// Number of tokens of each text once encoded, including the special tokens
+    fn token_lengths<S: AsRef<str> + Send + Sync>(&self, texts: &[S]) -> Result<Vec<usize>> {
+        texts
+            .par_iter()
+            .map(|text| {
+                let encoding = self
+                    .tokenizer
+                    .encode(text.as_ref(), self.add_special_tokens)
+                    .map_err(tokenization_error)?;
+                Ok(encoding.len())
+            })
+            .collect()
+    }
+
+    /// Method to generate sentence embeddings lazily, one batch at a time
+    ///
+    /// Only a single batch of texts and embeddings is held in memory at once, so that large corpora
+    /// can be streamed. The batches are not processed in parallel, unlike with `embed`
+    pub fn embed_iter<'a, S, I>(
+        &'a self,
+        texts: I,
+        batch_size: Option<usize>,
+    ) -> impl Iterator<Item = Result<Vec<Embedding>, FastEmbedError>> + 'a
+    where
+        S: AsRef<str> + 'a,
+        I: IntoIterator<Item = S>,
+        I::IntoIter: 'a,
+    {
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        let mut texts = texts.into_iter();
+        let mut done = false;
+        // Index of the first text of the next batch
+        let mut position = 0;
+
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            if batch_size == 0 {
+                done = true;
+                return Some(Err(invalid_input("The batch size must be at least 1")));
+            }
+
+            let batch: Vec<S> = texts.by_ref().take(batch_size).collect();
+            if batch.is_empty() {
+                done = true;
+                return None;
+            }
+            let offset = position;
+            position += batch.len();
+            Some(
+                self.embed_batch_with_policy(
+                    &batch,
+                    |index| offset + index,
+                    self.normalize,
+                    &self.tokenizer,
+                )
+                .map_err(FastEmbedError::from),
+            )
+        })
+    }
+
+    /// Method to generate sentence embeddings as a lazy parallel iterator, in the order of the texts
+    ///
+    /// The batches are embedded as the iterator is consumed, without collecting the embeddings into a Vec first,
+    /// so that they can be fed to a consumer which parallelizes itself. The batches run on the thread pool
+    /// the iterator is consumed in, rather than on the dedicated thread pool of the model.
+    /// When a batch fails, its error is yielded once in place of its embeddings.
+    /// Collecting the iterator into a `Result<Vec<Embedding>>`, as rayon allows, gives the same output as `embed`
+    pub fn par_embed<'a, S: AsRef<str> + Sync>(
+        &'a self,
+        texts: &'a [S],
+        batch_size: Option<usize>,
+    ) -> Result<impl ParallelIterator<Item = Result<Embedding, FastEmbedError>> + 'a, FastEmbedError>
+    {
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1"));
+        }
+        // The empty texts are rejected before any batch runs
+        if matches!(self.on_empty_text, OnEmptyText::Error) {
+            if let Some(index) = texts
+                .iter()
+                .position(|text| text.as_ref().trim().is_empty())
+            {
+                return Err(invalid_input(format!("The text {} is empty", index)));
+            }
+        }
+
+        Ok(texts
+            .par_chunks(batch_size)
+            .enumerate()
+            .map(move |(batch_index, batch)| {
+                self.embed_batch_with_policy(
+                    batch,
+                    |index| batch_index * batch_size + index,
+                    self.normalize,
+                    &self.tokenizer,
+                )
+            })
+            .flat_map_iter(|embeddings: Result<Vec<Embedding>>| {
+                embeddings.map_or_else(
+                    |error| vec![Err(error.into())],
+                    |embeddings| embeddings.into_iter().map(Ok).collect(),
+                )
+            }))
+    }
+
+    /// Method to generate sentence embeddings for a Vec of texts without blocking the async runtime
+    ///
+    /// The tokenization and inference run on tokio's blocking thread pool via `spawn_blocking`.
+    /// The model is shared with the blocking task through an `Arc`, so wrap the TextEmbedding in one
+    /// to use it concurrently across tasks.
+    #[cfg(feature = "tokio")]
+    pub fn embed_async<S: AsRef<str> + Send + Sync + 'static>(
+        self: &std::sync::Arc<Self>,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+    ) -> impl std::future::Future<Output = Result<Vec<Embedding>, FastEmbedError>> {
+        let model = std::sync::Arc::clone(self);
+        async move {
+            tokio::task::spawn_blocking(move || model.embed(texts, batch_size))
+                .await
+                .map_err(|error| FastEmbedError::Other(Box::new(error)))?
+        }
+    }
+
+    /// Embed the texts in batches, into the Vecs of `embed` or the rows of the array of `embed_array`
+    fn embed_internal<S: AsRef<str> + Send + Sync, E: BatchEmbeddings>(
+        &self,
+        texts: Vec<S>,
+        batch_size: Option<usize>,
+        normalize: Norm,
+        tokenizer: &Tokenizer,
+        cancel: Option<&AtomicBool>,
+        progress: Option<&Sender<()>>,
+    ) -> Result<E> {
+        // Determine the batch size, default if not specified
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            bail!(invalid_input("The batch size must be at least 1"));
+        }
+        if texts.is_empty() {
+            return E::concatenate(Vec::new());
+        }
+
+        let is_empty = |text: &S| text.as_ref().trim().is_empty();
+        // Indices of the texts to embed, when some of them are skipped
+        let kept: Option<Vec<usize>> = match self.on_empty_text {
+            OnEmptyText::Embed => None,
+            OnEmptyText::Skip => texts.iter().any(is_empty).then(|| {
+                (0..texts.len())
+                    .filter(|&index| !is_empty(&texts[index]))
+                    .collect()
+            }),
+            OnEmptyText::Error => match texts.iter().position(is_empty) {
+                Some(index) => bail!(invalid_input(format!("The text {} is empty", index))),
+                None => None,
+            },
+        };
+
+        self.install(|| {
+            if let Some(kept) = kept {
+                let batches: Vec<Vec<usize>> = if self.sort_by_length {
+                    let kept_texts: Vec<&str> =
+                        kept.iter().map(|&index| texts[index].as_ref()).collect();
+                    length_sorted_batches(&self.token_lengths(&kept_texts)?, batch_size)
+                        .into_iter()
+                        .map(|batch| batch.into_iter().map(|index| kept[index]).collect())
+                        .collect()
+                } else {
+                    kept.chunks(batch_size).map(<[usize]>::to_vec).collect()
+                };
+                // The skipped texts keep the empty embedding the batches are restored into
+                return self
+                    .embed_index_batches(&texts, &batches, normalize, tokenizer, cancel, progress);
+            }
+
+            if self.sort_by_length {
+                let batches = length_sorted_batches(&self.token_lengths(&texts)?, batch_size);
+                return self
+                    .embed_index_batches(&texts, &batches, normalize, tokenizer, cancel, progress);
+            }
+
+            let batch_embeddings = texts
+                .par_chunks(batch_size)
+                .enumerate()
+                .map(|(batch_index, batch)| {
+                    check_cancelled(cancel)?;
+                    let embeddings = self.embed_batch_retrying(
+                        batch,
+                        |index| batch_index * batch_size + index,
+                        normalize,
+                        tokenizer,
+                    )?;
+                    report_batch(progress);
+                    Ok(embeddings)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            E::concatenate(batch_embeddings)
+        })
+    }
+
+    /// The output of the pooler layer, when the model has one and uses CLS pooling
+    ///
+    /// It replaces the hidden state of the CLS token, which is the input of the pooler
+    fn pooler_output<'s>(&self, outputs: &'s SessionOutputs) -> Option<&'s Value> {
+        if self.has_pooler && self.pooling == Pooling::Cls {
+            outputs.get("pooler_output")
+        } else {
+            None
+        }
+    }
+
+    /// Apply the `on_truncation` policy to the encodings of a batch
+    fn check_truncation(
+        &self,
+        encodings: &[Encoding],
+        input_index: impl Fn(usize) -> usize,
+        tokenizer: &Tokenizer,
+    ) -> Result<()> {
+        if matches!(self.on_truncation, OnTruncation::Silent) {
+            return Ok(());
+        }
+
+        // The tokenizer keeps the truncated tokens as overflowing encodings
+        let truncated: Vec<(usize, usize)> = encodings
+            .iter()
+            .enumerate()
+            .filter(|(_, encoding)| !encoding.get_overflowing().is_empty())
+            .map(|(index, encoding)| (input_index(index), untruncated_length(encoding)))
+            .collect();
+        if truncated.is_empty() {
+            return Ok(());
+        }
+
+        match &self.on_truncation {
+            OnTruncation::Silent => {}
+            OnTruncation::Warn(callback) => {
+                for &(index, length) in &truncated {
+                    callback(index, length);
+                }
+            }
+            OnTruncation::Error => {
+                let max_length = tokenizer
+                    .get_truncation()
+                    .map_or(0, |truncation| truncation.max_length);
+                let inputs: Vec<String> = truncated
+                    .iter()
+                    .map(|(index, length)| format!("{} ({} tokens)", index, length))
+                    .collect();
+                bail!(invalid_input(format!(
+                    "The inputs {} are longer than the maximum length of {} tokens",
+                    inputs.join(", "),
+                    max_length
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tokenize and run the inference on a single batch of texts
+    ///
+    /// `input_index` maps the position of a text in the batch to its index in the inputs, to report the truncated texts
+    fn embed_batch<S: AsRef<str>>(
+        &self,
+        batch: &[S],
+        input_index: impl Fn(usize) -> usize,
+        normalize: Norm,
+        tokenizer: &Tokenizer,
+    ) -> Result<Vec<Embedding>> {
+        let pooled = self.embed_batch_array(batch, input_index, normalize, tokenizer)?;
+        Ok(Vec::from_pooled(pooled))
+    }
+
+    /// Same as embed_batch, halving the batch on out of memory errors if `adaptive_oom_retry` is set
+    fn embed_batch_retrying<S: AsRef<str>, E: BatchEmbeddings>(
+        &self,
+        batch: &[S],
+        input_index: impl Fn(usize) -> usize,
+        normalize: Norm,
+        tokenizer: &Tokenizer,
+    ) -> Result<E> {
+        let embed = |chunk: &[S], offset: usize| {
+            let pooled = self.embed_batch_array(
+                chunk,
+                |index| input_index(offset + index),
+                normalize,
+                tokenizer,
+            )?;
+            Ok(E::from_pooled(pooled))
+        };
+        if !self.adaptive_oom_retry {
+            return embed(batch, 0);
+        }
+        halve_on_oom(batch, 0, &embed)
+    }
+
+    /// Same as embed_batch_retrying, applying the `on_empty_text` policy to the texts of the batch
+    ///
+    /// The skipped texts keep an empty embedding, like with `embed`
+    fn embed_batch_with_policy<S: AsRef<str>>(
+        &self,
+        batch: &[S],
+        input_index: impl Fn(usize) -> usize,
+        normalize: Norm,
+        tokenizer: &Tokenizer,
+    ) -> Result<Vec<Embedding>> {
+        let is_empty = |text: &S| text.as_ref().trim().is_empty();
+        match self.on_empty_text {
+            OnEmptyText::Embed => {
+                return self.embed_batch_retrying(batch, input_index, normalize, tokenizer)
+            }
+            OnEmptyText::Error => {
+                if let Some(index) = batch.iter().position(is_empty) {
+                    bail!(invalid_input(format!(
+                        "The text {} is empty",
+                        input_index(index)
+                    )));
+                }
+                return self.embed_batch_retrying(batch, input_index, normalize, tokenizer);
+            }
+            OnEmptyText::Skip => {}
+        }
+
+        let kept: Vec<usize> = (0..batch.len())
+            .filter(|&index| !is_empty(&batch[index]))
+            .collect();
+        let mut embeddings = vec![Vec::new(); batch.len()];
+        if kept.is_empty() {
+            return Ok(embeddings);
+        }
+        let kept_texts: Vec<&str> = kept.iter().map(|&index| batch[index].as_ref()).collect();
+        let kept_embeddings: Vec<Embedding> = self.embed_batch_retrying(
+            &kept_texts,
+            |index| input_index(kept[index]),
+            normalize,
+            tokenizer,
+        )?;
+        for (&index, embedding) in kept.iter().zip(kept_embeddings) {
+            embeddings[index] = embedding;
+        }
+        Ok(embeddings)
+    }
+
+    /// Same as embed_batch, returning the embeddings of the batch as the rows of an array
+    fn embed_batch_array<S: AsRef<str>>(
+        &self,
+        batch: &[S],
+        input_index: impl Fn(usize) -> usize,
+        normalize: Norm,
+        tokenizer: &Tokenizer,
+    ) -> Result<Array2<f32>> {
+        let inputs = batch.iter().map(|text| text.as_ref()).collect();
+        self.embed_inputs(inputs, input_index, normalize, tokenizer)
+    }
+
+    /// Tokenize and run the inference on a batch of inputs, either single texts or pairs of texts
+    fn embed_inputs<'s, E: Into<EncodeInput<'s>> + Send>(
+        &self,
+        inputs: Vec<E>,
+        input_index: impl Fn(usize) -> usize,
+        normalize: Norm,
+        tokenizer: &Tokenizer,
+    ) -> Result<Array2<f32>> {
+        let (mut inputs_ids_array, mut attention_mask_array, mut token_type_ids_array) =
+            self.encode_inputs(inputs, input_index, tokenizer)?;
+        self.run_inference(
+            &mut inputs_ids_array,
+            &mut attention_mask_array,
+            &mut token_type_ids_array,
+            normalize,
+        )
+    }
+
+    /// Encode a batch of inputs into the token ids, attention mask and token type ids arrays
+    fn encode_inputs<'s, E: Into<EncodeInput<'s>> + Send>(
+        &self,
+        inputs: Vec<E>,
+        input_index: impl Fn(usize) -> usize,
+        tokenizer: &Tokenizer,
+    ) -> Result<(ArcArray2<i64>, ArcArray2<i64>, ArcArray2<i64>)> {
+        let mut scratch = EmbedScratch::default();
+        let shape = self.encode_into(inputs, input_index, tokenizer, &mut scratch)?;
+        scratch.arrays(shape)
+    }
+
+    /// Encode a batch of inputs into the buffers of the scratch, returning the shape of the batch
+    fn encode_into<'s, E: Into<EncodeInput<'s>> + Send>(
+        &self,
+        inputs: Vec<E>,
+        input_index: impl Fn(usize) -> usize,
+        tokenizer: &Tokenizer,
+        scratch: &mut EmbedScratch,
+    ) -> Result<(usize, usize)> {
+        // Encode the texts in the batch
+        let encodings = tokenizer
+            .encode_batch(inputs, self.add_special_tokens)
+            .map_err(tokenization_error)?;
+        self.check_truncation(&encodings, &input_index, tokenizer)?;
+
+        // Extract the encoding length and batch size
+        let encoding_length = encodings[0].len();
+        let batch_size = encodings.len();
+
+        let max_size = encoding_length * batch_size;
+
+        // Reuse the buffers of the scratch, growing them to the maximum size if needed
+        let EmbedScratch {
+            ids: ids_array,
+            mask: mask_array,
+            type_ids: typeids_array,
+        } = scratch;
+        for buffer in [&mut *ids_array, &mut *mask_array, &mut *typeids_array] {
+            buffer.clear();
+            buffer.reserve(max_size);
+        }
+
+        // Not using par_iter because the closure needs to be FnMut
+        encodings.iter().for_each(|encoding| {
+            let ids = encoding.get_ids();
+            let mask = encoding.get_attention_mask();
+            let typeids = encoding.get_type_ids();
+
+            // Extend the preallocated arrays with the current encoding
+            // Requires the closure to be FnMut
+            ids_array.extend(ids.iter().map(|x| *x as i64));
+            mask_array.extend(mask.iter().map(|x| *x as i64));
+            typeids_array.extend(typeids.iter().map(|x| *x as i64));
+        });
+
+        if let Some(token_filter) = &self.token_filter {
+            let padding = tokenizer.get_padding();
+            let pad_id = padding.map_or(0, |padding| padding.pad_id) as i64;
+            let fixed_length = match padding.map(|padding| &padding.strategy) {
+                Some(PaddingStrategy::Fixed(length)) => *length,
+                _ => 0,
+            };
+            let filtered_length = filter_tokens(
+                scratch,
+                encoding_length,
+                token_filter.as_ref(),
+                pad_id,
+                fixed_length,
+                input_index,
+            )?;
+            return Ok((batch_size, filtered_length));
+        }
+
+        Ok((batch_size, encoding_length))
+    }
+
+    /// Run the session on a batch of token ids, once fewer than `max_concurrent_batches` are running
+    ///
+    /// The shared arrays are handed over to ONNX Runtime without being copied, and are uniquely owned again once it returns
+    fn run_session(
+        &self,
+        inputs_ids_array: &mut ArcArray2<i64>,
+        attention_mask_array: &mut ArcArray2<i64>,
+        token_type_ids_array: &mut ArcArray2<i64>,
+    ) -> Result<SessionOutputs<'_>> {
+        // The sequences are padded on the right, their positions start at 0
+        let position_ids_array = self.need_position_ids.then(|| {
+            Array2::from_shape_fn(inputs_ids_array.dim(), |(_, position)| position as i64)
+        });
+
+        let mut session_inputs = ort::inputs![
+            "input_ids" => Value::from_array(inputs_ids_array)?,
+            "attention_mask" => Value::from_array(attention_mask_array)?,
+        ]?;
+        if self.need_token_type_ids {
+            session_inputs.insert("token_type_ids", Value::from_array(token_type_ids_array)?);
+        }
+        if let Some(position_ids_array) = position_ids_array {
+            session_inputs.insert("position_ids", Value::from_array(position_ids_array)?);
+        }
+
+        let run = || {
+            self.session
+                .run(session_inputs)
+                .with_context(inference_error)
+        };
+        match &self.batch_limiter {
+            Some(batch_limiter) => batch_limiter.run(run),
+            None => run(),
+        }
+    }
+
+    /// The output holding the embeddings, `output_key` if set
+    ///
+    /// Otherwise the pooler output if `pooler` is set and the model has one, then `last_hidden_state`, then the first output
+    fn select_output<'o>(&self, outputs: &'o SessionOutputs, pooler: bool) -> Result<&'o Value> {
+        match &self.output_key {
+            Some(output_key) => outputs
+                .get(output_key.as_str())
+                .with_context(|| format!("The model has no output named {}", output_key)),
+            None => pooler
+                .then(|| self.pooler_output(outputs))
+                .flatten()
+                .or_else(|| outputs.get("last_hidden_state"))
+                .or_else(|| {
+                    let first_output = self.session.outputs.first()?;
+                    outputs.get(first_output.name.as_str())
+                })
+                .context("The model has no outputs"),
+        }
+    }
+
+    /// Run the inference on a batch of token ids, then pool and normalize the embeddings
+    fn run_inference(
+        &self,
+        inputs_ids_array: &mut ArcArray2<i64>,
+        attention_mask_array: &mut ArcArray2<i64>,
+        token_type_ids_array: &mut ArcArray2<i64>,
+        normalize: Norm,
+    ) -> Result<Array2<f32>> {
+        let outputs =
+            self.run_session(inputs_ids_array, attention_mask_array, token_type_ids_array)?;
+
+        // Extract, pool and normalize embeddings
+        let output = self.select_output(&outputs, true)?;
+        let output_data = output.extract_tensor::<f32>()?;
+        let output_view = output_data.view();
+
+        let pooled = match output_view.ndim() {
+            // The embeddings of models such as sentence-transformers exports are already pooled
+            2 => output_view.view().into_dimensionality::<Ix2>()?.to_owned(),
+            3 => {
+                let token_embeddings = output_view.view().into_dimensionality::<Ix3>()?;
+                let normalized_tokens;
+                let token_embeddings = if self.normalize_before_pooling {
+                    normalized_tokens = pooling::normalize_tokens(&token_embeddings);
+                    normalized_tokens.view()
+                } else {
+                    token_embeddings
+                };
+                match self.pooling {
+                    Pooling::Cls => pooling::cls(&token_embeddings),
+                    Pooling::Mean => pooling::mean(&token_embeddings, &attention_mask_array.view()),
+                    Pooling::Max => pooling::max(&token_embeddings, &attention_mask_array.view()),
+                    Pooling::LastToken => {
+                        pooling::last_token(&token_embeddings, &attention_mask_array.view())
+                    }
+                }
+            }
+            dimensions => bail!(FastEmbedError::ShapeMismatch(
+                format!(
+                    "Expected a 2 or 3 dimensional output tensor, got {} dimensions",
+                    dimensions
+                )
+                .into()
+            )),
+        };
+
+        // Matryoshka truncation happens before normalization so that the vectors keep a unit norm
+        let mut pooled = match self.output_dimension {
+            Some(dim) => pooled.slice_move(s![.., ..dim]),
+            None => pooled,
+        };
+
+        pooling::activate(self.activation, &mut pooled);
+        if normalize != Norm::None {
+            for mut row in pooled.rows_mut() {
+                pooling::apply_norm(normalize, row.as_slice_mut().unwrap());
+            }
+        }
+
+        Ok(pooled)
+    }
+}
+
+impl Embed for TextEmbedding {
+    type Output = Embedding;
+
+    fn embed(
+        &self,
+        texts: Vec<&str>,
+        batch_size: Option<usize>,
+    ) -> Result<Vec<Embedding>, FastEmbedError> {
+        TextEmbedding::embed(self, texts, batch_size)
+    }
+}
+
+/// Group the texts by length into batches of at most `max_tokens` tokens, padding included
+///
+/// Returns the indices of the texts in each batch. The texts are sorted by length so that those of
+/// a batch have similar lengths, and a batch always holds at least one text
+pub(crate) fn token_budget_batches(lengths: &[usize], max_tokens: usize) -> Vec<Vec<usize>> {
+    let mut indices: Vec<usize> = (0..lengths.len()).collect();
+    indices.sort_by_key(|&index| lengths[index]);
+
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    let mut batch = Vec::new();
+    for index in indices {
+        // The texts are sorted, so the current one sets the padded length of the batch
+        if !batch.is_empty() && (batch.len() + 1) * lengths[index] > max_tokens {
+            batches.push(std::mem::take(&mut batch));
+        }
+        batch.push(index);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Run `embed` on the batch, splitting it in halves that are retried separately if it fails
+/// with an out of memory error, until the batches hold a single text
+///
+/// `embed` is called with a part of the batch and the offset of this part in the batch.
+/// The embeddings are returned in the order of the batch
+pub(crate) fn halve_on_oom<T, E: BatchEmbeddings>(
+    batch: &[T],
+    offset: usize,
+    embed: &impl Fn(&[T], usize) -> Result<E>,
+) -> Result<E> {
+    match embed(batch, offset) {
+        Err(error) if batch.len() > 1 && is_out_of_memory(&error) => {
+            let (first, second) = batch.split_at(batch.len() / 2);
+            E::concatenate(vec![
+                halve_on_oom(first, offset, embed)?,
+                halve_on_oom(second, offset + first.len(), embed)?,
+            ])
+        }
+        result => result,
+    }
+}
+
+/// Embeddings of the batches, collected into a Vec per text or kept as the rows of the pooled arrays
+pub(crate) trait BatchEmbeddings: Sized + Send {
+    /// Embeddings of a batch, from the pooled output of the model
+    fn from_pooled(pooled: Array2<f32>) -> Self;
+
+    /// Embeddings of consecutive batches, in their order
+    fn concatenate(batches: Vec<Self>) -> Result<Self>;
+
+    /// Embeddings of `count` texts, from the batches of the texts given by their indices
+    ///
+    /// The embeddings of the texts left out of the batches are empty, or rows of zeros
+    fn restore_order(count: usize, indices: &[Vec<usize>], batches: Vec<Self>) -> Result<Self>;
+}
+
+impl BatchEmbeddings for Vec<Embedding> {
+    fn from_pooled(pooled: Array2<f32>) -> Self {
+        pooled.rows().into_iter().map(|row| row.to_vec()).collect()
+    }
+
+    fn concatenate(batches: Vec<Self>) -> Result<Self> {
+        Ok(batches.into_iter().flatten().collect())
+    }
+
+    fn restore_order(count: usize, indices: &[Vec<usize>], batches: Vec<Self>) -> Result<Self> {
+        let mut embeddings = vec![Vec::new(); count];
+        for (indices, batch) in indices.iter().zip(batches) {
+            for (&index, embedding) in indices.iter().zip(batch) {
+                embeddings[index] = embedding;
+            }
+        }
+        Ok(embeddings)
+    }
+}
+
+impl BatchEmbeddings for Array2<f32> {
+    fn from_pooled(pooled: Array2<f32>) -> Self {
+        pooled
+    }
+
+    fn concatenate(batches: Vec<Self>) -> Result<Self> {
+        if batches.is_empty() {
+            return Ok(Array2::zeros((0, 0)));
+        }
+        let views: Vec<_> = batches.iter().map(Array2::view).collect();
+        Ok(ndarray::concatenate(Axis(0), &views)?)
+    }
+
+    fn restore_order(count: usize, indices: &[Vec<usize>], batches: Vec<Self>) -> Result<Self> {
+        let dim = batches.first().map_or(0, Array2::ncols);
+        let mut embeddings = Array2::zeros((count, dim));
+        for (indices, batch) in indices.iter().zip(batches) {
+            for (&index, row) in indices.iter().zip(batch.rows()) {
+                embeddings.row_mut(index).assign(&row);
+            }
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Whether the error comes from a failed allocation, of ONNX Runtime on the CPU or of CUDA
+pub(crate) fn is_out_of_memory(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let message = cause.to_string().to_lowercase();
+        ["out of memory", "failed to allocate", "bad_alloc"]
+            .iter()
+            .any(|pattern| message.contains(pattern))
+    })
+}
+
+/// Counting semaphore bounding the number of batches running at the same time
+pub(crate) struct BatchLimiter {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Permit of a running batch, released when dropped so that a panicking batch releases it too
+struct BatchPermit<'l>(&'l BatchLimiter);
+
+impl Drop for BatchPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+impl BatchLimiter {
+    pub(crate) fn new(max_concurrent_batches: usize) -> Result<Self> {
+        if max_concurrent_batches == 0 {
+            bail!(invalid_input(
+                "The maximum number of concurrent batches must be at least 1"
+            ));
+        }
+        Ok(Self {
+            available: Mutex::new(max_concurrent_batches),
+            released: Condvar::new(),
+        })
+    }
+
+    /// Run the batch once fewer than the maximum number of batches are running
+    pub(crate) fn run<T>(&self, batch: impl FnOnce() -> T) -> T {
+        let mut available = self
+            .released
+            .wait_while(self.available.lock().unwrap(), |available| *available == 0)
+            .unwrap();
+        *available -= 1;
+        drop(available);
+
+        let _permit = BatchPermit(self);
+        batch()
+    }
+}
+
+/// Check the model file against the SHA-256 declared for its variant in the ModelInfo,
+/// or else against the one the Hub recorded for it in the cache
+///
+/// The file is left unverified if neither is known, such as in the caches without symlinks
+pub(crate) fn verify_model_file(
+    model_info: &ModelInfo,
+    model_file_name: &str,
+    model_file: &Path,
+) -> Result<()> {
+    let declared_sha256 = if model_file_name == model_info.model_file {
+        &model_info.sha256
+    } else {
+        &model_info.sha256_q8
+    };
+    match declared_sha256
+        .clone()
+        .or_else(|| cached_sha256(model_file))
+    {
+        Some(sha256) => verify_sha256(model_file, &sha256),
+        None => Ok(()),
+    }
+}
+
+/// Notify the thread collecting the progress that a batch is completed
+fn report_batch(progress: Option<&Sender<()>>) {
+    if let Some(progress) = progress {
+        // The collecting thread only stops listening once all the batches are done
+        let _ = progress.send(());
+    }
+}
+
+/// Fail if the cancellation flag is set
+fn check_cancelled(cancel: Option<&AtomicBool>) -> Result<()> {
+    if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+        bail!("The embedding was cancelled");
+    }
+    Ok(())
+}
+
+/// Build the dedicated thread pool of a model, with as many threads as CPUs if `threads` is not set
+fn build_thread_pool(dedicated: bool, threads: Option<usize>) -> Result<Option<ThreadPool>> {
+    if !dedicated {
+        return Ok(None);
+    }
+    let thread_pool = ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .thread_name(|index| format!("fastembed-{}", index))
+        .build()?;
+    Ok(Some(thread_pool))
+}
+
+/// Number of tokens of the text before its truncation, including the special tokens
+fn untruncated_length(encoding: &Encoding) -> usize {
+    // The special tokens are added again to every overflowing part, and the parts are padded
+    let kept = encoding
+        .get_attention_mask()
+        .iter()
+        .filter(|&&mask| mask != 0)
+        .count();
+    let overflowing: usize = encoding
+        .get_overflowing()
+        .iter()
+        .map(|part| {
+            part.get_special_tokens_mask()
+                .iter()
+                .zip(part.get_attention_mask())
+                .filter(|(&special, &mask)| special == 0 && mask != 0)
+                .count()
+        })
+        .sum();
+    kept + overflowing
+}
+
+/// Remove the tokens rejected by the filter from the rows of `length` tokens of the scratch,
+/// then pad the rows again to the longest one
+///
+/// Only the attended tokens are passed to the filter, the padding is rebuilt with `pad_id`. The rows are padded to
+/// at least `min_length` tokens, for the fixed padding. Returns the new length of the rows
+///
+/// Fails if the filter rejects all the tokens of a row, with the index of its input given by `input_index`
+pub(crate) fn filter_tokens(
+    scratch: &mut EmbedScratch,
+    length: usize,
+    keep: &(dyn Fn(u32) -> bool + Send + Sync),
+    pad_id: i64,
+    min_length: usize,
+    input_index: impl Fn(usize) -> usize,
+) -> Result<usize> {
+    let EmbedScratch {
+        ids,
+        mask,
+        type_ids,
+    } = scratch;
+    let rows = ids.len().checked_div(length).unwrap_or(0);
+    let kept: Vec<bool> = ids
+        .iter()
+        .zip(mask.iter())
+        .map(|(&id, &mask)| mask != 0 && keep(id as u32))
+        .collect();
+    let kept_counts: Vec<usize> = kept
+        .chunks(length.max(1))
+        .map(|row| row.iter().filter(|&&kept| kept).count())
+        .collect();
+    // The pooling needs at least one token per sequence
+    if let Some(row) = kept_counts.iter().position(|&count| count == 0) {
+        bail!(invalid_input(format!(
+            "The token filter removed all the tokens of the input {}",
+            input_index(row)
+        )));
+    }
+    let new_length = kept_counts.into_iter().max().unwrap_or(0).max(min_length);
+
+    let mut filtered_ids = Vec::with_capacity(rows * new_length);
+    let mut filtered_mask = Vec::with_capacity(rows * new_length);
+    let mut filtered_type_ids = Vec::with_capacity(rows * new_length);
+    for row in 0..rows {
+        let start = row * length;
+        for position in (start..start + length).filter(|&position| kept[position]) {
+            filtered_ids.push(ids[position]);
+            filtered_mask.push(mask[position]);
+            filtered_type_ids.push(type_ids[position]);
+        }
+        filtered_ids.resize((row + 1) * new_length, pad_id);
+        filtered_mask.resize((row + 1) * new_length, 0);
+        filtered_type_ids.resize((row + 1) * new_length, 0);
+    }
+
+    // Copy the rows back, to keep the buffers of the scratch
+    for (buffer, filtered) in [
+        (ids, filtered_ids),
+        (mask, filtered_mask),
+        (type_ids, filtered_type_ids),
+    ] {
+        buffer.clear();
+        buffer.extend(filtered);
+    }
+    Ok(new_length)
+}
+
+/// Split the texts into batches of `batch_size`, after sorting them by length
+///
+/// Returns the indices of the texts in each batch
+pub(crate) fn length_sorted_batches(lengths: &[usize], batch_size: usize) -> Vec<Vec<usize>> {
+    let mut indices: Vec<usize> = (0..lengths.len()).collect();
+    indices.sort_by_key(|&index| lengths[index]);
+    indices.chunks(batch_size).map(<[usize]>::to_vec).collect()
+}
+
+/// Fill the `{instruction}` and `{text}` placeholders of the instruction template
+pub(crate) fn format_instruction(template: &str, instruction: &str, text: &str) -> String {
+    // Split at the text placeholder, so that placeholders written in the instruction or the text are left as is
+    let (before, after) = template.split_once("{text}").unwrap_or((template, ""));
+    format!(
+        "{}{}{}",
+        before.replace("{instruction}", instruction),
+        text,
+        after.replace("{instruction}", instruction)
+    )
+}
+
+/// Prepend the prefix to every text
+fn with_prefix<S: AsRef<str>>(prefix: &str, texts: Vec<S>) -> Vec<String> {
+    texts
+        .iter()
+        .map(|text| format!("{}{}", prefix, text.as_ref()))
+        .collect()
+}
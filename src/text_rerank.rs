@@ -0,0 +1,226 @@
+use crate::{
+    common::{
+        default_cache_dir, load_tokenizer_hf_hub, retrieve_model, session_builder, HubConfig,
+        OptimizationLevel, Padding, Tokenizer, DEFAULT_BATCH_SIZE, DEFAULT_MAX_LENGTH,
+    },
+    error::{inference_error, tokenization_error, FastEmbedError},
+    models::{reranker_models_list, RerankerModel, RerankerModelInfo},
+};
+use anyhow::{Context, Result};
+use ndarray::{s, Array, Ix2};
+use ort::{ExecutionProviderDispatch, Session, Value};
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+use std::path::PathBuf;
+use tokenizers::TruncationDirection;
+
+const DEFAULT_RERANKER_MODEL: RerankerModel = RerankerModel::BGERerankerBase;
+
+/// Options for initializing the TextRerank model
+#[derive(Debug, Clone)]
+pub struct RerankInitOptions {
+    pub model_name: RerankerModel,
+    pub execution_providers: Vec<ExecutionProviderDispatch>,
+    pub max_length: usize,
+    pub cache_dir: PathBuf,
+    pub show_download_progress: bool,
+}
+
+impl Default for RerankInitOptions {
+    fn default() -> Self {
+        Self {
+            model_name: DEFAULT_RERANKER_MODEL,
+            execution_providers: Default::default(),
+            max_length: DEFAULT_MAX_LENGTH,
+            cache_dir: default_cache_dir(),
+            show_download_progress: true,
+        }
+    }
+}
+
+/// Relevance of a document to the query, as returned by TextRerank::rerank
+#[derive(Debug, Clone, PartialEq)]
+pub struct RerankResult {
+    /// The document text, only set when `return_documents` is true
+    pub document: Option<String>,
+    pub score: f32,
+    /// Index of the document in the input
+    pub index: usize,
+}
+
+/// Rust representation of the TextRerank model
+pub struct TextRerank {
+    tokenizer: Tokenizer,
+    session: Session,
+    need_token_type_ids: bool,
+}
+
+impl TextRerank {
+    /// Try to generate a new TextRerank Instance
+    ///
+    /// Uses the highest level of Graph optimization
+    ///
+    /// Uses the total number of CPUs available as the number of intra-threads
+    pub fn try_new(options: RerankInitOptions) -> Result<Self, FastEmbedError> {
+        let RerankInitOptions {
+            model_name,
+            execution_providers,
+            max_length,
+            cache_dir,
+            show_download_progress,
+        } = options;
+
+        let model_info = TextRerank::get_model_info(&model_name);
+        let model_repo = retrieve_model(
+            model_info.model_code.clone(),
+            cache_dir,
+            show_download_progress,
+            false,
+            HubConfig::default(),
+            None,
+        )?;
+
+        let model_file_name = model_info.model_file;
+        let model_file_reference = model_repo.get(&model_file_name).with_context(|| {
+            format!("Failed to retrieve {} for {}", model_file_name, model_name)
+        })?;
+
+        let (session_builder, _) = session_builder(
+            execution_providers,
+            None,
+            None,
+            OptimizationLevel::default(),
+            None,
+        )?;
+        let session = session_builder.with_model_from_file(model_file_reference)?;
+
+        let tokenizer = load_tokenizer_hf_hub(
+            model_repo,
+            max_length,
+            Padding::default(),
+            TruncationDirection::default(),
+            false,
+        )?;
+        Ok(Self::new(tokenizer, session))
+    }
+
+    /// Private method to return an instance
+    fn new(tokenizer: Tokenizer, session: Session) -> Self {
+        let need_token_type_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+        Self {
+            tokenizer,
+            session,
+            need_token_type_ids,
+        }
+    }
+
+    /// Retrieve a list of supported reranker models
+    pub fn list_supported_models() -> Vec<RerankerModelInfo> {
+        reranker_models_list()
+    }
+
+    /// Get RerankerModelInfo from RerankerModel
+    pub fn get_model_info(model: &RerankerModel) -> RerankerModelInfo {
+        TextRerank::list_supported_models()
+            .into_iter()
+            .find(|m| &m.model == model)
+            .expect("Model not found.")
+    }
+
+    /// Method to score the relevance of the documents to the query
+    ///
+    /// The results are sorted by descending score and limited to the `top_n` best ones if set
+    // Generic type to accept String, &str, OsString, &OsStr
+    pub fn rerank<S: AsRef<str> + Send + Sync>(
+        &self,
+        query: &str,
+        documents: Vec<S>,
+        return_documents: bool,
+        top_n: Option<usize>,
+    ) -> Result<Vec<RerankResult>, FastEmbedError> {
+        let scores: Vec<f32> = documents
+            .par_chunks(DEFAULT_BATCH_SIZE)
+            .map(|batch| {
+                // Encode the query and every document of the batch as a single sequence
+                let inputs = batch
+                    .iter()
+                    .map(|document| (query, document.as_ref()))
+                    .collect();
+                let encodings = self
+                    .tokenizer
+                    .encode_batch(inputs, true)
+                    .map_err(tokenization_error)?;
+
+                let encoding_length = encodings[0].len();
+                let batch_size = batch.len();
+
+                let max_size = encoding_length * batch_size;
+
+                let mut ids_array = Vec::with_capacity(max_size);
+                let mut mask_array = Vec::with_capacity(max_size);
+                let mut typeids_array = Vec::with_capacity(max_size);
+
+                encodings.iter().for_each(|encoding| {
+                    ids_array.extend(encoding.get_ids().iter().map(|x| *x as i64));
+                    mask_array.extend(encoding.get_attention_mask().iter().map(|x| *x as i64));
+                    typeids_array.extend(encoding.get_type_ids().iter().map(|x| *x as i64));
+                });
+
+                let inputs_ids_array =
+                    Array::from_shape_vec((batch_size, encoding_length), ids_array)?;
+
+                let attention_mask_array =
+                    Array::from_shape_vec((batch_size, encoding_length), mask_array)?;
+
+                let token_type_ids_array =
+                    Array::from_shape_vec((batch_size, encoding_length), typeids_array)?;
+
+                let mut session_inputs = ort::inputs![
+                    "input_ids" => Value::from_array(inputs_ids_array)?,
+                    "attention_mask" => Value::from_array(attention_mask_array)?,
+                ]?;
+                if self.need_token_type_ids {
+                    session_inputs
+                        .insert("token_type_ids", Value::from_array(token_type_ids_array)?);
+                }
+
+                let outputs = self
+                    .session
+                    .run(session_inputs)
+                    .with_context(inference_error)?;
+
+                // The model outputs a single relevance logit per pair
+                let output_data = outputs
+                    .get("logits")
+                    .context("The model has no \"logits\" output")?
+                    .extract_tensor::<f32>()?;
+                let output_view = output_data.view();
+                let logits = output_view.view().into_dimensionality::<Ix2>()?;
+
+                Ok(logits.slice(s![.., 0]).to_vec())
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let mut results: Vec<RerankResult> = scores
+            .into_iter()
+            .enumerate()
+            .map(|(index, score)| RerankResult {
+                document: return_documents.then(|| documents[index].as_ref().to_string()),
+                score,
+                index,
+            })
+            .collect();
+
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        if let Some(top_n) = top_n {
+            results.truncate(top_n);
+        }
+
+        Ok(results)
+    }
+}